@@ -1,11 +1,16 @@
 //! Config module contains the top-level config for the app.
 
 use config_crate::{Config as RawConfig, ConfigError, Environment, File};
+use std::collections::HashMap;
 use std::env;
 use stq_http;
 use stq_logging;
 
 use sentry_integration::SentryConfig;
+use services::s3::types::{
+    Color, EncryptionOptions, Fit, ImageSize, InputFormat, KeyOptions, MultipartOptions, PngOptimization, Resampling, RetryOptions,
+    UploadOptions, WatermarkOptions,
+};
 
 /// Global app config
 #[derive(Debug, Deserialize, Clone)]
@@ -14,6 +19,15 @@ pub struct Config {
     pub client: Client,
     pub s3: S3,
     pub jwt: JWT,
+    /// Image processing settings
+    #[serde(default)]
+    pub images: Images,
+    /// On-the-fly resizing settings
+    #[serde(default)]
+    pub resize: Resize,
+    /// ACL, storage class and headers of uploaded objects
+    #[serde(default)]
+    pub uploads: Uploads,
     /// GrayLog settings
     pub graylog: Option<stq_logging::GrayLogConfig>,
     pub sentry: Option<SentryConfig>,
@@ -32,13 +46,42 @@ pub struct Server {
     pub acao: String,
 }
 
-/// AWS S3 credentials
+/// AWS S3 settings
 #[derive(Debug, Deserialize, Clone)]
 pub struct S3 {
-    pub key: String,
-    pub secret: String,
+    /// Static credentials, used before other providers of the credentials chain if set
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub secret: Option<String>,
     pub region: String,
     pub bucket: String,
+    /// Server-side encryption of stored objects, bucket default if not set
+    #[serde(default)]
+    pub encryption: Option<EncryptionOptions>,
+    /// Retries of requests that failed with transient errors
+    #[serde(default)]
+    pub retries: RetryOptions,
+    /// Multipart upload of large objects
+    #[serde(default)]
+    pub multipart: MultipartOptions,
+    /// Naming scheme of uploaded objects
+    #[serde(default)]
+    pub keys: KeyOptions,
+}
+
+impl S3 {
+    /// Key and secret from config if both are set
+    pub fn static_credentials(&self) -> Option<(String, String)> {
+        match (&self.key, &self.secret) {
+            (&Some(ref key), &Some(ref secret)) => Some((key.clone(), secret.clone())),
+            _ => None,
+        }
+    }
+}
+
+fn default_admin_role() -> String {
+    "admin".into()
 }
 
 /// JWT data
@@ -46,6 +89,133 @@ pub struct S3 {
 pub struct JWT {
     pub public_key_path: String,
     pub leeway: i64,
+    /// Users with this role in JWT `roles` claim can get signed urls of objects uploaded by other users
+    #[serde(default = "default_admin_role")]
+    pub admin_role: String,
+}
+
+fn default_formats() -> Vec<InputFormat> {
+    InputFormat::all()
+}
+
+/// Image processing settings
+#[derive(Debug, Deserialize, Clone)]
+pub struct Images {
+    /// How resized variants of `POST /images` fit the size presets, e.g. `thumb = "cover"`, see `Fit`.
+    /// Presets that are not listed use `outside` fit.
+    #[serde(default)]
+    pub fit: HashMap<ImageSize, Fit>,
+    /// Background colour for `contain` fit in `#rrggbb` or `#rrggbbaa` format
+    #[serde(default)]
+    pub background: Color,
+    /// Keep unedited image in s3 if uploaded image was cropped, rotated or watermarked
+    #[serde(default)]
+    pub keep_source: bool,
+    /// Watermark overlaid on large variants of uploaded images
+    #[serde(default)]
+    pub watermark: Option<WatermarkOptions>,
+    /// Produce animated variants of animated GIFs, except for thumbs. If not set, all variants
+    /// are static images of the first frame.
+    #[serde(default)]
+    pub animate: bool,
+    /// Accepted formats of uploaded images. Formats that are disabled by cargo features
+    /// are rejected even if listed here.
+    #[serde(default = "default_formats")]
+    pub formats: Vec<InputFormat>,
+    /// Embed sRGB ICC profile in variants of images that had a colour profile. Pixels of such images
+    /// are converted to sRGB regardless of this setting.
+    #[serde(default)]
+    pub embed_profile: bool,
+    /// Pixel densities of extra variants of size presets for high-DPI screens, e.g. `[2, 3]`.
+    /// Variants that would be upscaled are skipped.
+    #[serde(default)]
+    pub densities: Vec<u32>,
+    /// Lossless optimization of PNG variants, both uploaded and resized on the fly
+    #[serde(default)]
+    pub optimize_png: Option<PngOptimization>,
+    /// Resampling filter and sharpening of size presets, e.g. `thumb`. Presets that are not listed
+    /// use `triangle` filter without sharpening.
+    #[serde(default)]
+    pub resampling: HashMap<ImageSize, Resampling>,
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Self {
+            fit: HashMap::new(),
+            background: Color::default(),
+            keep_source: false,
+            watermark: None,
+            animate: false,
+            formats: default_formats(),
+            embed_profile: false,
+            densities: Vec::new(),
+            optimize_png: None,
+            resampling: HashMap::new(),
+        }
+    }
+}
+
+/// On-the-fly resizing settings
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Resize {
+    /// Allowed boxes for resizing in `WIDTHxHEIGHT` format, e.g. `200x100`.
+    /// Omitted dimension is specified as `0`, e.g. `200x0` allows `?w=200` only.
+    #[serde(default)]
+    pub allowed_sizes: Vec<String>,
+    /// Resampling filter and sharpening of resized images
+    #[serde(default)]
+    pub resampling: Resampling,
+}
+
+fn default_signed_url_expires_in() -> u64 {
+    3600
+}
+
+/// ACL, storage class and headers of objects uploaded to s3. Settings of routes override `global` ones,
+/// settings of size presets override the ones of routes, field by field.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Uploads {
+    #[serde(default)]
+    pub global: UploadOptions,
+    /// Images uploaded with `POST /images`
+    #[serde(default)]
+    pub images: UploadOptions,
+    /// Images uploaded with `POST /avatars`
+    #[serde(default)]
+    pub avatars: UploadOptions,
+    /// Images resized on the fly with `GET /images/<key>`
+    #[serde(default)]
+    pub resized: UploadOptions,
+    /// Variants of size presets of uploaded images and avatars, e.g. `thumb`
+    #[serde(default)]
+    pub presets: HashMap<ImageSize, UploadOptions>,
+    /// Lifetime of signed urls in seconds if `expires_in` param is not set
+    #[serde(default = "default_signed_url_expires_in")]
+    pub signed_url_expires_in: u64,
+}
+
+impl Uploads {
+    /// Checks if objects of any route or size preset are uploaded with a public ACL
+    pub fn has_public_acl(&self) -> bool {
+        [&self.images, &self.avatars, &self.resized].iter().any(|route| {
+            let route = self.global.merge(route);
+            route.is_public() || self.presets.values().any(|preset| route.merge(preset).is_public())
+        })
+    }
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        Self {
+            global: UploadOptions::default(),
+            images: UploadOptions::default(),
+            avatars: UploadOptions::default(),
+            resized: UploadOptions::default(),
+            presets: HashMap::new(),
+            signed_url_expires_in: default_signed_url_expires_in(),
+        }
+    }
 }
 
 /// Http client settings
@@ -76,7 +246,23 @@ impl Config {
         // Add in settings from the environment (with a prefix of STQ_STATICS)
         s.merge(Environment::with_prefix("STQ_STATICS"))?;
 
-        s.try_into()
+        let config: Self = s.try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks settings that can't be checked by their types
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.s3.keys.validate().map_err(ConfigError::Message)?;
+        match self.s3.encryption {
+            // s3 doesn't decrypt such objects for anonymous requests
+            Some(EncryptionOptions::Kms { .. }) | Some(EncryptionOptions::Customer { .. }) if self.uploads.has_public_acl() => {
+                Err(ConfigError::Message(
+                    "SSE-KMS and SSE-C objects can't be read by public urls, set `acl = \"private\"` in `uploads.global`".into(),
+                ))
+            }
+            _ => Ok(()),
+        }
     }
 
     pub fn to_http_config(&self) -> stq_http::client::Config {
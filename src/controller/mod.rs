@@ -7,8 +7,10 @@ pub mod multipart_utils;
 pub mod routes;
 pub mod utils;
 
+use std::collections::HashMap;
 use std::io::Read;
 use std::sync::Arc;
+use std::time::Duration;
 
 use failure;
 use failure::Fail;
@@ -18,8 +20,7 @@ use hyper;
 use hyper::header::{Authorization, Bearer};
 use hyper::server::Request;
 use hyper::Headers;
-use hyper::Post;
-use image;
+use hyper::{Get, Post};
 use jsonwebtoken::{decode, Algorithm, Validation};
 use multipart::server::Multipart;
 
@@ -33,12 +34,26 @@ use self::routes::Route;
 use config::Config;
 use errors::*;
 use sentry_integration::log_and_capture_error;
+use services::s3::error::S3Error;
+use services::s3::types::{
+    Color, Crop, Fit, FocalPoint, InputFormat, OutputFormat, ProcessOptions, Requester, Resampling, ResizeParams, Rotation, UploadOptions,
+    UploadedImage,
+};
 use services::s3::S3;
 
+/// Names of multipart fields and query params with image edits
+static EDIT_PARAMS: &[&str] = &["crop", "rotate", "focal"];
+
+/// Max lifetime of presigned urls in seconds, limited by AWS signature v4
+static MAX_SIGNED_URL_EXPIRES_IN: u64 = 7 * 24 * 60 * 60;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct JWTPayload {
     pub user_id: i32,
     pub exp: i64,
+    /// Roles of the user, e.g. `admin`
+    #[serde(default)]
+    pub roles: Vec<String>,
 }
 
 pub fn verify_token(jwt_key: Vec<u8>, leeway: i64, headers: &Headers) -> Box<Future<Item = JWTPayload, Error = failure::Error>> {
@@ -63,6 +78,19 @@ pub fn verify_token(jwt_key: Vec<u8>, leeway: i64, headers: &Headers) -> Box<Fut
     )
 }
 
+/// Verifies JWT token if the request has one, requests without `Authorization` header are anonymous
+pub fn verify_optional_token(
+    jwt_key: Vec<u8>,
+    leeway: i64,
+    headers: &Headers,
+) -> Box<Future<Item = Option<JWTPayload>, Error = failure::Error>> {
+    if headers.has::<Authorization<Bearer>>() {
+        Box::new(verify_token(jwt_key, leeway, headers).map(Some))
+    } else {
+        Box::new(future::ok(None))
+    }
+}
+
 /// Controller handles route parsing and calling `Service` layer
 pub struct ControllerImpl {
     pub config: Config,
@@ -86,75 +114,214 @@ impl ControllerImpl {
     }
 }
 
-impl Controller for ControllerImpl {
-    /// Handle a request and get future response
-    fn call(&self, req: Request) -> ControllerFuture {
-        let s3 = self.s3.clone();
+impl ControllerImpl {
+    /// Options of processing uploaded images from config, `upload` overrides global upload settings for the route
+    fn process_options(&self, upload: &UploadOptions) -> ProcessOptions {
+        ProcessOptions {
+            fit: self.config.images.fit.clone(),
+            background: self.config.images.background,
+            keep_source: self.config.images.keep_source,
+            animate: self.config.images.animate,
+            embed_profile: self.config.images.embed_profile,
+            densities: self.config.images.densities.clone(),
+            optimize_png: self.config.images.optimize_png.clone(),
+            resampling: self.config.images.resampling.clone(),
+            upload: self.config.uploads.global.merge(upload),
+            preset_uploads: self.config.uploads.presets.clone(),
+            ..Default::default()
+        }
+    }
 
-        let fut = match (req.method(), self.route_parser.test(req.path())) {
-            // POST /images
-            (&Post, Some(Route::Images)) => serialize_future({
-                let method = req.method().clone();
-                let headers = req.headers().clone();
+    /// User of JWT token of the request, `None` if the request has no token
+    fn requester(&self, headers: &Headers) -> Box<Future<Item = Option<Requester>, Error = failure::Error>> {
+        let admin_role = self.config.jwt.admin_role.clone();
+        Box::new(
+            verify_optional_token(self.jwt_public_key.clone(), self.config.jwt.leeway, headers).map(move |payload| {
+                payload.map(|payload| Requester {
+                    user_id: payload.user_id,
+                    admin: payload.roles.contains(&admin_role),
+                })
+            }),
+        )
+    }
 
-                info!("Received image upload request");
+    /// Formats of uploaded images that are both enabled in config and compiled in
+    fn accepted_formats(&self) -> Vec<InputFormat> {
+        self.config
+            .images
+            .formats
+            .iter()
+            .cloned()
+            .filter(InputFormat::is_compiled)
+            .collect()
+    }
 
-                future::ok(())
-                    .and_then({
-                        let headers = headers.clone();
-                        let leeway = self.config.jwt.leeway;
-                        let jwt_key = self.jwt_public_key.clone();
-                        move |_| verify_token(jwt_key, leeway, &headers)
-                    })
-                    .and_then(|_user_id| {
-                        read_bytes(req.body()).map_err(|e| e.context("Failed to read request body").context(Error::Network).into())
-                    })
-                    .and_then(move |bytes| {
-                        info!("Read payload bytes");
-                        let multipart_wrapper = multipart_utils::MultipartRequest::new(method, headers, bytes);
-                        Multipart::from_request(multipart_wrapper).map_err(|_| {
+    /// Reads multipart request with images, verifies JWT token and uploads every image with `upload`.
+    /// Crop, rotation and focal point of images are read from `crop`, `rotate` and `focal` multipart
+    /// fields or query params, see `parse_edit_params`. Objects are uploaded with `upload_options` of the route,
+    /// or without public access if `private=true` query param is set. Id of the JWT user is stored as owner of the objects.
+    fn upload_images<F>(&self, req: Request, upload_options: &UploadOptions, upload: F) -> ControllerFuture
+    where
+        F: Fn(InputFormat, Vec<u8>, ProcessOptions) -> Box<Future<Item = UploadedImage, Error = S3Error>> + 'static,
+    {
+        serialize_future({
+            let method = req.method().clone();
+            let headers = req.headers().clone();
+            let query = req.query().unwrap_or("").to_string();
+            let private = utils::query_params(&query).get("private") == Some(&"true");
+            let options = self.process_options(upload_options);
+            let accepted_formats = self.accepted_formats();
+
+            future::ok(())
+                .and_then({
+                    let headers = headers.clone();
+                    let leeway = self.config.jwt.leeway;
+                    let jwt_key = self.jwt_public_key.clone();
+                    move |_| verify_token(jwt_key, leeway, &headers)
+                })
+                .and_then(|payload| {
+                    read_bytes(req.body())
+                        .map(move |bytes| (payload.user_id, bytes))
+                        .map_err(|e| e.context("Failed to read request body").context(Error::Network).into())
+                })
+                .and_then(move |(user_id, bytes)| {
+                    info!("Read payload bytes");
+                    let multipart_wrapper = multipart_utils::MultipartRequest::new(method, headers, bytes);
+                    Multipart::from_request(multipart_wrapper)
+                        .map(|multipart_entity| (user_id, multipart_entity))
+                        .map_err(|_| {
                             format_err!("Couldn't convert request body to multipart")
                                 .context(Error::Parse)
                                 .into()
                         })
-                    })
-                    .and_then(|mut multipart_entity| {
-                        let mut files: Vec<Vec<u8>> = Vec::new();
-                        multipart_entity
-                            .foreach_entry(|mut field| {
+                })
+                .and_then(|(user_id, mut multipart_entity)| {
+                    let mut files: Vec<(Option<String>, Vec<u8>)> = Vec::new();
+                    let mut params: HashMap<String, String> = HashMap::new();
+                    multipart_entity
+                        .foreach_entry(|mut field| {
+                            if EDIT_PARAMS.contains(&field.headers.name.as_str()) {
+                                let mut value = String::new();
+                                let _ = field.data.read_to_string(&mut value);
+                                params.insert(field.headers.name.to_string(), value);
+                            } else {
                                 let mut file_data: Vec<u8> = Vec::new();
                                 let _ = field.data.read_to_end(&mut file_data);
-                                files.push(file_data);
-                            })
-                            .map_err(|e| format_err!("Parsed multipart, could not iterate over entries: {}", e).context(Error::Parse))?;
-                        Ok(files)
-                    })
-                    .map(futures::stream::iter_ok)
-                    .flatten_stream()
-                    .and_then(|file| {
-                        image::guess_format(&file)
-                            .map_err(|e| e.context("Invalid image format").context(Error::Image).into())
-                            .map(|format| (format, file))
-                            .into_future()
+                                files.push((field.headers.filename.clone(), file_data));
+                            }
+                        })
+                        .map_err(|e| format_err!("Parsed multipart, could not iterate over entries: {}", e).context(Error::Parse))?;
+                    Ok((user_id, files, params))
+                })
+                .and_then(move |(user_id, files, params)| {
+                    let (crop, rotation, focal_point) = parse_edit_params(&query, &params)?;
+                    let options = ProcessOptions {
+                        crop,
+                        rotation,
+                        focal_point,
+                        owner: Some(user_id),
+                        private,
+                        ..options
+                    };
+                    Ok(files.into_iter().map(move |(filename, file)| {
+                        let options = ProcessOptions {
+                            filename,
+                            ..options.clone()
+                        };
+                        (file, options)
+                    }))
+                })
+                .map(futures::stream::iter_ok)
+                .flatten_stream()
+                .and_then(move |(file, options)| {
+                    guess_format(&file, &accepted_formats)
+                        .map(|format| (format, file, options))
+                        .into_future()
+                })
+                .and_then(move |(format, data, options)| upload(format, data, options).map_err(s3_error))
+                .collect()
+                .and_then(|uploaded_images| {
+                    let result = if uploaded_images.len() == 1 {
+                        serde_json::to_value(&uploaded_images[0])
+                    } else {
+                        serde_json::to_value(&uploaded_images)
+                    };
+                    result.map_err(|e| {
+                        format_err!("Uploaded images, could not serialize result: {}", e)
+                            .context(Error::Parse)
+                            .into()
                     })
-                    .and_then(move |(format, data)| {
-                        Box::new(
-                            s3.upload_image(format, data)
-                                .map(|name| json!({ "url": name }))
-                                .map_err(|e| e.context(Error::Image).into()),
-                        )
+                })
+        })
+    }
+}
+
+impl Controller for ControllerImpl {
+    /// Handle a request and get future response
+    fn call(&self, req: Request) -> ControllerFuture {
+        let s3 = self.s3.clone();
+
+        let fut = match (req.method(), self.route_parser.test(req.path())) {
+            // POST /images
+            (&Post, Some(Route::Images)) => {
+                info!("Received image upload request");
+                self.upload_images(req, &self.config.uploads.images, move |format, data, options| {
+                    s3.upload_image(format, data, options)
+                })
+            }
+
+            // POST /avatars
+            (&Post, Some(Route::Avatars)) => {
+                info!("Received avatar upload request");
+                self.upload_images(req, &self.config.uploads.avatars, move |format, data, options| {
+                    s3.upload_avatar(format, data, options)
+                })
+            }
+
+            // GET /images/<key>
+            (&Get, Some(Route::Image { key })) => serialize_future({
+                let query = req.query().unwrap_or("").to_string();
+
+                info!("Received image resize request for {}", key);
+
+                let background = self.config.images.background;
+                let embed_profile = self.config.images.embed_profile;
+                let optimize_png = self.config.images.optimize_png.clone();
+                let resampling = self.config.resize.resampling.clone();
+                let upload = self.config.uploads.global.merge(&self.config.uploads.resized);
+                future::result(parse_resize_params(&query, &self.config.resize.allowed_sizes, background))
+                    .map(move |params| ResizeParams {
+                        embed_profile,
+                        optimize_png,
+                        resampling,
+                        ..params
                     })
-                    .collect()
-                    .and_then(|uploaded_images| {
-                        if uploaded_images.len() == 1 {
-                            uploaded_images.into_iter().next().ok_or(format_err!("No images were sent"))
-                        } else {
-                            serde_json::to_value(&uploaded_images).map_err(|e| {
-                                format_err!("Uploaded images, could not serialize result: {}", e)
-                                    .context(Error::Parse)
-                                    .into()
-                            })
-                        }
+                    .join(self.requester(req.headers()))
+                    .and_then(move |(params, requester)| s3.resize_image(&key, params, upload, requester).map_err(s3_error))
+                    .map(|url| json!({ "url": url }))
+            }),
+
+            // GET /images/<key>/info
+            (&Get, Some(Route::ImageInfo { key })) => serialize_future({
+                info!("Received image info request for {}", key);
+
+                self.requester(req.headers())
+                    .and_then(move |requester| s3.image_info(&key, requester).map_err(s3_error))
+            }),
+
+            // GET /images/<key>/signed
+            (&Get, Some(Route::SignedUrl { key })) => serialize_future({
+                info!("Received signed url request for {}", key);
+
+                let headers = req.headers().clone();
+                let query = req.query().unwrap_or("").to_string();
+                let admin_role = self.config.jwt.admin_role.clone();
+                let default_expires_in = self.config.uploads.signed_url_expires_in;
+                verify_token(self.jwt_public_key.clone(), self.config.jwt.leeway, &headers)
+                    .and_then(move |payload| parse_expires_in(&query, default_expires_in).map(|expires_in| (payload, expires_in)))
+                    .and_then(move |(payload, expires_in)| {
+                        let admin = payload.roles.contains(&admin_role);
+                        s3.signed_url(&key, payload.user_id, admin, expires_in).map_err(s3_error)
                     })
             }),
 
@@ -180,3 +347,187 @@ pub fn read_bytes(body: hyper::Body) -> Box<Future<Item = Vec<u8>, Error = hyper
         future::ok::<_, hyper::Error>(acc)
     }))
 }
+
+/// Parses `w`, `h`, `fit` and `format` query params of image resize request.
+/// Requested box must be one of `allowed_sizes`, see `config::Resize`.
+pub fn parse_resize_params(query: &str, allowed_sizes: &[String], background: Color) -> Result<ResizeParams, failure::Error> {
+    let params = utils::query_params(query);
+    let parse_dimension = |name: &str| -> Result<Option<u32>, failure::Error> {
+        match params.get(name) {
+            None | Some(&"") => Ok(None),
+            Some(value) => match value.parse::<u32>() {
+                Ok(0) | Err(_) => Err(format_err!("Invalid `{}` param: {}", name, value).context(Error::Parse).into()),
+                Ok(dimension) => Ok(Some(dimension)),
+            },
+        }
+    };
+    let width = parse_dimension("w")?;
+    let height = parse_dimension("h")?;
+    if width.is_none() && height.is_none() {
+        return Err(format_err!("At least one of `w` and `h` params is required")
+            .context(Error::Parse)
+            .into());
+    }
+
+    let size = format!("{}x{}", width.unwrap_or(0), height.unwrap_or(0));
+    if !allowed_sizes.contains(&size) {
+        return Err(format_err!("Size {} is not allowed", size).context(Error::Forbidden).into());
+    }
+
+    let fit = match params.get("fit") {
+        Some(fit) if !fit.is_empty() => fit.parse::<Fit>().map_err(|e| format_err!("{}", e).context(Error::Parse))?,
+        _ => Fit::Inside,
+    };
+    let format = match params.get("format") {
+        Some(format) if !format.is_empty() => format
+            .parse::<OutputFormat>()
+            .map_err(|e| format_err!("{}", e).context(Error::Parse))?,
+        _ => OutputFormat::Png,
+    };
+
+    Ok(ResizeParams {
+        width,
+        height,
+        fit,
+        background,
+        focal_point: None,
+        format,
+        embed_profile: false,
+        optimize_png: None,
+        resampling: Resampling::default(),
+    })
+}
+
+/// Parses `expires_in` query param of signed url request in seconds, `default` if it's not set.
+/// Presigned urls can't live longer than a week, see `MAX_SIGNED_URL_EXPIRES_IN`.
+pub fn parse_expires_in(query: &str, default: u64) -> Result<Duration, failure::Error> {
+    let expires_in = match utils::query_params(query).get("expires_in") {
+        None | Some(&"") => default,
+        Some(value) => match value.parse::<u64>() {
+            Ok(0) | Err(_) => return Err(format_err!("Invalid `expires_in` param: {}", value).context(Error::Parse).into()),
+            Ok(expires_in) => expires_in,
+        },
+    };
+    if expires_in > MAX_SIGNED_URL_EXPIRES_IN {
+        return Err(
+            format_err!("`expires_in` can't be longer than {} seconds", MAX_SIGNED_URL_EXPIRES_IN)
+                .context(Error::Parse)
+                .into(),
+        );
+    }
+    Ok(Duration::from_secs(expires_in))
+}
+
+/// Parses `crop` (`x,y,w,h` in pixels or percents, e.g. `10,10,50%,50%`), `rotate` (`90`, `180` or `270`)
+/// and `focal` (`x,y` in pixels or percents of edited image, e.g. `30%,60%`) params of uploaded image.
+/// Multipart fields take precedence over query params.
+pub fn parse_edit_params(
+    query: &str,
+    fields: &HashMap<String, String>,
+) -> Result<(Option<Crop>, Option<Rotation>, Option<FocalPoint>), failure::Error> {
+    let query_params = utils::query_params(query);
+    let param = |name: &str| {
+        fields
+            .get(name)
+            .map(|value| value.trim())
+            .or_else(|| query_params.get(name).cloned())
+            .filter(|value| !value.is_empty())
+    };
+
+    let crop = match param("crop") {
+        Some(crop) => Some(crop.parse::<Crop>().map_err(|e| format_err!("{}", e).context(Error::Parse))?),
+        None => None,
+    };
+    let rotation = match param("rotate") {
+        Some("0") | None => None,
+        Some(rotation) => Some(
+            rotation
+                .parse::<Rotation>()
+                .map_err(|e| format_err!("{}", e).context(Error::Parse))?,
+        ),
+    };
+
+    let focal_point = match param("focal") {
+        Some(focal_point) => Some(
+            focal_point
+                .parse::<FocalPoint>()
+                .map_err(|e| format_err!("{}", e).context(Error::Parse))?,
+        ),
+        None => None,
+    };
+
+    Ok((crop, rotation, focal_point))
+}
+
+/// Guesses format of uploaded image and checks that it is one of `accepted` formats
+///
+/// #Errors
+/// * `Error::Image` if format is unknown
+/// * `Error::UnsupportedFormat` with the list of accepted formats if format is not accepted
+pub fn guess_format(bytes: &[u8], accepted: &[InputFormat]) -> Result<InputFormat, failure::Error> {
+    let format = InputFormat::guess(bytes).ok_or_else(|| format_err!("Invalid image format").context(Error::Image))?;
+    if accepted.contains(&format) {
+        return Ok(format);
+    }
+    let accepted = accepted.iter().map(|format| format.to_string()).collect();
+    Err(format_err!("Image format {} is not accepted", format)
+        .context(Error::UnsupportedFormat(accepted))
+        .into())
+}
+
+/// Adds http-related `Error` context to s3 service error. Access and unknown errors are left
+/// without context, so that they are reported as internal errors.
+fn s3_error(e: S3Error) -> failure::Error {
+    match error_kind(&e) {
+        Some(kind) => e.context(kind).into(),
+        None => e.into(),
+    }
+}
+
+/// Http-related kind of s3 service error, rolled back uploads get the kind of the error that caused the rollback
+fn error_kind(e: &S3Error) -> Option<Error> {
+    match e {
+        &S3Error::NotFound(_) => Some(Error::NotFound),
+        &S3Error::Forbidden(_) => Some(Error::Forbidden),
+        &S3Error::Image(_) => Some(Error::Image),
+        &S3Error::Network(_) => Some(Error::Network),
+        &S3Error::Access(_) | &S3Error::Unknown(_) => None,
+        &S3Error::RolledBack {
+            ref cause,
+            ref deleted,
+            ref not_deleted,
+        } => Some(Error::RolledBack {
+            cause: error_kind(cause).map(Box::new),
+            deleted: deleted.clone(),
+            not_deleted: not_deleted.clone(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_s3_error_rolled_back() {
+        let err = s3_error(S3Error::RolledBack {
+            cause: Box::new(S3Error::Network("Connection reset".to_string())),
+            deleted: vec!["img-a.png".to_string()],
+            not_deleted: vec!["img-a-small.png".to_string()],
+        });
+        let wrapper = ErrorMessageWrapper::<Error>::from(&err);
+        assert_eq!(wrapper.inner.code, 400);
+        assert_eq!(
+            wrapper.inner.payload,
+            Some(json!({ "deleted": ["img-a.png"], "not_deleted": ["img-a-small.png"] }))
+        );
+    }
+
+    #[test]
+    fn test_s3_error_internal() {
+        let err = s3_error(S3Error::Access("Invalid credentials".to_string()));
+        let wrapper = ErrorMessageWrapper::<Error>::from(&err);
+        assert_eq!(wrapper.inner.code, 500);
+        assert_eq!(wrapper.inner.payload, None);
+    }
+}
@@ -2,6 +2,10 @@
 //! Currently it's
 //! - `GET /healthcheck` - returns `ok` if the server is live
 //! - `POST /images` - accepts multipart HTTP requests with png / jpeg images
+//! - `GET /images/<key>` - resizes uploaded image on the fly
+//! - `GET /images/<key>/info` - returns dimensions, format, size, variants and placeholders of uploaded image
+//! - `GET /images/<key>/signed` - returns expiring presigned url of uploaded image, e.g. a private one
+//! - `POST /avatars` - accepts multipart HTTP requests with png / jpeg images, resized variants are square
 use stq_router::RouteParser;
 
 /// List of all routes with params for the app
@@ -9,6 +13,10 @@ use stq_router::RouteParser;
 pub enum Route {
     Healthcheck,
     Images,
+    Image { key: String },
+    ImageInfo { key: String },
+    SignedUrl { key: String },
+    Avatars,
 }
 
 /// Creates global app route parser
@@ -21,5 +29,25 @@ pub fn create_route_parser() -> RouteParser<Route> {
     // Images upload route
     router.add_route(r"^/images$", || Route::Images);
 
+    // Avatars upload route
+    router.add_route(r"^/avatars$", || Route::Avatars);
+
+    // Keys can contain slashes if key template has them, so routes with suffixes go before the resize route
+
+    // Image info route
+    router.add_route_with_params(r"^/images/(.+)/info$", |params| {
+        params.get(0).map(|key| Route::ImageInfo { key: key.to_string() })
+    });
+
+    // Presigned url route
+    router.add_route_with_params(r"^/images/(.+)/signed$", |params| {
+        params.get(0).map(|key| Route::SignedUrl { key: key.to_string() })
+    });
+
+    // Image resize route
+    router.add_route_with_params(r"^/images/(.+)$", |params| {
+        params.get(0).map(|key| Route::Image { key: key.to_string() })
+    });
+
     router
 }
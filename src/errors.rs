@@ -14,8 +14,21 @@ pub enum Error {
     Parse,
     #[fail(display = "Unauthorized")]
     Unauthorized,
+    #[fail(display = "Forbidden")]
+    Forbidden,
     #[fail(display = "Network error")]
     Network,
+    /// Image format is not accepted, with a list of accepted formats
+    #[fail(display = "Unsupported image format, accepted formats: {:?}", _0)]
+    UnsupportedFormat(Vec<String>),
+    /// Upload failed and its uploaded objects were deleted. Status is the one of `cause`,
+    /// internal error if `cause` has no http-related kind.
+    #[fail(display = "Upload failed and was rolled back")]
+    RolledBack {
+        cause: Option<Box<Error>>,
+        deleted: Vec<String>,
+        not_deleted: Vec<String>,
+    },
 }
 
 impl Codeable for Error {
@@ -27,12 +40,28 @@ impl Codeable for Error {
             Image => StatusCode::UnprocessableEntity,
             Parse => StatusCode::UnprocessableEntity,
             Unauthorized | Network => StatusCode::BadRequest,
+            Forbidden => StatusCode::Forbidden,
+            UnsupportedFormat(_) => StatusCode::UnsupportedMediaType,
+            RolledBack { cause, .. } => cause.as_ref().map(|cause| cause.code()).unwrap_or(StatusCode::InternalServerError),
         }
     }
 }
 
 impl PayloadCarrier for Error {
     fn payload(&self) -> Option<serde_json::Value> {
-        None
+        match self {
+            Error::UnsupportedFormat(formats) => Some(json!({ "accepted_formats": formats })),
+            Error::RolledBack {
+                cause,
+                deleted,
+                not_deleted,
+            } => {
+                let mut payload = cause.as_ref().and_then(|cause| cause.payload()).unwrap_or_else(|| json!({}));
+                payload["deleted"] = json!(deleted);
+                payload["not_deleted"] = json!(not_deleted);
+                Some(payload)
+            }
+            _ => None,
+        }
     }
 }
@@ -10,7 +10,10 @@
 //! Returns `{"url": <url of uploaded image>}`. You can also use prefix with this url
 //! to get different sizes: thumb - 40 pixels, small - 80 pixels, medium - 320 pixels,
 //! large - 640 pixels. Example: `https://s3.amazonaws.com/storiqa-dev/img-2IpSsAjuxB8C.png` is original image,
-//! `https://s3.amazonaws.com/storiqa-dev/img-2IpSsAjuxB8C-large.png` is large image.
+//! `https://s3.amazonaws.com/storiqa-dev/img-2IpSsAjuxB8C-large.png` is large image. Jpeg originals are stored
+//! as jpeg, but keep the `png` extension in their url.
+//!
+//! See `README.md` for the other routes and their config.
 
 extern crate base64;
 extern crate chrono;
@@ -18,29 +21,39 @@ extern crate config as config_crate;
 extern crate env_logger;
 #[macro_use]
 extern crate failure;
+extern crate flate2;
 extern crate futures;
 extern crate futures_cpupool;
+extern crate futures_timer;
+extern crate gif;
+extern crate hmac;
 extern crate hyper;
 extern crate hyper_tls;
 extern crate image;
 extern crate jsonwebtoken;
 #[macro_use]
 extern crate log as log_crate;
+extern crate md5;
 extern crate mime;
 extern crate multipart;
+extern crate quick_xml;
 extern crate rand;
 extern crate rusoto_core;
 extern crate rusoto_s3;
+extern crate rusttype;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
+extern crate sha2;
 extern crate stq_http;
 extern crate stq_logging;
 extern crate stq_router;
 extern crate tokio_core;
 extern crate tokio_signal;
+#[cfg(feature = "svg")]
+extern crate usvg;
 #[macro_use]
 extern crate sentry;
 
@@ -63,6 +76,8 @@ use stq_http::controller::Application;
 use tokio_core::reactor::Core;
 
 pub use config::Config;
+use services::s3::encryption::Encryption;
+use services::s3::preprocessors::watermark::Watermark;
 use services::s3::S3;
 
 /// Starts new web service from provided `Config`
@@ -87,7 +102,32 @@ pub fn start_server<F: FnOnce() + 'static>(config: Config, port: Option<u16>, ca
 
     let region = config.s3.region.parse::<Region>().expect("Invalid region specified");
 
-    let s3 = Arc::new(S3::create(&config.s3.key, &config.s3.secret, region.clone(), &config.s3.bucket, &handle).unwrap());
+    let watermark = config
+        .images
+        .watermark
+        .clone()
+        .map(|options| Watermark::load(options).expect("Failed to load watermark"));
+
+    let encryption = config
+        .s3
+        .encryption
+        .as_ref()
+        .map(|options| Encryption::load(options).expect("Failed to load encryption key"));
+
+    let s3 = Arc::new(
+        S3::create(
+            config.s3.static_credentials(),
+            region.clone(),
+            &config.s3.bucket,
+            encryption,
+            config.s3.retries.clone(),
+            config.s3.multipart.clone(),
+            config.s3.keys.clone(),
+            watermark,
+            &handle,
+        )
+        .unwrap(),
+    );
 
     let address = {
         let port = port.as_ref().unwrap_or(&config.server.port);
@@ -11,6 +11,8 @@
 //! to get different sizes: thumb - 40 pixels, small - 80 pixels, medium - 320 pixels,
 //! large - 640 pixels. Example: `https://s3.amazonaws.com/storiqa-dev/img-2IpSsAjuxB8C.png` is original image,
 //! `https://s3.amazonaws.com/storiqa-dev/img-2IpSsAjuxB8C-large.png` is large image.
+//!
+//! See `README.md` for the other routes and their config.
 
 extern crate statics_lib as lib;
 extern crate stq_logging;
@@ -0,0 +1,40 @@
+//! Checksums of uploaded objects: MD5 for `Content-MD5` header, which lets s3 reject
+//! objects corrupted in transit, and SHA-256 stored in metadata for clients to verify downloads
+
+use base64;
+use md5;
+use sha2::{Digest, Sha256};
+
+use super::types::Checksums;
+
+/// Base64 encoded MD5 digest of `bytes`, the value of `Content-MD5` header
+pub fn content_md5(bytes: &[u8]) -> String {
+    base64::encode(&md5::compute(bytes).0)
+}
+
+/// Hex encoded SHA-256 digest of `bytes`
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Checksums {
+    pub fn compute(bytes: &[u8]) -> Self {
+        Self {
+            md5: content_md5(bytes),
+            sha256: sha256(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        let checksums = Checksums::compute(b"hello");
+        assert_eq!(checksums.md5, "XUFAKrxLKna5cZ2REBfFkg==");
+        assert_eq!(checksums.sha256, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert_eq!(sha256(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+}
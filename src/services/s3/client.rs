@@ -1,48 +1,464 @@
 //! Client for AWS S3
 
+use chrono::Utc;
+use futures::future;
 use futures::prelude::*;
+use futures::stream;
+use rusoto_core::region::Region;
 use rusoto_core::request::HttpClient;
-use rusoto_s3::{PutObjectRequest, S3Client as CrateS3Client, S3};
+use rusoto_core::ProvideAwsCredentials;
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload, CompletedPart, CreateMultipartUploadRequest,
+    DeleteObjectRequest, GetObjectRequest, HeadObjectRequest, PutObjectRequest, S3Client as CrateS3Client, UploadPartRequest, S3,
+};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Duration;
 
+use super::checksum::content_md5;
 use super::credentials::Credentials;
+use super::encryption::Encryption;
 use super::error::S3Error;
+use super::presign;
+use super::retry::retry;
+use super::tags;
+use super::types::{MultipartOptions, RetryOptions, UploadOptions};
+
+/// Canned ACL of uploaded objects if `UploadOptions::acl` is not set
+static DEFAULT_ACL: &str = "public-read";
+/// Min size of all parts of multipart upload except for the last one
+static MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+/// Max number of parts of multipart upload
+static MAX_PARTS: usize = 10_000;
+
+/// Object downloaded from s3
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct S3Object {
+    pub bytes: Vec<u8>,
+    /// User-defined metadata, without `x-amz-meta-` prefix
+    pub metadata: HashMap<String, String>,
+}
+
+/// Size and metadata of an object in s3, read without downloading it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct S3ObjectInfo {
+    /// Size in bytes
+    pub size: Option<u64>,
+    pub content_type: Option<String>,
+    /// Time of upload in RFC 2822 format, e.g. `Tue, 15 Nov 1994 12:45:26 GMT`
+    pub last_modified: Option<String>,
+    /// User-defined metadata, without `x-amz-meta-` prefix
+    pub metadata: HashMap<String, String>,
+}
 
 pub trait S3Client {
-    /// Uploads raw bytes to s3 with filename `key` and content-type (used for serving file from s3)
-    fn upload(&self, bucket: String, key: String, content_type: Option<String>, bytes: Vec<u8>) -> Box<Future<Item = (), Error = S3Error>>;
+    /// Uploads raw bytes to s3 with filename `key`, content-type (used for serving file from s3),
+    /// user-defined metadata (stored as `x-amz-meta-*` headers), tags and ACL, storage class and headers of `options`.
+    /// `bytes` are shared, so that parts of multipart uploads are sliced without copying the whole object.
+    /// `content_md5` is base64 encoded MD5 of `bytes`, it is sent in `Content-MD5` header of single part uploads.
+    fn upload(
+        &self,
+        bucket: String,
+        key: String,
+        content_type: Option<String>,
+        metadata: HashMap<String, String>,
+        tags: HashMap<String, String>,
+        bytes: Rc<Vec<u8>>,
+        content_md5: String,
+        options: UploadOptions,
+    ) -> Box<Future<Item = (), Error = S3Error>>;
+
+    /// Checks if object with filename `key` exists in s3
+    fn exists(&self, bucket: String, key: String) -> Box<Future<Item = bool, Error = S3Error>>;
+
+    /// Downloads raw bytes and user-defined metadata of object with filename `key` from s3
+    ///
+    /// #Errors
+    /// * `S3Error::NotFound` if there's no such object
+    fn download(&self, bucket: String, key: String) -> Box<Future<Item = S3Object, Error = S3Error>>;
+
+    /// Reads size, content-type and user-defined metadata of object with filename `key` from s3
+    ///
+    /// #Errors
+    /// * `S3Error::NotFound` if there's no such object
+    fn head(&self, bucket: String, key: String) -> Box<Future<Item = S3ObjectInfo, Error = S3Error>>;
+
+    /// Creates a url that allows to GET object with filename `key` without credentials until it expires,
+    /// regardless of the object's ACL. Doesn't check that the object exists.
+    fn presigned_url(&self, bucket: String, key: String, expires_in: Duration) -> Box<Future<Item = String, Error = S3Error>>;
+
+    /// Deletes object with filename `key` from s3, deleting a missing object is not an error
+    fn delete(&self, bucket: String, key: String) -> Box<Future<Item = (), Error = S3Error>>;
+}
+
+/// Rusoto s3 client along with its credentials, which are needed to presign urls,
+/// server-side encryption of objects and settings of multipart uploads
+pub struct S3ClientImpl {
+    client: Rc<CrateS3Client<Credentials, HttpClient>>,
+    credentials: Credentials,
+    region: Region,
+    encryption: Option<Encryption>,
+    multipart: MultipartOptions,
+    /// Retries of upload requests, every part of multipart uploads is retried on its own
+    retries: RetryOptions,
 }
 
-impl S3Client for CrateS3Client<Credentials, HttpClient> {
-    fn upload(&self, bucket: String, key: String, content_type: Option<String>, bytes: Vec<u8>) -> Box<Future<Item = (), Error = S3Error>> {
+impl S3ClientImpl {
+    pub fn new(
+        client: HttpClient,
+        credentials: Credentials,
+        region: Region,
+        encryption: Option<Encryption>,
+        multipart: MultipartOptions,
+        retries: RetryOptions,
+    ) -> Self {
+        Self {
+            client: Rc::new(CrateS3Client::new(client, credentials.clone(), region.clone())),
+            credentials,
+            region,
+            encryption,
+            multipart,
+            retries,
+        }
+    }
+
+    /// Uploads `bytes` in parts, `multipart.concurrency` parts at a time. Every request is retried on its own,
+    /// so that a transient failure of a part doesn't restart the whole upload. If any part still fails,
+    /// the upload is aborted, so that s3 doesn't keep the parts that were uploaded.
+    /// Parts are slices of `bytes`, so the whole object is kept in memory until the upload is over.
+    fn upload_multipart(&self, request: CreateMultipartUploadRequest, bytes: Rc<Vec<u8>>) -> Box<Future<Item = (), Error = S3Error>> {
+        let client = self.client.clone();
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.customer_key();
+        let parts = part_ranges(bytes.len(), self.multipart.part_size_bytes);
+        let concurrency = self.multipart.concurrency.max(1);
+        let retries = self.retries.clone();
+        let bucket = request.bucket.clone();
+        let key = request.key.clone();
+        let create = retry(&retries, "create multipart upload", key.clone(), {
+            let client = client.clone();
+            move || Box::new(client.create_multipart_upload(&request).map_err(S3Error::from))
+        });
+        Box::new(create.and_then(move |output| -> Box<Future<Item = (), Error = S3Error>> {
+            let upload_id = match output.upload_id {
+                Some(upload_id) => upload_id,
+                None => return S3Error::Unknown(format!("S3 returned no id of multipart upload of {}", key)).into(),
+            };
+            debug!("Uploading {} in {} parts, upload id {}", key, parts.len(), upload_id);
+            let upload_parts = stream::iter_ok(parts)
+                .map({
+                    let client = client.clone();
+                    let retries = retries.clone();
+                    let bucket = bucket.clone();
+                    let key = key.clone();
+                    let upload_id = upload_id.clone();
+                    move |(part_number, range)| {
+                        let part = &bytes[range];
+                        let request = UploadPartRequest {
+                            body: Some(part.to_vec()),
+                            content_md5: Some(content_md5(part)),
+                            bucket: bucket.clone(),
+                            key: key.clone(),
+                            part_number,
+                            upload_id: upload_id.clone(),
+                            sse_customer_algorithm: sse_customer_algorithm.clone(),
+                            sse_customer_key: sse_customer_key.clone(),
+                            sse_customer_key_md5: sse_customer_key_md5.clone(),
+                            ..Default::default()
+                        };
+                        let client = client.clone();
+                        retry(&retries, "upload part", format!("{} part {}", key, part_number), move || {
+                            Box::new(client.upload_part(&request).map_err(S3Error::from))
+                        })
+                        .map(move |output| CompletedPart {
+                            e_tag: output.e_tag,
+                            part_number: Some(part_number),
+                        })
+                    }
+                })
+                .buffered(concurrency)
+                .collect();
+            let complete = upload_parts.and_then({
+                let client = client.clone();
+                let bucket = bucket.clone();
+                let key = key.clone();
+                let upload_id = upload_id.clone();
+                move |parts| {
+                    let request = CompleteMultipartUploadRequest {
+                        bucket,
+                        key: key.clone(),
+                        multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+                        upload_id,
+                        ..Default::default()
+                    };
+                    retry(&retries, "complete multipart upload", key, move || {
+                        Box::new(client.complete_multipart_upload(&request).map(|_| ()).map_err(S3Error::from))
+                    })
+                }
+            });
+            Box::new(complete.or_else(move |e| {
+                warn!("Multipart upload of {} failed, aborting: {}", key, e);
+                let request = AbortMultipartUploadRequest {
+                    bucket,
+                    key: key.clone(),
+                    upload_id,
+                    ..Default::default()
+                };
+                client.abort_multipart_upload(&request).then(move |result| {
+                    if let Err(abort_error) = result {
+                        error!("Failed to abort multipart upload of {}: {}", key, S3Error::from(abort_error));
+                    }
+                    Err(e)
+                })
+            }))
+        }))
+    }
+
+    /// `server_side_encryption` and `ssekms_key_id` fields of upload requests
+    fn server_side_encryption(&self) -> (Option<String>, Option<String>) {
+        self.encryption
+            .as_ref()
+            .map(Encryption::server_side_encryption)
+            .unwrap_or((None, None))
+    }
+
+    /// SSE-C algorithm, key and key MD5 fields of requests that write, read or copy objects
+    fn customer_key(&self) -> (Option<String>, Option<String>, Option<String>) {
+        self.encryption.as_ref().map(Encryption::customer_key).unwrap_or((None, None, None))
+    }
+
+    /// Checks that an object exists with a GET of its first byte. HEAD responses have no body, so rusoto
+    /// reports all failed HEAD requests as `Unknown` errors without error code, and the code is read
+    /// from the error of GET instead.
+    ///
+    /// #Errors
+    /// * `S3Error::NotFound` if there's no such object
+    /// * Errors of GET otherwise, e.g. `S3Error::Unknown` with the code of transient errors
+    fn probe(&self, bucket: String, key: String) -> Box<Future<Item = (), Error = S3Error>> {
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.customer_key();
+        let request = GetObjectRequest {
+            bucket,
+            key,
+            range: Some("bytes=0-0".to_string()),
+            sse_customer_algorithm,
+            sse_customer_key,
+            sse_customer_key_md5,
+            ..Default::default()
+        };
+
+        Box::new(self.client.get_object(&request).then(|result| match result.map_err(S3Error::from) {
+            Ok(_) => Ok(()),
+            // empty objects have no first byte
+            Err(ref e) if e.code().as_ref().map(String::as_str) == Some("InvalidRange") => Ok(()),
+            Err(e) => Err(e),
+        }))
+    }
+}
+
+impl S3Client for S3ClientImpl {
+    fn upload(
+        &self,
+        bucket: String,
+        key: String,
+        content_type: Option<String>,
+        metadata: HashMap<String, String>,
+        tags: HashMap<String, String>,
+        bytes: Rc<Vec<u8>>,
+        content_md5: String,
+        options: UploadOptions,
+    ) -> Box<Future<Item = (), Error = S3Error>> {
+        let (server_side_encryption, ssekms_key_id) = self.server_side_encryption();
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.customer_key();
+        let acl = Some(options.acl.unwrap_or_else(|| DEFAULT_ACL.to_string()));
+        let metadata = if metadata.is_empty() { None } else { Some(metadata) };
+        if bytes.len() > self.multipart.threshold_bytes {
+            let request = CreateMultipartUploadRequest {
+                acl,
+                bucket,
+                cache_control: options.cache_control,
+                content_disposition: options.content_disposition,
+                content_type,
+                expires: options.expires,
+                key,
+                metadata,
+                sse_customer_algorithm,
+                sse_customer_key,
+                sse_customer_key_md5,
+                ssekms_key_id,
+                server_side_encryption,
+                storage_class: options.storage_class,
+                tagging: if tags.is_empty() { None } else { Some(tags::encode(&tags)) },
+                ..Default::default()
+            };
+            return self.upload_multipart(request, bytes);
+        }
         let request = PutObjectRequest {
-            acl: Some("public-read".to_string()),
-            body: Some(bytes),
+            acl,
+            // rusoto owns request bodies, the copy is skipped if nobody else holds the bytes
+            body: Some(Rc::try_unwrap(bytes).unwrap_or_else(|bytes| bytes.to_vec())),
             bucket,
-            cache_control: None,
-            content_disposition: None,
+            cache_control: options.cache_control,
+            content_disposition: options.content_disposition,
             content_encoding: None,
             content_language: None,
             content_length: None,
-            content_md5: None,
+            content_md5: Some(content_md5),
             content_type,
-            expires: None,
+            expires: options.expires,
             grant_full_control: None,
             grant_read: None,
             grant_read_acp: None,
             grant_write_acp: None,
             key,
-            metadata: None,
+            metadata,
             request_payer: None,
-            sse_customer_algorithm: None,
-            sse_customer_key: None,
-            sse_customer_key_md5: None,
-            ssekms_key_id: None,
-            server_side_encryption: None,
-            storage_class: None,
-            tagging: None,
+            sse_customer_algorithm,
+            sse_customer_key,
+            sse_customer_key_md5,
+            ssekms_key_id,
+            server_side_encryption,
+            storage_class: options.storage_class,
+            tagging: if tags.is_empty() { None } else { Some(tags::encode(&tags)) },
             website_redirect_location: None,
         };
 
-        Box::new(self.put_object(&request).map(|_| ()).map_err(S3Error::from))
+        let client = self.client.clone();
+        retry(&self.retries, "upload", request.key.clone(), move || {
+            Box::new(client.put_object(&request).map(|_| ()).map_err(S3Error::from))
+        })
+    }
+
+    fn exists(&self, bucket: String, key: String) -> Box<Future<Item = bool, Error = S3Error>> {
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.customer_key();
+        let request = HeadObjectRequest {
+            bucket,
+            key,
+            sse_customer_algorithm,
+            sse_customer_key,
+            sse_customer_key_md5,
+            ..Default::default()
+        };
+
+        let probe = self.probe(request.bucket.clone(), request.key.clone());
+        Box::new(
+            self.client
+                .head_object(&request)
+                .then(move |result| match result.map_err(S3Error::from) {
+                    Ok(_) => Box::new(future::ok(true)) as Box<Future<Item = bool, Error = S3Error>>,
+                    Err(S3Error::NotFound(_)) => Box::new(future::ok(false)),
+                    Err(S3Error::Unknown(_)) => Box::new(probe.then(|result| match result {
+                        Ok(()) => Ok(true),
+                        Err(S3Error::NotFound(_)) => Ok(false),
+                        Err(e) => Err(e),
+                    })),
+                    Err(e) => Box::new(future::err(e)),
+                }),
+        )
+    }
+
+    fn download(&self, bucket: String, key: String) -> Box<Future<Item = S3Object, Error = S3Error>> {
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.customer_key();
+        let request = GetObjectRequest {
+            bucket,
+            key,
+            sse_customer_algorithm,
+            sse_customer_key,
+            sse_customer_key_md5,
+            ..Default::default()
+        };
+
+        Box::new(self.client.get_object(&request).map_err(S3Error::from).and_then(|output| {
+            let metadata = output.metadata.unwrap_or_default();
+            let bytes = match output.body {
+                Some(body) => {
+                    Box::new(body.concat2().map_err(|e| S3Error::Network(format!("{}", e)))) as Box<Future<Item = Vec<u8>, Error = S3Error>>
+                }
+                None => Box::new(future::ok(Vec::new())),
+            };
+            bytes.map(move |bytes| S3Object { bytes, metadata })
+        }))
+    }
+
+    fn head(&self, bucket: String, key: String) -> Box<Future<Item = S3ObjectInfo, Error = S3Error>> {
+        let (sse_customer_algorithm, sse_customer_key, sse_customer_key_md5) = self.customer_key();
+        let request = HeadObjectRequest {
+            bucket,
+            key: key.clone(),
+            sse_customer_algorithm,
+            sse_customer_key,
+            sse_customer_key_md5,
+            ..Default::default()
+        };
+
+        let probe = self.probe(request.bucket.clone(), key.clone());
+        Box::new(
+            self.client
+                .head_object(&request)
+                .then(move |result| match result.map_err(S3Error::from) {
+                    Ok(output) => Box::new(future::ok(S3ObjectInfo {
+                        size: output.content_length.map(|size| size as u64),
+                        content_type: output.content_type,
+                        last_modified: output.last_modified,
+                        metadata: output.metadata.unwrap_or_default(),
+                    })) as Box<Future<Item = S3ObjectInfo, Error = S3Error>>,
+                    Err(S3Error::Unknown(_)) => Box::new(probe.then(move |result| match result {
+                        // the object is there, so HEAD failed with a transient error that GET didn't hit
+                        Ok(()) => Err(S3Error::Network(format!("HEAD request of {} failed", key))),
+                        Err(e) => Err(e),
+                    })),
+                    Err(e) => Box::new(future::err(e)),
+                }),
+        )
+    }
+
+    fn presigned_url(&self, bucket: String, key: String, expires_in: Duration) -> Box<Future<Item = String, Error = S3Error>> {
+        let region = self.region.clone();
+        Box::new(
+            self.credentials
+                .credentials()
+                .map_err(|e| S3Error::Access(format!("{}", e)))
+                .map(move |credentials| presign::presigned_url(&credentials, &region, &bucket, &key, expires_in, Utc::now())),
+        )
+    }
+
+    fn delete(&self, bucket: String, key: String) -> Box<Future<Item = (), Error = S3Error>> {
+        let request = DeleteObjectRequest {
+            bucket,
+            key,
+            ..Default::default()
+        };
+
+        Box::new(self.client.delete_object(&request).map(|_| ()).map_err(S3Error::from))
+    }
+}
+
+/// Splits an object of `len` bytes into numbered parts of multipart upload of at least `part_size` bytes,
+/// except for the last one. Part size is increased to fit s3 limits, see `MIN_PART_SIZE` and `MAX_PARTS`.
+fn part_ranges(len: usize, part_size: usize) -> Vec<(i64, Range<usize>)> {
+    let part_size = part_size.max(MIN_PART_SIZE).max((len + MAX_PARTS - 1) / MAX_PARTS);
+    (0..len)
+        .step_by(part_size)
+        .enumerate()
+        .map(|(index, start)| (index as i64 + 1, start..(start + part_size).min(len)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_part_ranges() {
+        let mb = 1024 * 1024;
+        assert_eq!(
+            part_ranges(12 * mb, 5 * mb),
+            vec![(1, 0..5 * mb), (2, 5 * mb..10 * mb), (3, 10 * mb..12 * mb)]
+        );
+        // parts are at least 5 MB
+        assert_eq!(part_ranges(6 * mb, mb), vec![(1, 0..5 * mb), (2, 5 * mb..6 * mb)]);
+        // and there are at most 10000 of them
+        let parts = part_ranges(60000 * mb, 5 * mb);
+        assert_eq!(parts.len(), 10000);
+        assert_eq!(parts.last().unwrap().1.end, 60000 * mb);
     }
 }
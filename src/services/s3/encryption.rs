@@ -0,0 +1,117 @@
+//! Server-side encryption of objects stored in s3, see
+//! [AWS docs](https://docs.aws.amazon.com/AmazonS3/latest/dev/serv-side-encryption.html)
+
+use base64;
+use md5;
+use std::fs::File;
+use std::io::Read;
+
+use super::error::S3Error;
+use super::types::EncryptionOptions;
+
+/// Algorithm of SSE-S3 and SSE-C
+static AES256: &str = "AES256";
+/// Algorithm of SSE-KMS
+static AWS_KMS: &str = "aws:kms";
+
+/// Server-side encryption applied to every uploaded object
+#[derive(Clone, Debug, PartialEq)]
+pub enum Encryption {
+    /// SSE-S3, keys are managed by s3
+    S3,
+    /// SSE-KMS with id of a KMS key, or the default `aws/s3` key
+    Kms(Option<String>),
+    /// SSE-C, the key is sent with every request that writes or reads an object
+    Customer {
+        /// Base64 encoded 256-bit key
+        key: String,
+        /// Base64 encoded MD5 digest of the key
+        key_md5: String,
+    },
+}
+
+impl Encryption {
+    /// Creates encryption from config, SSE-C key is read from `key_path`
+    ///
+    /// #Errors
+    /// * `S3Error::Access` if SSE-C key can't be read or is not a 256-bit key
+    pub fn load(options: &EncryptionOptions) -> Result<Self, S3Error> {
+        match options {
+            &EncryptionOptions::S3 => Ok(Encryption::S3),
+            &EncryptionOptions::Kms { ref key_id } => Ok(Encryption::Kms(key_id.clone())),
+            &EncryptionOptions::Customer { ref key_path } => {
+                let mut bytes = Vec::new();
+                File::open(key_path)
+                    .and_then(|mut file| file.read_to_end(&mut bytes))
+                    .map_err(|e| S3Error::Access(format!("Error reading SSE-C key file {}: {}", key_path, e)))?;
+                Self::customer(&bytes)
+            }
+        }
+    }
+
+    /// SSE-C with a 256-bit key, either raw or base64 encoded
+    ///
+    /// #Errors
+    /// * `S3Error::Access` if the key is not 256-bit
+    pub fn customer(bytes: &[u8]) -> Result<Self, S3Error> {
+        let key = if bytes.len() == 32 {
+            bytes.to_vec()
+        } else {
+            let encoded = String::from_utf8_lossy(bytes);
+            base64::decode(encoded.trim()).map_err(|e| S3Error::Access(format!("Error decoding SSE-C key: {}", e)))?
+        };
+        if key.len() != 32 {
+            return Err(S3Error::Access(format!("SSE-C key must be 256-bit, got {} bits", key.len() * 8)));
+        }
+        Ok(Encryption::Customer {
+            key: base64::encode(&key),
+            key_md5: base64::encode(&md5::compute(&key).0),
+        })
+    }
+
+    /// `server_side_encryption` and `ssekms_key_id` fields of upload requests
+    pub fn server_side_encryption(&self) -> (Option<String>, Option<String>) {
+        match self {
+            &Encryption::S3 => (Some(AES256.to_string()), None),
+            &Encryption::Kms(ref key_id) => (Some(AWS_KMS.to_string()), key_id.clone()),
+            &Encryption::Customer { .. } => (None, None),
+        }
+    }
+
+    /// `sse_customer_algorithm`, `sse_customer_key` and `sse_customer_key_md5` fields of requests that write,
+    /// read or copy objects, all `None` unless it's SSE-C. Copy requests need them for both source and target.
+    pub fn customer_key(&self) -> (Option<String>, Option<String>, Option<String>) {
+        match self {
+            &Encryption::Customer { ref key, ref key_md5 } => (Some(AES256.to_string()), Some(key.clone()), Some(key_md5.clone())),
+            _ => (None, None, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_customer_key() {
+        let raw = Encryption::customer(&[7u8; 32]).unwrap();
+        let encoded = Encryption::customer(base64::encode(&[7u8; 32]).as_bytes()).unwrap();
+        assert_eq!(raw, encoded);
+        let (algorithm, key, key_md5) = raw.customer_key();
+        assert_eq!(algorithm, Some("AES256".to_string()));
+        assert_eq!(key, Some(base64::encode(&[7u8; 32])));
+        assert_eq!(key_md5, Some(base64::encode(&md5::compute(&[7u8; 32]).0)));
+        assert_eq!(raw.server_side_encryption(), (None, None));
+        assert!(Encryption::customer(&[7u8; 16]).is_err());
+    }
+
+    #[test]
+    fn test_server_side_encryption() {
+        assert_eq!(Encryption::S3.server_side_encryption(), (Some("AES256".to_string()), None));
+        assert_eq!(
+            Encryption::Kms(Some("key-id".to_string())).server_side_encryption(),
+            (Some("aws:kms".to_string()), Some("key-id".to_string()))
+        );
+        assert_eq!(Encryption::S3.customer_key(), (None, None, None));
+    }
+}
@@ -2,7 +2,12 @@
 
 use futures::future::err;
 use futures::Future;
-use rusoto_s3::PutObjectError;
+use rusoto_s3::{
+    AbortMultipartUploadError, CompleteMultipartUploadError, CreateMultipartUploadError, DeleteObjectError, GetObjectError,
+    HeadObjectError, PutObjectError, UploadPartError,
+};
+
+use super::utils::xml_element;
 
 /// Error for S3 service
 #[derive(Debug, Fail)]
@@ -11,10 +16,36 @@ pub enum S3Error {
     Access(String),
     #[fail(display = "Network Error: {}", _0)]
     Network(String),
+    #[fail(display = "Not found: {}", _0)]
+    NotFound(String),
+    /// Object exists, but the user has no access to it
+    #[fail(display = "Forbidden: {}", _0)]
+    Forbidden(String),
     #[fail(display = "Image Error: {}", _0)]
     Image(String),
     #[fail(display = "Unknown error: {}", _0)]
     Unknown(String),
+    /// Upload of one of the objects of an image failed, so objects that were uploaded were deleted
+    #[fail(
+        display = "{}, rolled back upload: deleted {:?}, failed to delete {:?}",
+        cause, deleted, not_deleted
+    )]
+    RolledBack {
+        cause: Box<S3Error>,
+        deleted: Vec<String>,
+        not_deleted: Vec<String>,
+    },
+}
+
+impl S3Error {
+    /// Code of s3 error response of `Unknown` errors, e.g. `SlowDown`. `None` for other errors
+    /// and responses without code, e.g. of HEAD requests.
+    pub fn code(&self) -> Option<String> {
+        match self {
+            &S3Error::Unknown(ref body) => xml_element(body, "Code"),
+            _ => None,
+        }
+    }
 }
 
 impl<T: 'static> Into<Box<Future<Item = T, Error = S3Error>>> for S3Error {
@@ -33,3 +64,83 @@ impl From<PutObjectError> for S3Error {
         }
     }
 }
+
+impl From<GetObjectError> for S3Error {
+    fn from(e: GetObjectError) -> Self {
+        match e {
+            GetObjectError::NoSuchKey(err) => S3Error::NotFound(err),
+            GetObjectError::HttpDispatch(err) => S3Error::Network(format!("{}", err)),
+            GetObjectError::Credentials(err) => S3Error::Access(format!("{}", err)),
+            GetObjectError::Validation(err) => S3Error::Access(format!("{}", err)),
+            GetObjectError::Unknown(err) => S3Error::Unknown(format!("{}", err)),
+        }
+    }
+}
+
+impl From<HeadObjectError> for S3Error {
+    fn from(e: HeadObjectError) -> Self {
+        match e {
+            HeadObjectError::NoSuchKey(err) => S3Error::NotFound(err),
+            HeadObjectError::HttpDispatch(err) => S3Error::Network(format!("{}", err)),
+            HeadObjectError::Credentials(err) => S3Error::Access(format!("{}", err)),
+            HeadObjectError::Validation(err) => S3Error::Access(format!("{}", err)),
+            HeadObjectError::Unknown(err) => S3Error::Unknown(format!("{}", err)),
+        }
+    }
+}
+
+impl From<DeleteObjectError> for S3Error {
+    fn from(e: DeleteObjectError) -> Self {
+        match e {
+            DeleteObjectError::HttpDispatch(err) => S3Error::Network(format!("{}", err)),
+            DeleteObjectError::Credentials(err) => S3Error::Access(format!("{}", err)),
+            DeleteObjectError::Validation(err) => S3Error::Access(format!("{}", err)),
+            DeleteObjectError::Unknown(err) => S3Error::Unknown(format!("{}", err)),
+        }
+    }
+}
+
+impl From<CreateMultipartUploadError> for S3Error {
+    fn from(e: CreateMultipartUploadError) -> Self {
+        match e {
+            CreateMultipartUploadError::HttpDispatch(err) => S3Error::Network(format!("{}", err)),
+            CreateMultipartUploadError::Credentials(err) => S3Error::Access(format!("{}", err)),
+            CreateMultipartUploadError::Validation(err) => S3Error::Access(format!("{}", err)),
+            CreateMultipartUploadError::Unknown(err) => S3Error::Unknown(format!("{}", err)),
+        }
+    }
+}
+
+impl From<UploadPartError> for S3Error {
+    fn from(e: UploadPartError) -> Self {
+        match e {
+            UploadPartError::HttpDispatch(err) => S3Error::Network(format!("{}", err)),
+            UploadPartError::Credentials(err) => S3Error::Access(format!("{}", err)),
+            UploadPartError::Validation(err) => S3Error::Access(format!("{}", err)),
+            UploadPartError::Unknown(err) => S3Error::Unknown(format!("{}", err)),
+        }
+    }
+}
+
+impl From<CompleteMultipartUploadError> for S3Error {
+    fn from(e: CompleteMultipartUploadError) -> Self {
+        match e {
+            CompleteMultipartUploadError::HttpDispatch(err) => S3Error::Network(format!("{}", err)),
+            CompleteMultipartUploadError::Credentials(err) => S3Error::Access(format!("{}", err)),
+            CompleteMultipartUploadError::Validation(err) => S3Error::Access(format!("{}", err)),
+            CompleteMultipartUploadError::Unknown(err) => S3Error::Unknown(format!("{}", err)),
+        }
+    }
+}
+
+impl From<AbortMultipartUploadError> for S3Error {
+    fn from(e: AbortMultipartUploadError) -> Self {
+        match e {
+            AbortMultipartUploadError::NoSuchUpload(err) => S3Error::NotFound(err),
+            AbortMultipartUploadError::HttpDispatch(err) => S3Error::Network(format!("{}", err)),
+            AbortMultipartUploadError::Credentials(err) => S3Error::Access(format!("{}", err)),
+            AbortMultipartUploadError::Validation(err) => S3Error::Access(format!("{}", err)),
+            AbortMultipartUploadError::Unknown(err) => S3Error::Unknown(format!("{}", err)),
+        }
+    }
+}
@@ -1,27 +1,89 @@
 //! S3 service handles uploading static assets like images and videos to s3
 
+pub mod checksum;
 pub mod client;
 pub mod credentials;
+pub mod encryption;
 pub mod error;
 pub mod preprocessors;
+pub mod presign;
 pub mod random;
+pub mod retry;
+pub mod tags;
 pub mod types;
+pub mod utils;
 
+use chrono;
+use chrono::{DateTime, Utc};
 use futures::future;
 use futures::future::Future;
 use futures_cpupool::CpuPool;
-use image::ImageFormat;
 use rusoto_core::region::Region;
 use rusoto_core::request::{HttpClient, TlsError};
-use rusoto_s3::S3Client as CrateS3Client;
+use std::collections::{BTreeMap, HashMap};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio_core::reactor::Handle;
 
-use self::client::S3Client;
+use self::client::{S3Client, S3ClientImpl, S3Object};
+use self::encryption::Encryption;
 use self::error::S3Error;
-use self::preprocessors::{Image, ImageImpl};
+use self::preprocessors::watermark::Watermark;
+use self::preprocessors::{EncodedImage, Image, ImageImpl, ProcessedImage};
 use self::random::{Random, RandomImpl};
-use self::types::ImageSize;
+use self::retry::RetryingS3Client;
+use self::types::{
+    Checksums, Colors, DenseVariant, Fit, FocalPoint, ImageInfo, ImageSize, InputFormat, KeyOptions, MultipartOptions, OutputFormat,
+    Placeholder, ProcessOptions, Requester, ResizeParams, RetryOptions, SignedUrl, UploadOptions, UploadedImage, DEFAULT_KEY_TEMPLATE,
+};
+
+/// Metadata entry with focal point of uploaded image, see `FocalPoint`
+static FOCAL_POINT_METADATA: &str = "focal-point";
+/// Metadata entries with dimensions and format of the object
+static WIDTH_METADATA: &str = "width";
+static HEIGHT_METADATA: &str = "height";
+static FORMAT_METADATA: &str = "format";
+/// Metadata entry with comma-separated variants of uploaded image, e.g. `thumb.png,medium@2x.png`
+static VARIANTS_METADATA: &str = "variants";
+/// Metadata entries with placeholders of uploaded image, see `Placeholder`
+static BLURHASH_METADATA: &str = "blurhash";
+static LQIP_METADATA: &str = "lqip";
+/// Metadata entries with dominant colour and comma-separated palette of uploaded image, see `Colors`
+static DOMINANT_COLOR_METADATA: &str = "dominant-color";
+static PALETTE_METADATA: &str = "palette";
+/// Metadata entry with time of upload in RFC 3339 format
+static UPLOADED_AT_METADATA: &str = "uploaded-at";
+/// Hex encoded SHA-256 of the object, see `Checksums`
+static SHA256_METADATA: &str = "sha256";
+/// Max size of user-defined metadata of an object, s3 counts bytes of all keys and values
+static MAX_METADATA_SIZE: usize = 2048;
+/// Metadata entries that are dropped, in this order, if metadata of an object is larger than `MAX_METADATA_SIZE`
+static OPTIONAL_METADATA: &[&str] = &[LQIP_METADATA, PALETTE_METADATA, BLURHASH_METADATA, VARIANTS_METADATA];
+/// Metadata entry with id of the user who uploaded the image
+static OWNER_METADATA: &str = "owner";
+/// Metadata entry of private images, resized copies of them are private too
+static PRIVATE_METADATA: &str = "private";
+/// Canned ACL of private objects
+static PRIVATE_ACL: &str = "private";
+/// Tag with id of the user who uploaded the image
+static UPLOADER_TAG: &str = "uploader";
+/// Tag with the route of the upload, e.g. `images`, `avatars` or `resized`
+static ROUTE_TAG: &str = "route";
+/// Tag with the variant of the image, e.g. `thumb`, `medium@2x`, `original` or `source`
+static VARIANT_TAG: &str = "variant";
+/// Tag with the name of the uploaded file
+static FILENAME_TAG: &str = "filename";
+/// `{user_id}` of images uploaded without a user
+static ANONYMOUS_USER_ID: &str = "anonymous";
+/// Route of resized copies
+static RESIZED_ROUTE: &str = "resized";
+/// Variant of unedited source image
+static SOURCE_VARIANT: &str = "source";
+/// Private variant of watermarked original without the watermark, resized copies are made from it
+static UNMARKED_VARIANT: &str = "unmarked";
+/// Metadata entry of watermarked original with its unmarked variant, e.g. `unmarked.png`
+static UNMARKED_METADATA: &str = "unmarked";
 
 /// S3 service
 #[derive(Clone)]
@@ -32,6 +94,42 @@ pub struct S3 {
     cpu_pool: Rc<CpuPool>,
     random: Rc<Random>,
     image_preprocessor_factory: Rc<for<'a> Fn(&'a CpuPool) -> Box<Image + 'a>>,
+    /// Template of object keys, see `KeyOptions`
+    key_template: String,
+}
+
+/// Key, tags and upload options shared by all objects of an uploaded image
+struct ImageUpload {
+    image_key: String,
+    tags: HashMap<String, String>,
+    /// Upload options of the route
+    upload: UploadOptions,
+    /// Overrides of `upload` by preset
+    preset_uploads: HashMap<ImageSize, UploadOptions>,
+    private: bool,
+}
+
+impl ImageUpload {
+    /// Upload options of preset `size` with its overrides, unedited source (`None`) is uploaded with options of the route.
+    /// Options of private images always have `private` ACL.
+    fn options(&self, size: Option<&ImageSize>) -> UploadOptions {
+        let upload = match size.and_then(|size| self.preset_uploads.get(size)) {
+            Some(overrides) => self.upload.merge(overrides),
+            None => self.upload.clone(),
+        };
+        if self.private {
+            S3::private_upload(upload)
+        } else {
+            upload
+        }
+    }
+
+    /// Shared tags along with the `variant` tag
+    fn tags(&self, variant: String) -> HashMap<String, String> {
+        let mut tags = self.tags.clone();
+        tags.insert(VARIANT_TAG.to_string(), variant);
+        tags
+    }
 }
 
 impl S3 {
@@ -53,77 +151,807 @@ impl S3 {
             cpu_pool: Rc::new(CpuPool::new_num_cpus()),
             random: random.into(),
             image_preprocessor_factory: Rc::new(image_preprocessor_factory),
+            key_template: DEFAULT_KEY_TEMPLATE.to_string(),
         }
     }
 
+    /// Names uploaded objects after `key_template` instead of `DEFAULT_KEY_TEMPLATE`, see `KeyOptions`
+    pub fn with_key_template(self, key_template: String) -> Self {
+        Self { key_template, ..self }
+    }
+
     /// Create s3 service
     ///
-    /// * `key` - AWS key for s3 (from AWS console).
-    /// * `secret` - AWS secret for s3 (from AWS console).
+    /// * `static_credentials` - AWS key and secret from config, they take precedence over other providers
+    /// of the credentials chain, see `Credentials::chain`
     /// * `bucket` - AWS s3 bucket name
+    /// * `encryption` - server-side encryption of stored objects
+    /// * `retries` - retries of requests that failed with transient errors
+    /// * `multipart` - multipart upload of large objects
+    /// * `keys` - naming scheme of uploaded objects
+    /// * `watermark` - watermark overlaid on uploaded images
     /// * `handle` - tokio event loop handle (needed for s3 http client)
-    pub fn create<K, S, B>(key: K, secret: S, region: Region, bucket: B, handle: &Handle) -> Result<Self, TlsError>
+    pub fn create<B>(
+        static_credentials: Option<(String, String)>,
+        region: Region,
+        bucket: B,
+        encryption: Option<Encryption>,
+        retries: RetryOptions,
+        multipart: MultipartOptions,
+        keys: KeyOptions,
+        watermark: Option<Watermark>,
+        handle: &Handle,
+    ) -> Result<Self, TlsError>
     where
-        K: ToString,
-        S: ToString,
         B: ToString,
     {
-        let credentials = credentials::Credentials::new(key.to_string(), secret.to_string());
+        let credentials = credentials::Credentials::chain(static_credentials, &region, handle);
         let client = HttpClient::new(handle)?;
-        let random = RandomImpl::new();
+        let random = RandomImpl::with_id(keys.id);
+        let watermark = watermark.map(Arc::new);
         Ok(Self::new(
             region.clone(),
             bucket,
-            Box::new(CrateS3Client::new(client, credentials, region)),
+            Box::new(RetryingS3Client::new(
+                Box::new(S3ClientImpl::new(
+                    client,
+                    credentials,
+                    region,
+                    encryption,
+                    multipart,
+                    retries.clone(),
+                )),
+                retries,
+            )),
             Box::new(random),
-            |cpu_pool| Box::new(ImageImpl::new(cpu_pool)),
-        ))
+            move |cpu_pool| Box::new(ImageImpl::with_watermark(cpu_pool, watermark.clone())),
+        )
+        .with_key_template(keys.template))
     }
 
     /// Uploads image along with all resized variants in `ImageSize` enum. If original image size is less
     /// than e.g. ImageSize::Large, then original image is uploaded instead of large.
     ///
-    /// * `format` - now only "png" or "jpg" are supported
+    /// * `format` - "png", "jpg", "gif" or one of formats enabled by cargo features, see `InputFormat`
     /// * `bytes` - bytes representing compressed image (compressed with `image_type` codec)
+    /// * `options` - options of processing, e.g. how resized images fit the `ImageSize` boxes
+    ///
+    /// Returns url of original image along with placeholders and colours. Animated variants
+    /// are uploaded with `gif` extension, e.g. `img-dsf-large.gif`. Jpeg originals are uploaded as is,
+    /// but keep `png` extension, e.g. `img-dsf.png`. Colours and focal point
+    /// are also stored in metadata of every uploaded object. If the image was cropped, rotated or
+    /// watermarked, the edited image is uploaded as original, and unedited image is kept only if
+    /// `options.keep_source` is set. Watermarked original also has a private unmarked variant, e.g.
+    /// `img-dsf-unmarked.png`, that is not listed in variants. Variants for high density screens are uploaded with density suffix,
+    /// e.g. `img-dsf-medium@2x.png`, and listed in `srcset` of every preset. MD5 and SHA-256 of every
+    /// uploaded object are returned in `checksums`, SHA-256 is also stored in its metadata.
+    /// Objects are tagged with the uploader, route, variant and name of the uploaded file.
+    /// If any of the uploads fails, objects that were uploaded are deleted.
     ///
     /// #Errors
     /// * `S3Error::Image` if encoding is incorrect, incl zero dimensions
-    pub fn upload_image(&self, format: ImageFormat, bytes: Vec<u8>) -> Box<Future<Item = String, Error = S3Error>> {
-        let random_hash = self.random.generate_hash();
-        let original_name = Self::create_aws_name("img", "png", &ImageSize::Original, &random_hash);
-        let url = format!("https://s3.{}.amazonaws.com/{}/{}", self.region.name(), self.bucket, original_name);
+    /// * `S3Error::RolledBack` if uploading any of the objects failed
+    pub fn upload_image(
+        &self,
+        format: InputFormat,
+        bytes: Vec<u8>,
+        options: ProcessOptions,
+    ) -> Box<Future<Item = UploadedImage, Error = S3Error>> {
+        self.upload_image_with_prefix("img", "images", format, bytes, options)
+    }
+
+    /// Uploads avatar the same way as `upload_image`, except that resized variants
+    /// are square crops of the image and filenames start with `avatar`.
+    pub fn upload_avatar(
+        &self,
+        format: InputFormat,
+        bytes: Vec<u8>,
+        options: ProcessOptions,
+    ) -> Box<Future<Item = UploadedImage, Error = S3Error>> {
+        let options = ProcessOptions {
+            fit: ImageSize::presets().into_iter().map(|size| (size, Fit::Cover)).collect(),
+            ..options
+        };
+        self.upload_image_with_prefix("avatar", "avatars", format, bytes, options)
+    }
+
+    fn upload_image_with_prefix(
+        &self,
+        prefix: &'static str,
+        route: &str,
+        format: InputFormat,
+        bytes: Vec<u8>,
+        options: ProcessOptions,
+    ) -> Box<Future<Item = UploadedImage, Error = S3Error>> {
         let preprocessor = (*self.image_preprocessor_factory)(&*self.cpu_pool);
         let self_clone = self.clone();
-        Box::new(preprocessor.process(format, bytes).and_then(move |images_hash| {
-            let futures = images_hash
-                .into_iter()
-                .map(move |(size, bytes)| self_clone.upload_image_with_size(&random_hash, &size, bytes));
-            future::join_all(futures).map(move |_| url)
+        let owner = options.owner;
+        let image_upload = ImageUpload {
+            image_key: self.create_image_key(prefix, owner),
+            tags: Self::upload_tags(route, &options),
+            upload: options.upload.clone(),
+            preset_uploads: options.preset_uploads.clone(),
+            private: options.private,
+        };
+        Box::new(
+            preprocessor
+                .process(format, bytes, options)
+                .and_then(move |processed| self_clone.upload_processed(format, processed, owner, image_upload)),
+        )
+    }
+
+    /// Tags shared by all objects of an uploaded image: uploader, route and name of the uploaded file
+    fn upload_tags(route: &str, options: &ProcessOptions) -> HashMap<String, String> {
+        let mut tags = HashMap::new();
+        if let Some(owner) = options.owner {
+            tags.insert(UPLOADER_TAG.to_string(), owner.to_string());
+        }
+        tags.insert(ROUTE_TAG.to_string(), route.to_string());
+        if let Some(ref filename) = options.filename {
+            tags.insert(FILENAME_TAG.to_string(), tags::sanitize(filename));
+        }
+        tags
+    }
+
+    /// Uploads original, variants, unmarked original and source of a processed image, see `upload_image`
+    fn upload_processed(
+        &self,
+        format: InputFormat,
+        processed: ProcessedImage,
+        owner: Option<i32>,
+        image_upload: ImageUpload,
+    ) -> Box<Future<Item = UploadedImage, Error = S3Error>> {
+        let metadata = Self::upload_metadata(&processed, owner, image_upload.private);
+        let original_format = processed
+            .variants
+            .get(&ImageSize::Original)
+            .map(|original| original.format.clone())
+            .unwrap_or(OutputFormat::Png);
+        let original_name = Self::create_aws_name(
+            &image_upload.image_key,
+            Self::original_extension(&original_format),
+            &ImageSize::Original,
+        );
+        let url = self.create_url(&original_name);
+        let srcset = self.create_srcset(&image_upload.image_key, &processed);
+        let unmarked_variant = processed
+            .unmarked
+            .as_ref()
+            .map(|unmarked| format!("{}.{}", UNMARKED_VARIANT, unmarked.format.extension()));
+        let placeholder = processed.placeholder;
+        let colors = processed.colors;
+        let mut uploads = self.upload_variants(
+            &image_upload,
+            &metadata,
+            unmarked_variant,
+            processed.variants,
+            processed.dense_variants,
+        );
+        if let Some(encoded) = processed.unmarked {
+            uploads.push(self.upload_unmarked(&image_upload, &metadata, encoded));
+        }
+        let source_url = processed.source.map(|bytes| {
+            let (name, upload) = self.upload_source(&image_upload, &metadata, format, bytes);
+            let url = self.create_url(&name);
+            uploads.push((name, upload));
+            url
+        });
+        let mut checksums = BTreeMap::new();
+        let mut futures = Vec::new();
+        for (name, (object_checksums, upload)) in uploads {
+            checksums.insert(name.clone(), object_checksums);
+            futures.push((name, upload));
+        }
+        Box::new(self.upload_all(futures).map(move |_| UploadedImage {
+            url,
+            source_url,
+            placeholder,
+            colors,
+            srcset,
+            checksums,
         }))
     }
 
-    /// Uploads an image with specific size to S3
+    /// Metadata shared by all objects of a processed image: colours, focal point, owner, privacy,
+    /// variants, placeholders and upload time
+    fn upload_metadata(processed: &ProcessedImage, owner: Option<i32>, private: bool) -> HashMap<String, String> {
+        let mut variants = processed
+            .variants
+            .iter()
+            .filter(|&(size, _)| size != &ImageSize::Original)
+            .map(|(size, encoded)| format!("{}.{}", size, encoded.format.extension()))
+            .chain(
+                processed
+                    .dense_variants
+                    .iter()
+                    .map(|(variant, encoded)| format!("{}.{}", variant, encoded.format.extension())),
+            )
+            .collect::<Vec<_>>();
+        variants.sort();
+        let mut metadata = Self::image_metadata(&processed.colors, &processed.focal_point);
+        if let Some(owner) = owner {
+            metadata.insert(OWNER_METADATA.to_string(), owner.to_string());
+        }
+        if private {
+            metadata.insert(PRIVATE_METADATA.to_string(), "true".to_string());
+        }
+        metadata.extend(Self::info_metadata(&variants, &processed.placeholder));
+        metadata
+    }
+
+    /// Urls of presets and their high density variants by preset, e.g.
+    /// `{"medium": "https://.../img-dsf-medium.png 1x, https://.../img-dsf-medium@2x.png 2x"}`
+    fn create_srcset(&self, image_key: &str, processed: &ProcessedImage) -> BTreeMap<String, String> {
+        let mut srcset = BTreeMap::new();
+        for (size, encoded) in processed.variants.iter().filter(|&(size, _)| size != &ImageSize::Original) {
+            let name = Self::create_aws_name(image_key, encoded.format.extension(), size);
+            srcset.insert(size.to_string(), vec![(1, self.create_url(&name))]);
+        }
+        for (variant, encoded) in &processed.dense_variants {
+            let name = Self::create_dense_aws_name(image_key, encoded.format.extension(), variant);
+            if let Some(sources) = srcset.get_mut(&variant.size.to_string()) {
+                sources.push((variant.density, self.create_url(&name)));
+            }
+        }
+        srcset
+            .into_iter()
+            .map(|(size, mut sources)| {
+                sources.sort();
+                let sources = sources
+                    .into_iter()
+                    .map(|(density, url)| format!("{} {}x", url, density))
+                    .collect::<Vec<_>>();
+                (size, sources.join(", "))
+            })
+            .collect()
+    }
+
+    /// Uploads original, presets and high density variants. Watermarked original refers to its
+    /// `unmarked_variant` in metadata, e.g. `unmarked.png`. Returns names of the objects along with
+    /// their checksums and uploads.
+    fn upload_variants(
+        &self,
+        image_upload: &ImageUpload,
+        metadata: &HashMap<String, String>,
+        unmarked_variant: Option<String>,
+        variants: HashMap<ImageSize, EncodedImage>,
+        dense_variants: HashMap<DenseVariant, EncodedImage>,
+    ) -> Vec<(String, (Checksums, Box<Future<Item = (), Error = S3Error>>))> {
+        let image_key = &image_upload.image_key;
+        variants
+            .into_iter()
+            .map(|(size, encoded)| {
+                let extension = match size {
+                    ImageSize::Original => Self::original_extension(&encoded.format),
+                    _ => encoded.format.extension(),
+                };
+                let name = Self::create_aws_name(image_key, extension, &size);
+                let tags = image_upload.tags(size.to_string());
+                let mut metadata = metadata.clone();
+                if let (&ImageSize::Original, &Some(ref unmarked)) = (&size, &unmarked_variant) {
+                    metadata.insert(UNMARKED_METADATA.to_string(), unmarked.clone());
+                }
+                let upload = self.upload_image_with_name(name.clone(), metadata, tags, encoded, image_upload.options(Some(&size)));
+                (name, upload)
+            })
+            .chain(dense_variants.into_iter().map(|(variant, encoded)| {
+                let name = Self::create_dense_aws_name(image_key, encoded.format.extension(), &variant);
+                let tags = image_upload.tags(variant.to_string());
+                let upload = self.upload_image_with_name(
+                    name.clone(),
+                    metadata.clone(),
+                    tags,
+                    encoded,
+                    image_upload.options(Some(&variant.size)),
+                );
+                (name, upload)
+            }))
+            .collect()
+    }
+
+    /// Uploads unmarked variant of watermarked original. It's always private,
+    /// anyone could download the original without watermark otherwise.
+    fn upload_unmarked(
+        &self,
+        image_upload: &ImageUpload,
+        metadata: &HashMap<String, String>,
+        encoded: EncodedImage,
+    ) -> (String, (Checksums, Box<Future<Item = (), Error = S3Error>>)) {
+        let name = Self::create_variant_name(&image_upload.image_key, UNMARKED_VARIANT, encoded.format.extension());
+        let mut metadata = metadata.clone();
+        metadata.insert(PRIVATE_METADATA.to_string(), "true".to_string());
+        let upload = self.upload_image_with_name(
+            name.clone(),
+            metadata,
+            image_upload.tags(UNMARKED_VARIANT.to_string()),
+            encoded,
+            Self::private_upload(image_upload.options(Some(&ImageSize::Original))),
+        );
+        (name, upload)
+    }
+
+    /// Uploads unedited source image in its uploaded `format`, with upload options of the route
+    fn upload_source(
+        &self,
+        image_upload: &ImageUpload,
+        metadata: &HashMap<String, String>,
+        format: InputFormat,
+        bytes: Vec<u8>,
+    ) -> (String, (Checksums, Box<Future<Item = (), Error = S3Error>>)) {
+        let name = Self::create_variant_name(&image_upload.image_key, SOURCE_VARIANT, format.extension());
+        let upload = self.upload_object(
+            name.clone(),
+            format.content_type().to_string(),
+            metadata.clone(),
+            image_upload.tags(SOURCE_VARIANT.to_string()),
+            bytes,
+            image_upload.options(None),
+        );
+        (name, upload)
+    }
+
+    /// Resizes an uploaded image on the fly. Resized images are cached in s3 under a name
+    /// derived from `key` and `params`, so later requests with the same params reuse them.
     ///
-    /// * `random_hash` - technically a filename for image
-    /// * `size` - image size for deriving a name tag, like `dsf-small.png`
-    /// * `image_type` - either "png", "jpg" or "jpeg" - these are types that are supported
-    /// * `bytes` - bytes representing compressed image (compressed with `image_type` codec)
-    fn upload_image_with_size(&self, random_hash: &str, size: &ImageSize, bytes: Vec<u8>) -> Box<Future<Item = (), Error = S3Error>> {
-        let name = Self::create_aws_name("img", "png", size, random_hash);
-        self.inner.upload(self.bucket.clone(), name, Some("image/png".to_string()), bytes)
+    /// * `key` - s3 filename of original image, e.g. `img-2IpSsAjuxB8C.png`
+    /// * `params` - box, fit and format of resized image
+    /// * `upload` - ACL, storage class and headers of resized image
+    /// * `requester` - user who requests the image, `None` for anonymous requests
+    ///
+    /// Returns url of resized image. Smart crops without focal point in `params` use
+    /// the focal point stored in metadata of original image. Watermarked originals are resized
+    /// from their unmarked variant and watermarked again, so that the watermark isn't scaled
+    /// along with the image and resized copies never come without it.
+    /// Resized copies of private images are private and have the same owner, and only the owner
+    /// or an admin can request them. They are tagged with route `resized` and their box as variant,
+    /// e.g. `w100-h50-inside`.
+    ///
+    /// #Errors
+    /// * `S3Error::NotFound` if there's no image with filename `key`
+    /// * `S3Error::Forbidden` if the image is private and `requester` is neither its owner nor an admin
+    /// * `S3Error::Image` if original image can't be decoded
+    /// * `S3Error::Forbidden` if the original is watermarked, but the service has no watermark
+    pub fn resize_image(
+        &self,
+        key: &str,
+        params: ResizeParams,
+        upload: UploadOptions,
+        requester: Option<Requester>,
+    ) -> Box<Future<Item = String, Error = S3Error>> {
+        let resized_name = Self::create_resized_name(key, &params);
+        let variant = Self::resized_variant(&params);
+        let url = self.create_url(&resized_name);
+        let key = key.to_string();
+        let self_clone = self.clone();
+        Box::new(self.inner.head(self.bucket.clone(), resized_name.clone()).then(
+            move |cached| -> Box<Future<Item = String, Error = S3Error>> {
+                match cached {
+                    // resized copies have owner and privacy of their originals
+                    Ok(object) => {
+                        debug!("Serving cached resized image {}", resized_name);
+                        return Box::new(future::result(
+                            Self::check_access(&resized_name, &object.metadata, requester).map(|_| url),
+                        ));
+                    }
+                    Err(S3Error::NotFound(_)) => (),
+                    Err(e) => return Box::new(future::err(e)),
+                }
+                Box::new(
+                    self_clone
+                        .inner
+                        .download(self_clone.bucket.clone(), key.clone())
+                        .and_then({
+                            let self_clone = self_clone.clone();
+                            move |object| -> Box<Future<Item = (S3Object, bool), Error = S3Error>> {
+                                if let Err(e) = Self::check_access(&key, &object.metadata, requester) {
+                                    return Box::new(future::err(e));
+                                }
+                                let unmarked = match object.metadata.get(UNMARKED_METADATA).cloned() {
+                                    Some(unmarked) => format!("{}-{}", Self::stem(&key), unmarked),
+                                    None => return Box::new(future::ok((object, false))),
+                                };
+                                // metadata of the original tells the owner and privacy of resized copy
+                                let metadata = object.metadata;
+                                Box::new(self_clone.inner.download(self_clone.bucket.clone(), unmarked).map(move |unmarked| {
+                                    let object = S3Object {
+                                        bytes: unmarked.bytes,
+                                        metadata,
+                                    };
+                                    // copies of the unmarked variant are as public as the original, so they are watermarked
+                                    (object, true)
+                                }))
+                            }
+                        })
+                        .and_then({
+                            let self_clone = self_clone.clone();
+                            move |(object, watermark)| {
+                                let params = match (&params.fit, &params.focal_point) {
+                                    (&Fit::Smart, &None) => ResizeParams {
+                                        focal_point: object
+                                            .metadata
+                                            .get(FOCAL_POINT_METADATA)
+                                            .and_then(|focal_point| focal_point.parse::<FocalPoint>().ok()),
+                                        ..params
+                                    },
+                                    _ => params,
+                                };
+                                let metadata = object
+                                    .metadata
+                                    .iter()
+                                    .filter(|&(name, _)| name == OWNER_METADATA || name == PRIVATE_METADATA)
+                                    .map(|(name, value)| (name.clone(), value.clone()))
+                                    .collect::<HashMap<_, _>>();
+                                let preprocessor = (*self_clone.image_preprocessor_factory)(&*self_clone.cpu_pool);
+                                preprocessor
+                                    .resize(object.bytes, params, watermark)
+                                    .map(|resized| (resized, metadata))
+                            }
+                        })
+                        .and_then(move |(resized, metadata)| {
+                            let upload = if metadata.contains_key(PRIVATE_METADATA) {
+                                Self::private_upload(upload)
+                            } else {
+                                upload
+                            };
+                            let mut tags = HashMap::new();
+                            if let Some(owner) = metadata.get(OWNER_METADATA) {
+                                tags.insert(UPLOADER_TAG.to_string(), owner.clone());
+                            }
+                            tags.insert(ROUTE_TAG.to_string(), RESIZED_ROUTE.to_string());
+                            tags.insert(VARIANT_TAG.to_string(), variant);
+                            let (_, upload) = self_clone.upload_image_with_name(resized_name, metadata, tags, resized, upload);
+                            upload
+                        })
+                        .map(move |_| url),
+                )
+            },
+        ))
     }
 
-    fn create_aws_name(prefix: &str, image_type: &str, size: &ImageSize, random_hash: &str) -> String {
-        let name = match size {
-            &ImageSize::Original => format!("{}-{}.{}", prefix, random_hash, image_type), // don't use postfix if this is original image
-            _ => format!("{}-{}-{}.{}", prefix, random_hash, size, image_type),
-        };
-        name
+    /// Runs uploads of objects of one image concurrently. If any of them fails, objects that were uploaded
+    /// are deleted, so that the bucket has no partially uploaded images.
+    ///
+    /// * `uploads` - s3 filenames along with their uploads
+    ///
+    /// #Errors
+    /// * `S3Error::RolledBack` with the first upload error and the result of deleting uploaded objects
+    fn upload_all(&self, uploads: Vec<(String, Box<Future<Item = (), Error = S3Error>>)>) -> Box<Future<Item = (), Error = S3Error>> {
+        let self_clone = self.clone();
+        // wait for all uploads to finish, so that none of them is left in flight
+        let uploads = uploads
+            .into_iter()
+            .map(|(name, upload)| upload.then(move |result| Ok::<_, S3Error>((name, result))));
+        Box::new(
+            future::join_all(uploads).and_then(move |results| -> Box<Future<Item = (), Error = S3Error>> {
+                let mut uploaded = Vec::new();
+                let mut first_error = None;
+                for (name, result) in results {
+                    match result {
+                        Ok(()) => uploaded.push(name),
+                        Err(e) => {
+                            error!("Failed to upload {}: {}", name, e);
+                            first_error = first_error.or(Some(e));
+                        }
+                    }
+                }
+                let cause = match first_error {
+                    Some(cause) => cause,
+                    None => return Box::new(future::ok(())),
+                };
+                let deletes = uploaded.into_iter().map(move |name| {
+                    self_clone
+                        .inner
+                        .delete(self_clone.bucket.clone(), name.clone())
+                        .then(move |result| Ok::<_, S3Error>((name, result)))
+                });
+                Box::new(future::join_all(deletes).and_then(move |results| {
+                    let mut deleted = Vec::new();
+                    let mut not_deleted = Vec::new();
+                    for (name, result) in results {
+                        match result {
+                            Ok(()) => deleted.push(name),
+                            Err(e) => {
+                                error!("Failed to delete {} while rolling back upload: {}", name, e);
+                                not_deleted.push(name);
+                            }
+                        }
+                    }
+                    warn!("Rolled back upload, deleted {:?}, failed to delete {:?}", deleted, not_deleted);
+                    Err(S3Error::RolledBack {
+                        cause: Box::new(cause),
+                        deleted,
+                        not_deleted,
+                    })
+                }))
+            }),
+        )
+    }
+
+    /// Issues a presigned url of an uploaded object, which allows to download it until it expires
+    /// even if it's private
+    ///
+    /// * `key` - s3 filename, e.g. `img-2IpSsAjuxB8C-small.png`
+    /// * `user_id` - id of the user who requests the url, must be the owner of the object unless `admin` is set
+    /// * `expires_in` - lifetime of the url
+    ///
+    /// #Errors
+    /// * `S3Error::NotFound` if there's no object with filename `key`
+    /// * `S3Error::Forbidden` if the object wasn't uploaded by `user_id` and `admin` is not set
+    pub fn signed_url(&self, key: &str, user_id: i32, admin: bool, expires_in: Duration) -> Box<Future<Item = SignedUrl, Error = S3Error>> {
+        let key = key.to_string();
+        let self_clone = self.clone();
+        Box::new(self.inner.head(self.bucket.clone(), key.clone()).and_then(
+            move |object| -> Box<Future<Item = SignedUrl, Error = S3Error>> {
+                let owner = object.metadata.get(OWNER_METADATA).and_then(|owner| owner.parse::<i32>().ok());
+                if !admin && owner != Some(user_id) {
+                    return S3Error::Forbidden(key).into();
+                }
+                let expires_at = Utc::now() + chrono::Duration::seconds(expires_in.as_secs() as i64);
+                Box::new(
+                    self_clone
+                        .inner
+                        .presigned_url(self_clone.bucket.clone(), key, expires_in)
+                        .map(move |url| SignedUrl {
+                            url,
+                            expires_at: expires_at.to_rfc3339(),
+                        }),
+                )
+            },
+        ))
+    }
+
+    /// Checks that `requester` can read object `key` with `metadata`. Private objects can be read
+    /// only by their owner or an admin, other objects by anyone.
+    ///
+    /// #Errors
+    /// * `S3Error::Forbidden` if the object is private and `requester` is neither its owner nor an admin
+    fn check_access(key: &str, metadata: &HashMap<String, String>, requester: Option<Requester>) -> Result<(), S3Error> {
+        if !metadata.contains_key(PRIVATE_METADATA) {
+            return Ok(());
+        }
+        let owner = metadata.get(OWNER_METADATA).and_then(|owner| owner.parse::<i32>().ok());
+        match requester {
+            Some(requester) if requester.admin || owner == Some(requester.user_id) => Ok(()),
+            _ => Err(S3Error::Forbidden(key.to_string())),
+        }
+    }
+
+    /// Upload options with `private` ACL
+    fn private_upload(upload: UploadOptions) -> UploadOptions {
+        UploadOptions {
+            acl: Some(PRIVATE_ACL.to_string()),
+            ..upload
+        }
+    }
+
+    /// Uploads an image variant to S3
+    ///
+    /// * `name` - s3 filename, like `img-dsf-small.png`
+    /// * `metadata` - user-defined metadata of s3 object
+    /// * `tags` - tags of s3 object
+    /// * `image` - encoded image, its format defines content-type
+    /// * `upload` - ACL, storage class and headers of s3 object
+    ///
+    /// Dimensions and format of the variant are stored in its metadata.
+    /// Returns checksums of the variant along with the upload.
+    fn upload_image_with_name(
+        &self,
+        name: String,
+        mut metadata: HashMap<String, String>,
+        tags: HashMap<String, String>,
+        image: EncodedImage,
+        upload: UploadOptions,
+    ) -> (Checksums, Box<Future<Item = (), Error = S3Error>>) {
+        metadata.insert(WIDTH_METADATA.to_string(), image.width.to_string());
+        metadata.insert(HEIGHT_METADATA.to_string(), image.height.to_string());
+        metadata.insert(FORMAT_METADATA.to_string(), image.format.extension().to_string());
+        self.upload_object(name, image.format.content_type().to_string(), metadata, tags, image.bytes, upload)
+    }
+
+    /// Uploads an object with SHA-256 of `bytes` stored in its metadata. Optional metadata entries
+    /// are dropped if metadata doesn't fit s3 limit, see `fit_metadata`.
+    /// Returns checksums of the object along with the upload.
+    fn upload_object(
+        &self,
+        name: String,
+        content_type: String,
+        mut metadata: HashMap<String, String>,
+        tags: HashMap<String, String>,
+        bytes: Vec<u8>,
+        upload: UploadOptions,
+    ) -> (Checksums, Box<Future<Item = (), Error = S3Error>>) {
+        let checksums = Checksums::compute(&bytes);
+        metadata.insert(SHA256_METADATA.to_string(), checksums.sha256.clone());
+        Self::fit_metadata(&name, &mut metadata);
+        let upload = self.inner.upload(
+            self.bucket.clone(),
+            name,
+            Some(content_type),
+            metadata,
+            tags,
+            Rc::new(bytes),
+            checksums.md5.clone(),
+            upload,
+        );
+        (checksums, upload)
+    }
+
+    /// Drops entries of `OPTIONAL_METADATA` from metadata of object `name` until it's no larger
+    /// than `MAX_METADATA_SIZE`, s3 rejects objects with larger metadata
+    fn fit_metadata(name: &str, metadata: &mut HashMap<String, String>) {
+        for entry in OPTIONAL_METADATA {
+            let size = metadata.iter().map(|(key, value)| key.len() + value.len()).sum::<usize>();
+            if size <= MAX_METADATA_SIZE {
+                return;
+            }
+            if metadata.remove(*entry).is_some() {
+                warn!("Dropped `{}` metadata of {}, its metadata is {} bytes", entry, name, size);
+            }
+        }
+    }
+
+    /// Metadata entries with dominant colour, comma-separated palette and focal point
+    fn image_metadata(colors: &Colors, focal_point: &FocalPoint) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        metadata.insert(DOMINANT_COLOR_METADATA.to_string(), colors.dominant_color.clone());
+        metadata.insert(PALETTE_METADATA.to_string(), colors.palette.join(","));
+        metadata.insert(FOCAL_POINT_METADATA.to_string(), focal_point.to_string());
+        metadata
+    }
+
+    /// Reads dimensions, format, size, variants, placeholders and upload time of an uploaded image
+    /// from metadata of its original, without downloading it
+    ///
+    /// * `key` - s3 filename of original image, e.g. `img-2IpSsAjuxB8C.png`
+    /// * `requester` - user who requests the info, `None` for anonymous requests
+    ///
+    /// Info of private images is given only to their owner or an admin.
+    ///
+    /// #Errors
+    /// * `S3Error::NotFound` if there's no image with filename `key`
+    /// * `S3Error::Forbidden` if the image is private and `requester` is neither its owner nor an admin
+    pub fn image_info(&self, key: &str, requester: Option<Requester>) -> Box<Future<Item = ImageInfo, Error = S3Error>> {
+        let url = self.create_url(key);
+        let stem = Self::stem(key).to_string();
+        let extension = key[stem.len()..].trim_left_matches('.').to_string();
+        let self_clone = self.clone();
+        let key = key.to_string();
+        Box::new(self.inner.head(self.bucket.clone(), key.clone()).and_then(move |object| {
+            Self::check_access(&key, &object.metadata, requester)?;
+            let size = object.size;
+            let last_modified = object.last_modified;
+            let metadata = object.metadata;
+            let variants = metadata
+                .get(VARIANTS_METADATA)
+                .map(|variants| {
+                    variants
+                        .split(',')
+                        .filter(|variant| !variant.is_empty())
+                        .map(|variant| {
+                            let name = Self::stem(variant).to_string();
+                            (name, self_clone.create_url(&format!("{}-{}", stem, variant)))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            let uploaded_at = metadata.get(UPLOADED_AT_METADATA).cloned().or_else(|| {
+                last_modified
+                    .and_then(|last_modified| DateTime::parse_from_rfc2822(&last_modified).ok())
+                    .map(|last_modified| last_modified.to_rfc3339())
+            });
+            Ok(ImageInfo {
+                url,
+                width: metadata.get(WIDTH_METADATA).and_then(|width| width.parse().ok()),
+                height: metadata.get(HEIGHT_METADATA).and_then(|height| height.parse().ok()),
+                format: metadata.get(FORMAT_METADATA).cloned().unwrap_or(extension),
+                size,
+                variants,
+                blurhash: metadata.get(BLURHASH_METADATA).cloned(),
+                lqip: metadata.get(LQIP_METADATA).cloned(),
+                uploaded_at,
+            })
+        }))
+    }
+
+    /// Metadata entries with variants, placeholders and time of upload of an uploaded image.
+    /// Dimensions and format are stored by `upload_image_with_name` for every variant.
+    fn info_metadata(variants: &[String], placeholder: &Placeholder) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        metadata.insert(VARIANTS_METADATA.to_string(), variants.join(","));
+        metadata.insert(BLURHASH_METADATA.to_string(), placeholder.blurhash.clone());
+        metadata.insert(LQIP_METADATA.to_string(), placeholder.lqip.clone());
+        metadata.insert(UPLOADED_AT_METADATA.to_string(), Utc::now().to_rfc3339());
+        metadata
+    }
+
+    /// Public url of s3 object
+    fn create_url(&self, name: &str) -> String {
+        format!("https://s3.{}.amazonaws.com/{}/{}", self.region.name(), self.bucket, name)
+    }
+
+    /// Name of resized image, like `img-dsf-w100-h50-inside.jpg` for `img-dsf.png`
+    fn create_resized_name(key: &str, params: &ResizeParams) -> String {
+        format!(
+            "{}-{}.{}",
+            Self::stem(key),
+            Self::resized_variant(params),
+            params.format.extension()
+        )
+    }
+
+    /// Box and fit of resized image, like `w100-h50-inside`
+    fn resized_variant(params: &ResizeParams) -> String {
+        let mut variant = String::new();
+        if let Some(width) = params.width {
+            variant.push_str(&format!("w{}-", width));
+        }
+        if let Some(height) = params.height {
+            variant.push_str(&format!("h{}-", height));
+        }
+        format!("{}{}", variant, params.fit)
+    }
+
+    /// Filename without extension, like `img-dsf` for `img-dsf.png`
+    fn stem(key: &str) -> &str {
+        match key.rfind('.') {
+            Some(index) => &key[..index],
+            None => key,
+        }
+    }
+
+    /// Key of uploaded image, i.e. `key_template` with all placeholders filled and without `{variant}`,
+    /// which is appended to it for objects of the image
+    fn create_image_key(&self, prefix: &str, owner: Option<i32>) -> String {
+        let now = Utc::now();
+        let user_id = owner
+            .map(|owner| owner.to_string())
+            .unwrap_or_else(|| ANONYMOUS_USER_ID.to_string());
+        self.key_template
+            .trim_right_matches("{variant}")
+            .replace("{prefix}", prefix)
+            .replace("{user_id}", &user_id)
+            .replace("{yyyy}", &now.format("%Y").to_string())
+            .replace("{mm}", &now.format("%m").to_string())
+            .replace("{dd}", &now.format("%d").to_string())
+            .replace("{id}", &self.random.generate_hash())
+    }
+
+    fn create_aws_name(image_key: &str, image_type: &str, size: &ImageSize) -> String {
+        match size {
+            &ImageSize::Original => Self::create_variant_name(image_key, "", image_type), // don't use postfix if this is original image
+            _ => Self::create_variant_name(image_key, &size.to_string(), image_type),
+        }
+    }
+
+    /// Extension of original image. Jpeg originals keep `png` extension they had before
+    /// they were uploaded as is, so that their urls don't change for existing clients.
+    fn original_extension(format: &OutputFormat) -> &'static str {
+        match format {
+            &OutputFormat::Jpeg => OutputFormat::Png.extension(),
+            format => format.extension(),
+        }
+    }
+
+    /// Name of a high density variant, like `img-dsf-medium@2x.png`
+    fn create_dense_aws_name(image_key: &str, image_type: &str, variant: &DenseVariant) -> String {
+        Self::create_variant_name(image_key, &variant.to_string(), image_type)
+    }
+
+    /// Name of an object of uploaded image, like `img-dsf-small.png`, `variant` is empty for original image
+    fn create_variant_name(image_key: &str, variant: &str, image_type: &str) -> String {
+        if variant.is_empty() {
+            format!("{}.{}", image_key, image_type)
+        } else {
+            format!("{}-{}.{}", image_key, variant, image_type)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::client::{S3Object, S3ObjectInfo};
+    use super::preprocessors::ProcessedImage;
+    use super::types::{Color, Colors, Fit, OutputFormat, Placeholder, Resampling, Rotation};
     use super::*;
     use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
@@ -146,17 +974,35 @@ mod tests {
 
     struct ImageMock<'a> {
         _cpu_pool: &'a CpuPool,
+        /// Original is watermarked and has an unmarked variant
+        watermarked: bool,
     }
 
     impl<'a> ImageMock<'a> {
         pub fn new(cpu_pool: &'a CpuPool) -> Self {
-            Self { _cpu_pool: cpu_pool }
+            Self {
+                _cpu_pool: cpu_pool,
+                watermarked: false,
+            }
+        }
+
+        pub fn watermarked(cpu_pool: &'a CpuPool) -> Self {
+            Self {
+                _cpu_pool: cpu_pool,
+                watermarked: true,
+            }
         }
     }
 
     impl<'a> Image for ImageMock<'a> {
-        fn process(&self, _format: ImageFormat, _bytes: Vec<u8>) -> Box<Future<Item = HashMap<ImageSize, Vec<u8>>, Error = S3Error>> {
-            let result = vec![
+        fn process(
+            &self,
+            format: InputFormat,
+            _bytes: Vec<u8>,
+            options: ProcessOptions,
+        ) -> Box<Future<Item = ProcessedImage, Error = S3Error>> {
+            let animated = options.animate && format == InputFormat::Gif;
+            let variants = vec![
                 (ImageSize::Thumb, "thumb"),
                 (ImageSize::Small, "small"),
                 (ImageSize::Medium, "medium"),
@@ -164,15 +1010,107 @@ mod tests {
                 (ImageSize::Original, "original"),
             ]
             .into_iter()
-            .map(|(size, s)| (size, s.as_bytes().to_vec()))
+            .map(|(size, s)| {
+                let format = if animated && size != ImageSize::Thumb {
+                    OutputFormat::Gif
+                } else if size == ImageSize::Original && format == InputFormat::Jpeg {
+                    OutputFormat::Jpeg
+                } else {
+                    OutputFormat::Png
+                };
+                (
+                    size,
+                    EncodedImage {
+                        format,
+                        bytes: s.as_bytes().to_vec(),
+                        width: 100,
+                        height: 50,
+                    },
+                )
+            })
             .collect::<HashMap<_, _>>();
-            Box::new(future::ok(result))
+            let dense_variants = options
+                .densities
+                .iter()
+                .map(|&density| {
+                    (
+                        DenseVariant {
+                            size: ImageSize::Medium,
+                            density,
+                        },
+                        EncodedImage {
+                            format: OutputFormat::Png,
+                            bytes: format!("medium@{}x", density).into_bytes(),
+                            width: 320 * density,
+                            height: 160 * density,
+                        },
+                    )
+                })
+                .collect::<HashMap<_, _>>();
+            Box::new(future::ok(ProcessedImage {
+                variants,
+                dense_variants,
+                placeholder: placeholder_mock(),
+                colors: colors_mock(),
+                focal_point: FocalPoint::center(),
+                source: if options.rotation.is_some() && options.keep_source {
+                    Some(b"source".to_vec())
+                } else {
+                    None
+                },
+                unmarked: if self.watermarked {
+                    Some(EncodedImage {
+                        format: OutputFormat::Png,
+                        bytes: b"unmarked".to_vec(),
+                        width: 100,
+                        height: 50,
+                    })
+                } else {
+                    None
+                },
+            }))
+        }
+
+        fn resize(&self, bytes: Vec<u8>, params: ResizeParams, watermark: bool) -> Box<Future<Item = EncodedImage, Error = S3Error>> {
+            let mut resized = b"resized-".to_vec();
+            resized.extend(bytes);
+            if watermark {
+                resized.extend(b"-marked");
+            }
+            if let Some(focal_point) = params.focal_point {
+                resized.extend(format!("@{}", focal_point).into_bytes());
+            }
+            Box::new(future::ok(EncodedImage {
+                format: params.format,
+                bytes: resized,
+                width: params.width.unwrap_or(0),
+                height: params.height.unwrap_or(0),
+            }))
+        }
+    }
+
+    fn placeholder_mock() -> Placeholder {
+        Placeholder {
+            blurhash: "00TSUA".to_string(),
+            lqip: "data:image/png;base64,".to_string(),
+        }
+    }
+
+    fn colors_mock() -> Colors {
+        Colors {
+            dominant_color: "#ff0000".to_string(),
+            palette: vec!["#ff0000".to_string(), "#0000ff".to_string()],
         }
     }
 
     #[derive(Default)]
     struct S3ClientMock {
         pub uploads: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        pub metadata: Arc<Mutex<HashMap<String, HashMap<String, String>>>>,
+        pub options: Arc<Mutex<HashMap<String, UploadOptions>>>,
+        pub tags: Arc<Mutex<HashMap<String, HashMap<String, String>>>>,
+        /// Uploads of this key fail with a network error
+        pub failing_key: Option<String>,
     }
 
     impl S3Client for S3ClientMock {
@@ -181,10 +1119,61 @@ mod tests {
             _bucket: String,
             key: String,
             _content_type: Option<String>,
-            bytes: Vec<u8>,
+            metadata: HashMap<String, String>,
+            tags: HashMap<String, String>,
+            bytes: Rc<Vec<u8>>,
+            _content_md5: String,
+            options: UploadOptions,
         ) -> Box<Future<Item = (), Error = S3Error>> {
+            if self.failing_key.as_ref() == Some(&key) {
+                return S3Error::Network(format!("Connection reset while uploading {}", key)).into();
+            }
+            self.metadata.lock().unwrap().insert(key.clone(), metadata);
+            self.options.lock().unwrap().insert(key.clone(), options);
+            self.tags.lock().unwrap().insert(key.clone(), tags);
             let mut uploads = self.uploads.lock().unwrap();
-            uploads.insert(key, bytes);
+            uploads.insert(key, bytes.to_vec());
+            Box::new(future::ok(()))
+        }
+
+        fn exists(&self, _bucket: String, key: String) -> Box<Future<Item = bool, Error = S3Error>> {
+            Box::new(future::ok(self.uploads.lock().unwrap().contains_key(&key)))
+        }
+
+        fn download(&self, _bucket: String, key: String) -> Box<Future<Item = S3Object, Error = S3Error>> {
+            match self.uploads.lock().unwrap().get(&key) {
+                Some(bytes) => Box::new(future::ok(S3Object {
+                    bytes: bytes.clone(),
+                    metadata: self.metadata.lock().unwrap().get(&key).cloned().unwrap_or_default(),
+                })),
+                None => S3Error::NotFound(key).into(),
+            }
+        }
+
+        fn head(&self, _bucket: String, key: String) -> Box<Future<Item = S3ObjectInfo, Error = S3Error>> {
+            match self.uploads.lock().unwrap().get(&key) {
+                Some(bytes) => Box::new(future::ok(S3ObjectInfo {
+                    size: Some(bytes.len() as u64),
+                    content_type: None,
+                    last_modified: None,
+                    metadata: self.metadata.lock().unwrap().get(&key).cloned().unwrap_or_default(),
+                })),
+                None => S3Error::NotFound(key).into(),
+            }
+        }
+
+        fn presigned_url(&self, bucket: String, key: String, expires_in: Duration) -> Box<Future<Item = String, Error = S3Error>> {
+            Box::new(future::ok(format!(
+                "https://{}.s3.amazonaws.com/{}?X-Amz-Expires={}",
+                bucket,
+                key,
+                expires_in.as_secs()
+            )))
+        }
+
+        fn delete(&self, _bucket: String, key: String) -> Box<Future<Item = (), Error = S3Error>> {
+            self.uploads.lock().unwrap().remove(&key);
+            self.metadata.lock().unwrap().remove(&key);
             Box::new(future::ok(()))
         }
     }
@@ -194,6 +1183,7 @@ mod tests {
         let random = RandomMock::new("somehash");
         let client = S3ClientMock::default();
         let uploads = client.uploads.clone();
+        let metadata = client.metadata.clone();
         let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
             Box::new(ImageMock::new(cpu_pool))
         });
@@ -208,8 +1198,613 @@ mod tests {
         .map(|(file, size)| (file.to_string(), size.as_bytes().to_vec()))
         .collect::<HashMap<_, _>>();
 
-        let url = s3.upload_image(ImageFormat::PNG, b"".to_vec()).wait().unwrap();
-        assert_eq!(url, "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash.png");
+        let image = s3
+            .upload_image(InputFormat::Png, b"".to_vec(), ProcessOptions::default())
+            .wait()
+            .unwrap();
+        assert_eq!(image.url, "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash.png");
+        assert_eq!(image.source_url, None);
+        assert_eq!(image.placeholder, placeholder_mock());
+        assert_eq!(image.colors, colors_mock());
         assert_eq!(&*uploads.lock().unwrap(), &expected_uploads);
+        let metadata = metadata.lock().unwrap();
+        assert_eq!(metadata.len(), 5);
+        for (name, object_metadata) in metadata.iter() {
+            assert_eq!(object_metadata["dominant-color"], "#ff0000");
+            assert_eq!(object_metadata["palette"], "#ff0000,#0000ff");
+            assert_eq!(object_metadata["focal-point"], "50.00%,50.00%");
+            assert_eq!(object_metadata["sha256"], image.checksums[name].sha256);
+        }
+        assert_eq!(image.checksums.len(), 5);
+        assert_eq!(image.checksums["img-somehash-thumb.png"], Checksums::compute(b"thumb"));
+    }
+
+    #[test]
+    fn test_upload_image_keep_source() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploads = client.uploads.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let options = ProcessOptions {
+            rotation: Some(Rotation::Rotate90),
+            keep_source: true,
+            ..Default::default()
+        };
+
+        let image = s3.upload_image(InputFormat::Jpeg, b"".to_vec(), options).wait().unwrap();
+        assert_eq!(
+            image.source_url,
+            Some("https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-source.jpg".to_string())
+        );
+        assert_eq!(uploads.lock().unwrap()["img-somehash-source.jpg"], b"source".to_vec());
+        assert_eq!(image.url, "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash.png");
+        assert_eq!(uploads.lock().unwrap()["img-somehash.png"], b"original".to_vec());
+    }
+
+    #[test]
+    fn test_upload_watermarked_image() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploads = client.uploads.clone();
+        let metadata = client.metadata.clone();
+        let options = client.options.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::watermarked(cpu_pool))
+        });
+
+        let image = s3
+            .upload_image(InputFormat::Png, b"".to_vec(), ProcessOptions::default())
+            .wait()
+            .unwrap();
+        assert_eq!(uploads.lock().unwrap()["img-somehash-unmarked.png"], b"unmarked".to_vec());
+        assert_eq!(
+            options.lock().unwrap()["img-somehash-unmarked.png"].acl,
+            Some("private".to_string())
+        );
+        assert!(!image.srcset.values().any(|srcset| srcset.contains("unmarked")));
+        {
+            let metadata = metadata.lock().unwrap();
+            assert_eq!(metadata["img-somehash.png"]["unmarked"], "unmarked.png");
+            assert!(!metadata["img-somehash.png"]["variants"].contains("unmarked"));
+            assert!(!metadata["img-somehash-large.png"].contains_key("unmarked"));
+        }
+        match s3.image_info("img-somehash-unmarked.png", None).wait() {
+            Err(S3Error::Forbidden(_)) => (),
+            _ => panic!("expected forbidden error"),
+        }
+
+        // resized copies are made from the unmarked variant and watermarked
+        let params = ResizeParams {
+            width: Some(100),
+            height: None,
+            fit: Fit::Inside,
+            background: Color::default(),
+            focal_point: None,
+            format: OutputFormat::Png,
+            embed_profile: false,
+            optimize_png: None,
+            resampling: Resampling::default(),
+        };
+        s3.resize_image("img-somehash.png", params, UploadOptions::default(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            uploads.lock().unwrap()["img-somehash-w100-inside.png"],
+            b"resized-unmarked-marked".to_vec()
+        );
+        assert_eq!(options.lock().unwrap()["img-somehash-w100-inside.png"].acl, None);
+    }
+
+    #[test]
+    fn test_upload_image_densities() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploads = client.uploads.clone();
+        let metadata = client.metadata.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let options = ProcessOptions {
+            densities: vec![3, 2],
+            ..Default::default()
+        };
+
+        let image = s3.upload_image(InputFormat::Png, b"".to_vec(), options).wait().unwrap();
+        assert_eq!(uploads.lock().unwrap()["img-somehash-medium@2x.png"], b"medium@2x".to_vec());
+        // every variant has its own dimensions
+        assert_eq!(metadata.lock().unwrap()["img-somehash-medium@2x.png"]["width"], "640");
+        assert_eq!(metadata.lock().unwrap()["img-somehash-medium@3x.png"]["height"], "480");
+        assert_eq!(
+            image.srcset["medium"],
+            "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-medium.png 1x, \
+             https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-medium@2x.png 2x, \
+             https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-medium@3x.png 3x"
+        );
+        assert_eq!(
+            image.srcset["large"],
+            "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-large.png 1x"
+        );
+        assert!(!image.srcset.contains_key("original"));
+    }
+
+    #[test]
+    fn test_fit_metadata() {
+        let mut metadata = HashMap::new();
+        metadata.insert("lqip".to_string(), "a".repeat(1500));
+        metadata.insert("palette".to_string(), "b".repeat(600));
+        metadata.insert("variants".to_string(), "c".repeat(100));
+        metadata.insert("owner".to_string(), "42".to_string());
+        S3::fit_metadata("img-somehash.png", &mut metadata);
+        assert!(!metadata.contains_key("lqip"));
+        assert_eq!(metadata.len(), 3);
+
+        metadata.insert("blurhash".to_string(), "d".repeat(1400));
+        S3::fit_metadata("img-somehash.png", &mut metadata);
+        assert!(!metadata.contains_key("palette"));
+        assert!(metadata.contains_key("blurhash"));
+        assert_eq!(metadata["owner"], "42");
+    }
+
+    #[test]
+    fn test_upload_image_options() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploaded_options = client.options.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let upload = UploadOptions {
+            cache_control: Some("public, max-age=31536000, immutable".to_string()),
+            ..Default::default()
+        };
+        let mut preset_uploads = HashMap::new();
+        preset_uploads.insert(
+            ImageSize::Medium,
+            UploadOptions {
+                storage_class: Some("STANDARD_IA".to_string()),
+                ..Default::default()
+            },
+        );
+        let options = ProcessOptions {
+            densities: vec![2],
+            upload: upload.clone(),
+            preset_uploads,
+            ..Default::default()
+        };
+
+        s3.upload_image(InputFormat::Png, b"".to_vec(), options).wait().unwrap();
+        let uploaded_options = uploaded_options.lock().unwrap();
+        assert_eq!(uploaded_options["img-somehash.png"], upload);
+        assert_eq!(uploaded_options["img-somehash-small.png"], upload);
+        let medium = UploadOptions {
+            storage_class: Some("STANDARD_IA".to_string()),
+            ..upload
+        };
+        assert_eq!(uploaded_options["img-somehash-medium.png"], medium);
+        assert_eq!(uploaded_options["img-somehash-medium@2x.png"], medium);
+    }
+
+    #[test]
+    fn test_upload_private_image() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploaded_options = client.options.clone();
+        let uploaded_metadata = client.metadata.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let mut preset_uploads = HashMap::new();
+        preset_uploads.insert(
+            ImageSize::Thumb,
+            UploadOptions {
+                acl: Some("public-read".to_string()),
+                ..Default::default()
+            },
+        );
+        let options = ProcessOptions {
+            preset_uploads,
+            private: true,
+            owner: Some(42),
+            ..Default::default()
+        };
+
+        s3.upload_image(InputFormat::Png, b"".to_vec(), options).wait().unwrap();
+        for (key, options) in uploaded_options.lock().unwrap().iter() {
+            assert_eq!(options.acl, Some("private".to_string()), "{} is not private", key);
+        }
+        assert_eq!(uploaded_metadata.lock().unwrap()["img-somehash-thumb.png"]["owner"], "42");
+
+        let signed = s3
+            .signed_url("img-somehash-thumb.png", 42, false, Duration::from_secs(60))
+            .wait()
+            .unwrap();
+        assert_eq!(
+            signed.url,
+            "https://test-bucket.s3.amazonaws.com/img-somehash-thumb.png?X-Amz-Expires=60"
+        );
+        assert!(s3.signed_url("img-somehash.png", 7, true, Duration::from_secs(60)).wait().is_ok());
+        match s3.signed_url("img-somehash.png", 7, false, Duration::from_secs(60)).wait() {
+            Err(S3Error::Forbidden(key)) => assert_eq!(key, "img-somehash.png"),
+            _ => panic!("expected forbidden error"),
+        }
+
+        let params = ResizeParams {
+            width: Some(100),
+            height: None,
+            fit: Fit::Inside,
+            background: Color::default(),
+            focal_point: None,
+            format: OutputFormat::Png,
+            embed_profile: false,
+            optimize_png: None,
+            resampling: Resampling::default(),
+        };
+        let owner = Requester { user_id: 42, admin: false };
+        let stranger = Requester { user_id: 7, admin: false };
+        match s3
+            .resize_image("img-somehash.png", params.clone(), UploadOptions::default(), None)
+            .wait()
+        {
+            Err(S3Error::Forbidden(key)) => assert_eq!(key, "img-somehash.png"),
+            _ => panic!("expected forbidden error"),
+        }
+        s3.resize_image("img-somehash.png", params.clone(), UploadOptions::default(), Some(owner))
+            .wait()
+            .unwrap();
+        assert_eq!(
+            uploaded_options.lock().unwrap()["img-somehash-w100-inside.png"].acl,
+            Some("private".to_string())
+        );
+        assert_eq!(uploaded_metadata.lock().unwrap()["img-somehash-w100-inside.png"]["owner"], "42");
+        // cached copy is private too
+        match s3
+            .resize_image("img-somehash.png", params, UploadOptions::default(), Some(stranger))
+            .wait()
+        {
+            Err(S3Error::Forbidden(key)) => assert_eq!(key, "img-somehash-w100-inside.png"),
+            _ => panic!("expected forbidden error"),
+        }
+
+        match s3.image_info("img-somehash.png", None).wait() {
+            Err(S3Error::Forbidden(key)) => assert_eq!(key, "img-somehash.png"),
+            _ => panic!("expected forbidden error"),
+        }
+        assert!(s3.image_info("img-somehash.png", Some(owner)).wait().is_ok());
+        let admin = Requester { admin: true, ..stranger };
+        assert!(s3.image_info("img-somehash.png", Some(admin)).wait().is_ok());
+    }
+
+    #[test]
+    fn test_upload_image_key_template() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploads = client.uploads.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        })
+        .with_key_template("{prefix}/{yyyy}/{mm}/{dd}/{user_id}/{id}{variant}".to_string());
+        let options = ProcessOptions {
+            densities: vec![2],
+            owner: Some(42),
+            ..Default::default()
+        };
+
+        let image_key = || format!("img/{}/42/somehash", Utc::now().format("%Y/%m/%d"));
+        let key_before = image_key();
+        let image = s3.upload_image(InputFormat::Png, b"".to_vec(), options).wait().unwrap();
+        // the date may change during the upload
+        let image_key = [key_before, image_key()]
+            .iter()
+            .find(|key| image.url == format!("https://s3.us-east-1.amazonaws.com/test-bucket/{}.png", key))
+            .cloned()
+            .unwrap_or_else(|| panic!("unexpected url {}", image.url));
+        {
+            let uploads = uploads.lock().unwrap();
+            assert!(uploads.contains_key(&format!("{}-thumb.png", image_key)));
+            assert!(uploads.contains_key(&format!("{}-medium@2x.png", image_key)));
+        }
+
+        let params = ResizeParams {
+            width: Some(100),
+            height: None,
+            fit: Fit::Inside,
+            background: Color::default(),
+            focal_point: None,
+            format: OutputFormat::Png,
+            embed_profile: false,
+            optimize_png: None,
+            resampling: Resampling::default(),
+        };
+        let url = s3
+            .resize_image(&format!("{}.png", image_key), params, UploadOptions::default(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            url,
+            format!("https://s3.us-east-1.amazonaws.com/test-bucket/{}-w100-inside.png", image_key)
+        );
+    }
+
+    #[test]
+    fn test_upload_image_tags() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploaded_tags = client.tags.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let options = ProcessOptions {
+            densities: vec![2],
+            owner: Some(42),
+            filename: Some("my photo (1).png".to_string()),
+            ..Default::default()
+        };
+
+        s3.upload_avatar(InputFormat::Png, b"".to_vec(), options).wait().unwrap();
+        {
+            let uploaded_tags = uploaded_tags.lock().unwrap();
+            let thumb = &uploaded_tags["avatar-somehash-thumb.png"];
+            assert_eq!(thumb["uploader"], "42");
+            assert_eq!(thumb["route"], "avatars");
+            assert_eq!(thumb["variant"], "thumb");
+            assert_eq!(thumb["filename"], "my photo _1_.png");
+            assert_eq!(uploaded_tags["avatar-somehash-medium@2x.png"]["variant"], "medium@2x");
+            assert_eq!(uploaded_tags["avatar-somehash.png"]["variant"], "original");
+        }
+
+        let params = ResizeParams {
+            width: Some(100),
+            height: Some(50),
+            fit: Fit::Cover,
+            background: Color::default(),
+            focal_point: None,
+            format: OutputFormat::Png,
+            embed_profile: false,
+            optimize_png: None,
+            resampling: Resampling::default(),
+        };
+        s3.resize_image("avatar-somehash.png", params, UploadOptions::default(), None)
+            .wait()
+            .unwrap();
+        let uploaded_tags = uploaded_tags.lock().unwrap();
+        let resized = &uploaded_tags["avatar-somehash-w100-h50-cover.png"];
+        assert_eq!(resized["uploader"], "42");
+        assert_eq!(resized["route"], "resized");
+        assert_eq!(resized["variant"], "w100-h50-cover");
+        assert!(!resized.contains_key("filename"));
+    }
+
+    #[test]
+    fn test_upload_image_rollback() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock {
+            failing_key: Some("img-somehash-large.png".to_string()),
+            ..Default::default()
+        };
+        let uploads = client.uploads.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+
+        match s3.upload_image(InputFormat::Png, b"".to_vec(), ProcessOptions::default()).wait() {
+            Err(S3Error::RolledBack {
+                cause,
+                mut deleted,
+                not_deleted,
+            }) => {
+                match *cause {
+                    S3Error::Network(_) => (),
+                    _ => panic!("expected network error"),
+                }
+                deleted.sort();
+                assert_eq!(
+                    deleted,
+                    vec![
+                        "img-somehash-medium.png",
+                        "img-somehash-small.png",
+                        "img-somehash-thumb.png",
+                        "img-somehash.png",
+                    ]
+                );
+                assert!(not_deleted.is_empty());
+            }
+            _ => panic!("expected rolled back upload"),
+        }
+        assert!(uploads.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_image_info() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let options = ProcessOptions {
+            densities: vec![2],
+            ..Default::default()
+        };
+        s3.upload_image(InputFormat::Png, b"".to_vec(), options).wait().unwrap();
+
+        let info = s3.image_info("img-somehash.png", None).wait().unwrap();
+        assert_eq!(info.url, "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash.png");
+        assert_eq!((info.width, info.height), (Some(100), Some(50)));
+        assert_eq!(info.format, "png");
+        assert_eq!(info.size, Some(b"original".len() as u64));
+        assert_eq!(info.variants.len(), 5);
+        assert_eq!(
+            info.variants["medium@2x"],
+            "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-medium@2x.png"
+        );
+        assert_eq!(info.blurhash, Some("00TSUA".to_string()));
+        assert_eq!(info.lqip, Some("data:image/png;base64,".to_string()));
+        assert!(info.uploaded_at.is_some());
+        match s3.image_info("img-otherhash.png", None).wait() {
+            Err(S3Error::NotFound(key)) => assert_eq!(key, "img-otherhash.png"),
+            _ => panic!("expected not found error"),
+        }
+    }
+
+    #[test]
+    fn test_image_info_key_template() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        })
+        .with_key_template("{prefix}/{user_id}/{id}{variant}".to_string());
+        let options = ProcessOptions {
+            densities: vec![2],
+            owner: Some(42),
+            ..Default::default()
+        };
+        s3.upload_image(InputFormat::Png, b"".to_vec(), options).wait().unwrap();
+
+        let info = s3.image_info("img/42/somehash.png", None).wait().unwrap();
+        assert_eq!(info.url, "https://s3.us-east-1.amazonaws.com/test-bucket/img/42/somehash.png");
+        assert_eq!(info.variants.len(), 5);
+        assert_eq!(
+            info.variants["thumb"],
+            "https://s3.us-east-1.amazonaws.com/test-bucket/img/42/somehash-thumb.png"
+        );
+        assert_eq!(
+            info.variants["medium@2x"],
+            "https://s3.us-east-1.amazonaws.com/test-bucket/img/42/somehash-medium@2x.png"
+        );
+    }
+
+    #[test]
+    fn test_upload_avatar() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploads = client.uploads.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+
+        let image = s3
+            .upload_avatar(InputFormat::Png, b"".to_vec(), ProcessOptions::default())
+            .wait()
+            .unwrap();
+        assert_eq!(image.url, "https://s3.us-east-1.amazonaws.com/test-bucket/avatar-somehash.png");
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(uploads.len(), 5);
+        assert!(uploads.contains_key("avatar-somehash-thumb.png"));
+    }
+
+    #[test]
+    fn test_resize_image() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploads = client.uploads.clone();
+        let metadata = client.metadata.clone();
+        uploads.lock().unwrap().insert("img-somehash.png".to_string(), b"original".to_vec());
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let params = ResizeParams {
+            width: Some(100),
+            height: None,
+            fit: Fit::Inside,
+            background: Color::default(),
+            focal_point: None,
+            format: OutputFormat::Jpeg,
+            embed_profile: false,
+            optimize_png: None,
+            resampling: Resampling::default(),
+        };
+
+        let url = s3
+            .resize_image("img-somehash.png", params.clone(), UploadOptions::default(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(url, "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-w100-inside.jpg");
+        assert_eq!(
+            uploads.lock().unwrap()["img-somehash-w100-inside.jpg"],
+            b"resized-original".to_vec()
+        );
+        {
+            let metadata = &metadata.lock().unwrap()["img-somehash-w100-inside.jpg"];
+            assert_eq!(metadata["width"], "100");
+            assert_eq!(metadata["height"], "0");
+            assert_eq!(metadata["format"], "jpg");
+        }
+
+        // cached image is served as is
+        uploads
+            .lock()
+            .unwrap()
+            .insert("img-somehash-w100-inside.jpg".to_string(), b"cached".to_vec());
+        let url = s3
+            .resize_image("img-somehash.png", params.clone(), UploadOptions::default(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(url, "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-w100-inside.jpg");
+        assert_eq!(uploads.lock().unwrap()["img-somehash-w100-inside.jpg"], b"cached".to_vec());
+
+        match s3.resize_image("img-otherhash.png", params, UploadOptions::default(), None).wait() {
+            Err(S3Error::NotFound(_)) => (),
+            res => assert!(false, format!("Expected error S3Error::NotFound, found {:?}", res)),
+        }
+    }
+
+    #[test]
+    fn test_resize_image_smart() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploads = client.uploads.clone();
+        uploads.lock().unwrap().insert("img-somehash.png".to_string(), b"original".to_vec());
+        let mut metadata = HashMap::new();
+        metadata.insert("focal-point".to_string(), "25.00%,75.00%".to_string());
+        client.metadata.lock().unwrap().insert("img-somehash.png".to_string(), metadata);
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let params = ResizeParams {
+            width: Some(100),
+            height: Some(100),
+            fit: Fit::Smart,
+            background: Color::default(),
+            focal_point: None,
+            format: OutputFormat::Png,
+            embed_profile: false,
+            optimize_png: None,
+            resampling: Resampling::default(),
+        };
+
+        let url = s3
+            .resize_image("img-somehash.png", params, UploadOptions::default(), None)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            url,
+            "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash-w100-h100-smart.png"
+        );
+        assert_eq!(
+            uploads.lock().unwrap()["img-somehash-w100-h100-smart.png"],
+            b"resized-original@25.00%,75.00%".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_upload_animated_image() {
+        let random = RandomMock::new("somehash");
+        let client = S3ClientMock::default();
+        let uploads = client.uploads.clone();
+        let s3 = S3::new(Region::UsEast1, "test-bucket", Box::new(client), Box::new(random), |cpu_pool| {
+            Box::new(ImageMock::new(cpu_pool))
+        });
+        let options = ProcessOptions {
+            animate: true,
+            ..Default::default()
+        };
+
+        let image = s3.upload_image(InputFormat::Gif, b"".to_vec(), options).wait().unwrap();
+        assert_eq!(image.url, "https://s3.us-east-1.amazonaws.com/test-bucket/img-somehash.gif");
+        let uploads = uploads.lock().unwrap();
+        assert!(uploads.contains_key("img-somehash-thumb.png"));
+        assert!(uploads.contains_key("img-somehash-large.gif"));
     }
 }
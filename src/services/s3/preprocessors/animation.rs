@@ -0,0 +1,166 @@
+//! Decoding and encoding of animated GIFs frame by frame
+
+use gif;
+use gif::SetParameter;
+use image::{imageops, RgbaImage};
+
+use super::super::error::S3Error;
+
+/// Max width and height of GIF images
+static MAX_DIMENSION: u32 = 65_535;
+/// Max number of pixels of all decoded frames, i.e. 200 MB of RGBA canvases
+pub static MAX_PIXELS: u64 = 50_000_000;
+/// Speed of NeuQuant palette quantization of encoded frames, 1 is the slowest and 30 the fastest
+static QUANTIZATION_SPEED: i32 = 10;
+
+/// Frame of an animation
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// Whole canvas of the animation at this frame
+    pub image: RgbaImage,
+    /// Delay before the next frame in hundredths of a second
+    pub delay: u16,
+}
+
+/// Decodes all frames of a GIF. Frames are composed onto the canvas according to their
+/// disposal methods, so every frame is a complete image of canvas size.
+///
+/// #Errors
+/// * `S3Error::Image` if GIF can't be decoded, or its frames have more than `max_pixels` pixels in total.
+/// The limit is checked before any frame is composed.
+pub fn decode(bytes: &[u8], max_pixels: u64) -> Result<Vec<Frame>, S3Error> {
+    let (width, height, count) = frame_count(bytes)?;
+    if u64::from(width) * u64::from(height) * count > max_pixels {
+        return Err(S3Error::Image(format!(
+            "Animation {}x{} with {} frames is too large",
+            width, height, count
+        )));
+    }
+    let mut decoder = gif::Decoder::new(bytes);
+    decoder.set(gif::ColorOutput::RGBA);
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| S3Error::Image(format!("Error parsing gif: {}", e)))?;
+    let mut canvas = RgbaImage::new(width, height);
+    let mut frames = Vec::new();
+    while let Some(frame) = reader
+        .read_next_frame()
+        .map_err(|e| S3Error::Image(format!("Error parsing gif frame: {}", e)))?
+    {
+        let previous = canvas.clone();
+        let patch = RgbaImage::from_raw(u32::from(frame.width), u32::from(frame.height), frame.buffer.to_vec())
+            .ok_or_else(|| S3Error::Image("Gif frame is smaller than its dimensions".to_string()))?;
+        imageops::overlay(&mut canvas, &patch, u32::from(frame.left), u32::from(frame.top));
+        frames.push(Frame {
+            image: canvas.clone(),
+            delay: frame.delay,
+        });
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                let cleared = RgbaImage::new(u32::from(frame.width), u32::from(frame.height));
+                imageops::replace(&mut canvas, &cleared, u32::from(frame.left), u32::from(frame.top));
+            }
+            gif::DisposalMethod::Previous => canvas = previous,
+            gif::DisposalMethod::Any | gif::DisposalMethod::Keep => (),
+        }
+    }
+    Ok(frames)
+}
+
+/// Canvas dimensions and number of frames of a GIF, read without composing or keeping pixels of the frames
+fn frame_count(bytes: &[u8]) -> Result<(u32, u32, u64), S3Error> {
+    let mut reader = gif::Decoder::new(bytes)
+        .read_info()
+        .map_err(|e| S3Error::Image(format!("Error parsing gif: {}", e)))?;
+    let mut count = 0;
+    while reader
+        .next_frame_info()
+        .map_err(|e| S3Error::Image(format!("Error parsing gif frame: {}", e)))?
+        .is_some()
+    {
+        count += 1;
+    }
+    Ok((u32::from(reader.width()), u32::from(reader.height()), count))
+}
+
+/// Encodes frames as a looped GIF. All frames must have the same dimensions.
+///
+/// #Errors
+/// * `S3Error::Image` if there are no frames or they are too large for GIF
+pub fn encode(frames: Vec<Frame>) -> Result<Vec<u8>, S3Error> {
+    let (width, height) = match frames.first() {
+        Some(frame) => frame.image.dimensions(),
+        None => return Err(S3Error::Image("Animation has no frames".to_string())),
+    };
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(S3Error::Image(format!("Image {}x{} is too large for gif", width, height)));
+    }
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut buffer, width as u16, height as u16, &[])
+            .map_err(|e| S3Error::Image(format!("Error encoding gif: {}", e)))?;
+        if frames.len() > 1 {
+            encoder
+                .set(gif::Repeat::Infinite)
+                .map_err(|e| S3Error::Image(format!("Error encoding gif: {}", e)))?;
+        }
+        for frame in frames {
+            let mut pixels = frame.image.into_raw();
+            let mut gif_frame = gif::Frame::from_rgba_speed(width as u16, height as u16, &mut pixels, QUANTIZATION_SPEED);
+            gif_frame.delay = frame.delay;
+            encoder
+                .write_frame(&gif_frame)
+                .map_err(|e| S3Error::Image(format!("Error encoding gif frame: {}", e)))?;
+        }
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_encode_decode() {
+        let frames = vec![
+            Frame {
+                image: RgbaImage::from_pixel(8, 4, Rgba { data: [255, 0, 0, 255] }),
+                delay: 10,
+            },
+            Frame {
+                image: RgbaImage::from_pixel(8, 4, Rgba { data: [0, 0, 255, 255] }),
+                delay: 20,
+            },
+        ];
+        let decoded = decode(&encode(frames).unwrap(), MAX_PIXELS).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].image.dimensions(), (8, 4));
+        assert_eq!(decoded[0].delay, 10);
+        assert_eq!(decoded[1].delay, 20);
+        assert_eq!(decoded[0].image.get_pixel(0, 0).data, [255, 0, 0, 255]);
+        assert_eq!(decoded[1].image.get_pixel(7, 3).data, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_decode_too_many_pixels() {
+        let frame = Frame {
+            image: RgbaImage::from_pixel(8, 4, Rgba { data: [255, 0, 0, 255] }),
+            delay: 10,
+        };
+        let bytes = encode(vec![frame.clone(), frame.clone(), frame]).unwrap();
+        assert_eq!(decode(&bytes, 96).unwrap().len(), 3);
+        match decode(&bytes, 95) {
+            Err(S3Error::Image(_)) => (),
+            res => assert!(false, format!("Expected error S3Error::Image, found {:?}", res)),
+        }
+    }
+
+    #[test]
+    fn test_encode_no_frames() {
+        match encode(Vec::new()) {
+            Err(S3Error::Image(_)) => (),
+            res => assert!(false, format!("Expected error S3Error::Image, found {:?}", res)),
+        }
+    }
+}
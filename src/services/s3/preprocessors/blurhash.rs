@@ -0,0 +1,130 @@
+//! [BlurHash](https://blurha.sh) encoder. Produces a compact string that frontends
+//! decode into a blurred placeholder while the real image is loading.
+
+use image::RgbaImage;
+use std::f32::consts::PI;
+
+/// Alphabet of the base83 encoding used by BlurHash
+static BASE83_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// Encodes an image into BlurHash string
+///
+/// * `image` - image to encode, should be small (e.g. 32px) since encoding is `O(w * h * components)`
+/// * `x_components` - number of horizontal components, 1 to 9
+/// * `y_components` - number of vertical components, 1 to 9
+pub fn encode(image: &RgbaImage, x_components: u32, y_components: u32) -> String {
+    let (width, height) = image.dimensions();
+    let mut factors: Vec<[f32; 3]> = Vec::with_capacity((x_components * y_components) as usize);
+    for j in 0..y_components {
+        for i in 0..x_components {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0f32; 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let basis =
+                        normalisation * (PI * i as f32 * x as f32 / width as f32).cos() * (PI * j as f32 * y as f32 / height as f32).cos();
+                    let pixel = image.get_pixel(x, y);
+                    factor[0] += basis * srgb_to_linear(pixel.data[0]);
+                    factor[1] += basis * srgb_to_linear(pixel.data[1]);
+                    factor[2] += basis * srgb_to_linear(pixel.data[2]);
+                }
+            }
+            let scale = 1.0 / (width * height) as f32;
+            factors.push([factor[0] * scale, factor[1] * scale, factor[2] * scale]);
+        }
+    }
+
+    let dc = factors[0];
+    let ac = &factors[1..];
+
+    let mut hash = String::new();
+    let size_flag = (x_components - 1) + (y_components - 1) * 9;
+    encode_base83(size_flag, 1, &mut hash);
+
+    let maximum_value = if ac.is_empty() {
+        encode_base83(0, 1, &mut hash);
+        1.0
+    } else {
+        let actual_maximum = ac.iter().flat_map(|f| f.iter()).fold(0.0f32, |acc, v| acc.max(v.abs()));
+        let quantised_maximum = (actual_maximum * 166.0 - 0.5).floor().max(0.0).min(82.0) as u32;
+        encode_base83(quantised_maximum, 1, &mut hash);
+        (quantised_maximum + 1) as f32 / 166.0
+    };
+
+    encode_base83(encode_dc(dc), 4, &mut hash);
+    for factor in ac {
+        encode_base83(encode_ac(*factor, maximum_value), 2, &mut hash);
+    }
+    hash
+}
+
+fn encode_dc(value: [f32; 3]) -> u32 {
+    (linear_to_srgb(value[0]) << 16) + (linear_to_srgb(value[1]) << 8) + linear_to_srgb(value[2])
+}
+
+fn encode_ac(value: [f32; 3], maximum_value: f32) -> u32 {
+    let quantise = |v: f32| (sign_pow(v / maximum_value, 0.5) * 9.0 + 9.5).floor().max(0.0).min(18.0) as u32;
+    quantise(value[0]) * 19 * 19 + quantise(value[1]) * 19 + quantise(value[2])
+}
+
+fn encode_base83(value: u32, length: u32, out: &mut String) {
+    for i in 1..=length {
+        let digit = (value / 83u32.pow(length - i)) % 83;
+        out.push(BASE83_CHARS[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let v = value.max(0.0).min(1.0);
+    if v <= 0.003_130_8 {
+        (v * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * v.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+fn sign_pow(value: f32, exp: f32) -> f32 {
+    let result = value.abs().powf(exp);
+    if value < 0.0 {
+        -result
+    } else {
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn test_encode_solid_color() {
+        let image = ImageBuffer::from_pixel(
+            8,
+            8,
+            Rgba {
+                data: [255, 255, 255, 255],
+            },
+        );
+        assert_eq!(encode(&image, 1, 1), "00TSUA");
+    }
+
+    #[test]
+    fn test_encode_length() {
+        let image = ImageBuffer::from_fn(16, 12, |x, y| Rgba {
+            data: [(x * 16) as u8, (y * 20) as u8, 128, 255],
+        });
+        let hash = encode(&image, 4, 3);
+        assert_eq!(hash.len(), 4 + 2 * 4 * 3);
+        assert!(hash.starts_with("L"));
+    }
+}
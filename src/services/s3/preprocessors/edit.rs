@@ -0,0 +1,75 @@
+//! Client-side edits of uploaded images, like cropping and rotation
+
+use image::DynamicImage;
+use image::GenericImageView;
+
+use super::super::error::S3Error;
+use super::super::types::{Crop, Rotation};
+
+/// Crops and then rotates an image
+///
+/// * `crop` - crop rectangle in source image coordinates
+/// * `rotation` - clockwise rotation
+///
+/// #Errors
+/// * `S3Error::Image` if crop rectangle is empty or out of image bounds
+pub fn apply(image: DynamicImage, crop: Option<&Crop>, rotation: Option<&Rotation>) -> Result<DynamicImage, S3Error> {
+    let image = match crop {
+        Some(crop) => crop_image(image, crop)?,
+        None => image,
+    };
+    Ok(match rotation {
+        Some(&Rotation::Rotate90) => image.rotate90(),
+        Some(&Rotation::Rotate180) => image.rotate180(),
+        Some(&Rotation::Rotate270) => image.rotate270(),
+        None => image,
+    })
+}
+
+fn crop_image(mut image: DynamicImage, crop: &Crop) -> Result<DynamicImage, S3Error> {
+    let (w, h) = image.dimensions();
+    let x = crop.x.to_pixels(w);
+    let y = crop.y.to_pixels(h);
+    let width = crop.width.to_pixels(w);
+    let height = crop.height.to_pixels(h);
+    if width == 0 || height == 0 {
+        return Err(S3Error::Image("Crop rectangle is empty".to_string()));
+    }
+    if u64::from(x) + u64::from(width) > u64::from(w) || u64::from(y) + u64::from(height) > u64::from(h) {
+        return Err(S3Error::Image(format!(
+            "Crop rectangle {}x{}+{}+{} is out of image bounds {}x{}",
+            width, height, x, y, w, h
+        )));
+    }
+    Ok(image.crop(x, y, width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    use super::super::super::types::Length;
+
+    #[test]
+    fn test_apply_crop_and_rotation() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(200, 100));
+        let crop = "10,10,50%,50".parse::<Crop>().unwrap();
+        assert_eq!(crop.width, Length::Percent(50.0));
+        let edited = apply(image, Some(&crop), Some(&Rotation::Rotate90)).unwrap();
+        assert_eq!(edited.dimensions(), (50, 100));
+    }
+
+    #[test]
+    fn test_apply_crop_out_of_bounds() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(200, 100));
+        let crop = "150,0,100,100".parse::<Crop>().unwrap();
+        match apply(image, Some(&crop), None) {
+            Err(S3Error::Image(_)) => (),
+            res => assert!(
+                false,
+                format!("Expected error S3Error::Image, found {:?}", res.map(|i| i.dimensions()))
+            ),
+        }
+    }
+}
@@ -0,0 +1,557 @@
+//! Extracting, converting and embedding ICC colour profiles
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{DynamicImage, Rgb, Rgba};
+use std::io::{Read, Write};
+
+use super::super::error::S3Error;
+use super::super::types::{InputFormat, OutputFormat};
+use super::png;
+use super::png::PNG_SIGNATURE;
+
+/// Signature of JPEG APP2 segments with ICC profile chunks
+static JPEG_ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+/// Max size of profile chunk in a JPEG segment
+static JPEG_ICC_CHUNK_SIZE: usize = 65_519;
+/// Max size of a PNG profile after inflating, larger profiles are ignored
+static MAX_PROFILE_SIZE: usize = 4 * 1024 * 1024;
+/// Size of a lookup table for encoding linear values to sRGB
+static ENCODE_TABLE_SIZE: usize = 4096;
+/// Matrix converting CIE XYZ with D50 white point to linear sRGB (Bradford adaptation)
+static XYZ_TO_SRGB: [[f64; 3]; 3] = [
+    [3.133_856_1, -1.616_866_7, -0.490_614_6],
+    [-0.978_768_4, 1.916_141_5, 0.033_454_0],
+    [0.071_945_3, -0.228_991_4, 1.405_242_7],
+];
+/// Columns of the matrix converting linear sRGB to CIE XYZ with D50 white point
+static SRGB_PRIMARIES: [[f64; 3]; 3] = [
+    [0.436_074_7, 0.222_504_5, 0.013_932_2],
+    [0.385_064_9, 0.716_878_6, 0.097_104_5],
+    [0.143_080_4, 0.060_616_9, 0.714_173_3],
+];
+/// CIE D50 white point of profile connection space
+static D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// Tone reproduction curve of a colour channel
+#[derive(Clone, Debug)]
+enum Curve {
+    Gamma(f64),
+    /// Samples evenly spaced over `[0, 1]`
+    Table(Vec<f64>),
+    /// ICC parametric function, parameters `g, a, b, c, d, e, f`
+    Parametric(u16, [f64; 7]),
+}
+
+impl Curve {
+    /// Maps encoded value in `[0, 1]` to linear one
+    fn linearize(&self, x: f64) -> f64 {
+        match self {
+            &Curve::Gamma(g) => x.powf(g),
+            &Curve::Table(ref table) => {
+                let position = x * (table.len() - 1) as f64;
+                let index = (position.floor() as usize).min(table.len() - 1);
+                let next = (index + 1).min(table.len() - 1);
+                let fraction = position - index as f64;
+                table[index] + (table[next] - table[index]) * fraction
+            }
+            &Curve::Parametric(function, [g, a, b, c, d, e, f]) => match function {
+                0 => x.powf(g),
+                1 if x >= -b / a => (a * x + b).powf(g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(g) + c,
+                2 => c,
+                3 if x >= d => (a * x + b).powf(g),
+                3 => c * x,
+                _ if x >= d => (a * x + b).powf(g) + e,
+                _ => c * x + f,
+            },
+        }
+    }
+}
+
+/// RGB colour profile defined by primaries and tone reproduction curves
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// Matrix converting linear values of the profile to linear sRGB
+    to_srgb: [[f64; 3]; 3],
+    curves: [Curve; 3],
+}
+
+impl Profile {
+    /// Parses matrix-based RGB profile, e.g. Adobe RGB or Display P3
+    ///
+    /// #Errors
+    /// * `S3Error::Image` if profile is malformed, not RGB or based on lookup tables
+    pub fn parse(bytes: &[u8]) -> Result<Self, S3Error> {
+        if bytes.len() < 132 || &bytes[36..40] != b"acsp" {
+            return Err(S3Error::Image("Invalid icc profile".to_string()));
+        }
+        if &bytes[16..20] != b"RGB " {
+            return Err(S3Error::Image("Icc profile is not rgb".to_string()));
+        }
+        // the tag table can't be longer than the profile
+        let count = (read_u32(bytes, 128)? as usize).min((bytes.len() - 132) / 12);
+        let tag = |signature: &[u8]| -> Result<&[u8], S3Error> {
+            (0..count)
+                .map(|i| 132 + i * 12)
+                .find(|&entry| bytes.get(entry..entry + 4) == Some(signature))
+                .ok_or_else(|| S3Error::Image(format!("Icc profile has no {} tag", String::from_utf8_lossy(signature))))
+                .and_then(|entry| {
+                    let offset = read_u32(bytes, entry + 4)? as usize;
+                    let size = read_u32(bytes, entry + 8)? as usize;
+                    bytes
+                        .get(offset..offset + size)
+                        .ok_or_else(|| S3Error::Image("Icc profile tag is out of bounds".to_string()))
+                })
+        };
+
+        let mut primaries = [[0.0; 3]; 3];
+        for (primary, signature) in primaries.iter_mut().zip(&[b"rXYZ", b"gXYZ", b"bXYZ"]) {
+            *primary = parse_xyz(tag(*signature)?)?;
+        }
+        let curves = [
+            parse_curve(tag(b"rTRC")?)?,
+            parse_curve(tag(b"gTRC")?)?,
+            parse_curve(tag(b"bTRC")?)?,
+        ];
+
+        let mut to_srgb = [[0.0; 3]; 3];
+        for (row, xyz_row) in to_srgb.iter_mut().zip(XYZ_TO_SRGB.iter()) {
+            for (value, primary) in row.iter_mut().zip(primaries.iter()) {
+                *value = xyz_row.iter().zip(primary.iter()).map(|(a, b)| a * b).sum();
+            }
+        }
+        Ok(Self { to_srgb, curves })
+    }
+
+    /// Checks if the profile is (close enough to) sRGB, so pixels don't need conversion
+    pub fn is_srgb(&self) -> bool {
+        let identity = self.to_srgb.iter().enumerate().all(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .all(|(j, value)| (value - if i == j { 1.0 } else { 0.0 }).abs() < 0.02)
+        });
+        identity
+            && self.curves.iter().all(|curve| {
+                [0.25, 0.5, 0.75]
+                    .iter()
+                    .all(|&x| (curve.linearize(x) - srgb_linearize(x)).abs() < 0.01)
+            })
+    }
+
+    /// Converts pixels of an image in this profile to sRGB. Alpha channel is kept as is.
+    pub fn to_srgb(&self, image: DynamicImage) -> DynamicImage {
+        let tables = [
+            linearize_table(&self.curves[0]),
+            linearize_table(&self.curves[1]),
+            linearize_table(&self.curves[2]),
+        ];
+        let encode_table = (0..ENCODE_TABLE_SIZE)
+            .map(|i| (srgb_encode(i as f64 / (ENCODE_TABLE_SIZE - 1) as f64) * 255.0).round() as u8)
+            .collect::<Vec<_>>();
+        let convert = |rgb: &mut [u8]| {
+            let linear = [tables[0][rgb[0] as usize], tables[1][rgb[1] as usize], tables[2][rgb[2] as usize]];
+            for (value, row) in rgb.iter_mut().zip(self.to_srgb.iter()) {
+                let converted = row.iter().zip(linear.iter()).map(|(a, b)| a * b).sum::<f64>();
+                let index = (converted.max(0.0).min(1.0) * (ENCODE_TABLE_SIZE - 1) as f64).round() as usize;
+                *value = encode_table[index];
+            }
+        };
+        match image {
+            DynamicImage::ImageRgb8(mut buffer) => {
+                for &mut Rgb { ref mut data } in buffer.pixels_mut() {
+                    convert(data);
+                }
+                DynamicImage::ImageRgb8(buffer)
+            }
+            image => {
+                let mut buffer = image.to_rgba();
+                for &mut Rgba { ref mut data } in buffer.pixels_mut() {
+                    convert(&mut data[..3]);
+                }
+                DynamicImage::ImageRgba8(buffer)
+            }
+        }
+    }
+}
+
+/// Extracts ICC profile embedded in JPEG, PNG or WebP image
+pub fn extract(format: &InputFormat, bytes: &[u8]) -> Option<Vec<u8>> {
+    match format {
+        &InputFormat::Jpeg => extract_jpeg(bytes),
+        &InputFormat::Png => extract_png(bytes),
+        &InputFormat::Webp => extract_webp(bytes),
+        _ => None,
+    }
+}
+
+/// Embeds ICC profile in JPEG or PNG image. Images in other formats are returned as is.
+pub fn embed(format: &OutputFormat, bytes: Vec<u8>, profile: &[u8]) -> Result<Vec<u8>, S3Error> {
+    match format {
+        &OutputFormat::Jpeg => Ok(embed_jpeg(bytes, profile)),
+        &OutputFormat::Png => embed_png(bytes, profile),
+        _ => Ok(bytes),
+    }
+}
+
+/// Compact ICC v2 profile of sRGB colour space
+pub fn srgb_profile() -> Vec<u8> {
+    let curve = (0..1024)
+        .map(|i| (srgb_linearize(f64::from(i) / 1023.0) * 65535.0).round() as u16)
+        .collect::<Vec<_>>();
+    write_profile(&SRGB_PRIMARIES, &curve)
+}
+
+/// Writes ICC v2 display profile with `primaries` and the same `curve` for all channels
+fn write_profile(primaries: &[[f64; 3]; 3], curve: &[u16]) -> Vec<u8> {
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    let description = b"sRGB\0";
+    push_u32(&mut desc, description.len() as u32);
+    desc.extend_from_slice(description);
+    // empty unicode and scriptcode descriptions, the latter has fixed size of 67 bytes
+    desc.extend_from_slice(&[0; 11]);
+    desc.extend_from_slice(&[0; 67]);
+    let mut cprt = b"text\0\0\0\0".to_vec();
+    cprt.extend_from_slice(b"No copyright, use freely\0");
+    let mut trc = b"curv\0\0\0\0".to_vec();
+    push_u32(&mut trc, curve.len() as u32);
+    for value in curve {
+        trc.extend_from_slice(&[(value >> 8) as u8, *value as u8]);
+    }
+
+    let tags = vec![
+        (&b"desc"[..], desc),
+        (&b"cprt"[..], cprt),
+        (&b"wtpt"[..], write_xyz(&D50)),
+        (&b"rXYZ"[..], write_xyz(&primaries[0])),
+        (&b"gXYZ"[..], write_xyz(&primaries[1])),
+        (&b"bXYZ"[..], write_xyz(&primaries[2])),
+        (&b"rTRC"[..], trc),
+    ];
+    // green and blue channels share the curve of red one
+    let tags_count = tags.len() + 2;
+    let data_offset = 128 + 4 + tags_count * 12;
+    let mut table = Vec::new();
+    let mut data = Vec::new();
+    for (signature, tag) in tags {
+        let offset = (data_offset + data.len()) as u32;
+        let signatures = if signature == b"rTRC" {
+            vec![signature, b"gTRC", b"bTRC"]
+        } else {
+            vec![signature]
+        };
+        for signature in signatures {
+            table.extend_from_slice(signature);
+            push_u32(&mut table, offset);
+            push_u32(&mut table, tag.len() as u32);
+        }
+        data.extend_from_slice(&tag);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+    }
+
+    let size = data_offset + data.len();
+    let mut profile = Vec::with_capacity(size);
+    push_u32(&mut profile, size as u32);
+    profile.extend_from_slice(&[0; 4]);
+    profile.extend_from_slice(&[0x02, 0x10, 0, 0]);
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    profile.extend_from_slice(&[0; 12]);
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 28]);
+    profile.extend_from_slice(&write_xyz(&D50)[8..]);
+    profile.extend_from_slice(&[0; 48]);
+    push_u32(&mut profile, tags_count as u32);
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
+
+fn extract_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut chunks = Vec::new();
+    let mut position = 2;
+    while position + 4 <= bytes.len() && bytes[position] == 0xff {
+        let marker = bytes[position + 1];
+        // start of scan or end of image
+        if marker == 0xda || marker == 0xd9 {
+            break;
+        }
+        if marker == 0x01 || marker == 0xff || (marker >= 0xd0 && marker <= 0xd7) {
+            position += if marker == 0xff { 1 } else { 2 };
+            continue;
+        }
+        let length = (usize::from(bytes[position + 2]) << 8) + usize::from(bytes[position + 3]);
+        let segment = bytes.get(position + 4..position + 2 + length)?;
+        if marker == 0xe2 && segment.starts_with(JPEG_ICC_SIGNATURE) && segment.len() > JPEG_ICC_SIGNATURE.len() + 2 {
+            let sequence = segment[JPEG_ICC_SIGNATURE.len()];
+            chunks.push((sequence, &segment[JPEG_ICC_SIGNATURE.len() + 2..]));
+        }
+        position += 2 + length;
+    }
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|&(sequence, _)| sequence);
+    Some(chunks.into_iter().flat_map(|(_, chunk)| chunk.iter().cloned()).collect())
+}
+
+fn extract_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+    let mut position = PNG_SIGNATURE.len();
+    while position + 8 <= bytes.len() {
+        let length = read_u32(bytes, position).ok()? as usize;
+        let kind = &bytes[position + 4..position + 8];
+        let data = bytes.get(position + 8..position + 8 + length)?;
+        match kind {
+            b"iCCP" => {
+                // profile name, null separator and compression method precede compressed profile
+                let name_end = data.iter().position(|&byte| byte == 0)?;
+                let mut profile = Vec::new();
+                ZlibDecoder::new(data.get(name_end + 2..)?)
+                    .take(MAX_PROFILE_SIZE as u64 + 1)
+                    .read_to_end(&mut profile)
+                    .ok()?;
+                return Some(profile).filter(|profile| profile.len() <= MAX_PROFILE_SIZE);
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => position += 12 + length,
+        }
+    }
+    None
+}
+
+fn extract_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return None;
+    }
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let kind = &bytes[position..position + 4];
+        let length = bytes[position + 4..position + 8]
+            .iter()
+            .rev()
+            .fold(0usize, |acc, &byte| (acc << 8) + usize::from(byte));
+        let data = bytes.get(position + 8..position + 8 + length)?;
+        if kind == b"ICCP" {
+            return Some(data.to_vec());
+        }
+        // chunks are padded to even size
+        position += 8 + length + length % 2;
+    }
+    None
+}
+
+/// Inserts APP2 segments after SOI and JFIF APP0 segment, which must be the first one
+fn embed_jpeg(bytes: Vec<u8>, profile: &[u8]) -> Vec<u8> {
+    let mut position = 2;
+    if bytes.len() > 6 && bytes[2] == 0xff && bytes[3] == 0xe0 {
+        position += 2 + (usize::from(bytes[4]) << 8) + usize::from(bytes[5]);
+    }
+    let position = position.min(bytes.len());
+    let chunks = profile.chunks(JPEG_ICC_CHUNK_SIZE).collect::<Vec<_>>();
+    let mut embedded = Vec::with_capacity(bytes.len() + profile.len() + chunks.len() * 18);
+    embedded.extend_from_slice(&bytes[..position]);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let length = 2 + JPEG_ICC_SIGNATURE.len() + 2 + chunk.len();
+        embedded.extend_from_slice(&[0xff, 0xe2, (length >> 8) as u8, length as u8]);
+        embedded.extend_from_slice(JPEG_ICC_SIGNATURE);
+        embedded.extend_from_slice(&[(i + 1) as u8, chunks.len() as u8]);
+        embedded.extend_from_slice(chunk);
+    }
+    embedded.extend_from_slice(&bytes[position..]);
+    embedded
+}
+
+/// Inserts iCCP chunk after IHDR chunk, which must be the first one
+fn embed_png(bytes: Vec<u8>, profile: &[u8]) -> Result<Vec<u8>, S3Error> {
+    if !bytes.starts_with(PNG_SIGNATURE) {
+        return Err(S3Error::Image("Invalid png to embed icc profile".to_string()));
+    }
+    let position = PNG_SIGNATURE.len() + 12 + read_u32(&bytes, PNG_SIGNATURE.len())? as usize;
+    if position > bytes.len() {
+        return Err(S3Error::Image("Invalid png to embed icc profile".to_string()));
+    }
+    // profile name, null separator and compression method precede compressed profile
+    let mut encoder = ZlibEncoder::new(b"ICC profile\0\0".to_vec(), Compression::default());
+    encoder
+        .write_all(profile)
+        .map_err(|e| S3Error::Image(format!("Error compressing icc profile: {}", e)))?;
+    let data = encoder
+        .finish()
+        .map_err(|e| S3Error::Image(format!("Error compressing icc profile: {}", e)))?;
+
+    let mut embedded = Vec::with_capacity(bytes.len() + data.len() + 12);
+    embedded.extend_from_slice(&bytes[..position]);
+    png::write_chunk(&mut embedded, b"iCCP", &data);
+    embedded.extend_from_slice(&bytes[position..]);
+    Ok(embedded)
+}
+
+fn parse_xyz(tag: &[u8]) -> Result<[f64; 3], S3Error> {
+    if tag.len() < 20 || &tag[0..4] != b"XYZ " {
+        return Err(S3Error::Image("Invalid icc profile xyz tag".to_string()));
+    }
+    Ok([read_fixed(tag, 8)?, read_fixed(tag, 12)?, read_fixed(tag, 16)?])
+}
+
+fn parse_curve(tag: &[u8]) -> Result<Curve, S3Error> {
+    match tag.get(0..4) {
+        Some(b"curv") => {
+            let count = read_u32(tag, 8)? as usize;
+            let values = tag
+                .get(12..12 + count * 2)
+                .ok_or_else(|| S3Error::Image("Icc profile curve is out of bounds".to_string()))?
+                .chunks(2)
+                .map(|value| f64::from((u16::from(value[0]) << 8) + u16::from(value[1])))
+                .collect::<Vec<_>>();
+            Ok(match values.len() {
+                0 => Curve::Gamma(1.0),
+                1 => Curve::Gamma(values[0] / 256.0),
+                _ => Curve::Table(values.into_iter().map(|value| value / 65535.0).collect()),
+            })
+        }
+        Some(b"para") => {
+            let function = (u16::from(tag.get(8).cloned().unwrap_or(0)) << 8) + u16::from(tag.get(9).cloned().unwrap_or(0));
+            let count = match function {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return Err(S3Error::Image(format!("Unknown icc profile curve function {}", function))),
+            };
+            let mut params = [0.0; 7];
+            for (i, param) in params.iter_mut().enumerate().take(count) {
+                *param = read_fixed(tag, 12 + i * 4)?;
+            }
+            Ok(Curve::Parametric(function, params))
+        }
+        _ => Err(S3Error::Image("Unknown icc profile curve type".to_string())),
+    }
+}
+
+fn write_xyz(xyz: &[f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for value in xyz {
+        push_u32(&mut tag, (value * 65536.0).round() as i32 as u32);
+    }
+    tag
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, S3Error> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|value| value.iter().fold(0, |acc, &byte| (acc << 8) + u32::from(byte)))
+        .ok_or_else(|| S3Error::Image("Unexpected end of icc profile".to_string()))
+}
+
+/// Reads s15Fixed16Number
+fn read_fixed(bytes: &[u8], offset: usize) -> Result<f64, S3Error> {
+    read_u32(bytes, offset).map(|value| f64::from(value as i32) / 65536.0)
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+fn linearize_table(curve: &Curve) -> Vec<f64> {
+    (0..256).map(|i| curve.linearize(f64::from(i) / 255.0)).collect()
+}
+
+fn srgb_linearize(x: f64) -> f64 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb_encode(x: f64) -> f64 {
+    if x <= 0.003_130_8 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+
+    /// Display P3 primaries adapted to D50
+    static P3_PRIMARIES: [[f64; 3]; 3] = [[0.5151, 0.2412, -0.0011], [0.2920, 0.6922, 0.0419], [0.1571, 0.0666, 0.7841]];
+
+    #[test]
+    fn test_srgb_profile() {
+        let profile = Profile::parse(&srgb_profile()).unwrap();
+        assert!(profile.is_srgb());
+    }
+
+    #[test]
+    fn test_to_srgb() {
+        let curve = (0..1024)
+            .map(|i| (srgb_linearize(f64::from(i) / 1023.0) * 65535.0).round() as u16)
+            .collect::<Vec<_>>();
+        let profile = Profile::parse(&write_profile(&P3_PRIMARIES, &curve)).unwrap();
+        assert!(!profile.is_srgb());
+
+        let mut image = RgbImage::new(2, 1);
+        image.put_pixel(0, 0, Rgb { data: [128, 0, 0] });
+        image.put_pixel(1, 0, Rgb { data: [128, 128, 128] });
+        let converted = profile.to_srgb(DynamicImage::ImageRgb8(image)).to_rgb();
+        // P3 red is more saturated than sRGB one
+        let red = converted.get_pixel(0, 0).data;
+        assert!(red[0] > 135 && red[1] < 3 && red[2] < 3, "{:?}", red);
+        let gray = converted.get_pixel(1, 0).data;
+        assert!(gray.iter().all(|&value| value >= 127 && value <= 129), "{:?}", gray);
+    }
+
+    #[test]
+    fn test_parse_tag_count() {
+        let mut profile = srgb_profile();
+        profile[128..132].copy_from_slice(&[0xFF; 4]);
+        assert!(Profile::parse(&profile).unwrap().is_srgb());
+    }
+
+    #[test]
+    fn test_extract_png_bomb() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![0; MAX_PROFILE_SIZE + 1]).unwrap();
+        let mut data = b"bomb\0\0".to_vec();
+        data.extend(encoder.finish().unwrap());
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        bytes.extend(&[
+            (data.len() >> 24) as u8,
+            (data.len() >> 16) as u8,
+            (data.len() >> 8) as u8,
+            data.len() as u8,
+        ]);
+        bytes.extend(b"iCCP");
+        bytes.extend(data);
+        bytes.extend(&[0; 4]);
+        assert_eq!(extract(&InputFormat::Png, &bytes), None);
+    }
+
+    #[test]
+    fn test_embed_extract() {
+        let profile = srgb_profile();
+        let image = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        for (output, input, image_format) in vec![
+            (OutputFormat::Png, InputFormat::Png, ImageFormat::PNG),
+            (OutputFormat::Jpeg, InputFormat::Jpeg, ImageFormat::JPEG),
+        ] {
+            let mut bytes = Vec::new();
+            image.write_to(&mut bytes, image_format).unwrap();
+            assert_eq!(extract(&input, &bytes), None);
+            let embedded = embed(&output, bytes, &profile).unwrap();
+            assert_eq!(extract(&input, &embedded), Some(profile.clone()));
+            assert!(::image::load_from_memory_with_format(&embedded, image_format).is_ok());
+        }
+    }
+}
@@ -1,90 +1,553 @@
 //! Preprocessors module contains functions for preprocessing images / videos, etc.
 
+pub mod animation;
+pub mod blurhash;
+pub mod edit;
+pub mod icc;
+pub mod palette;
+pub mod placeholder;
+pub mod png;
+#[cfg(feature = "svg")]
+pub mod raster;
+pub mod resize;
+pub mod saliency;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod watermark;
+
 use futures::future;
 use futures::future::Future;
 use futures_cpupool::CpuPool;
 use image;
+use image::DynamicImage;
 use image::GenericImageView;
-use image::{DynamicImage, FilterType, ImageFormat};
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use self::animation::Frame;
+use self::watermark::Watermark;
 use super::error::S3Error;
-use super::types::ImageSize;
+use super::types::{
+    Colors, DenseVariant, Fit, FocalPoint, ImageSize, InputFormat, OutputFormat, Placeholder, ProcessOptions, ResizeParams,
+};
+
+/// Image encoded in one of output formats
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncodedImage {
+    pub format: OutputFormat,
+    pub bytes: Vec<u8>,
+    /// Dimensions of the encoded image, which may differ from the ones of the box it was resized into
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Result of image processing
+pub struct ProcessedImage {
+    /// Sized and resized images, encoded in GIF if they are animated and in PNG otherwise
+    pub variants: HashMap<ImageSize, EncodedImage>,
+    /// Variants of size presets for high density screens, encoded the same way as presets
+    pub dense_variants: HashMap<DenseVariant, EncodedImage>,
+    /// Placeholders to render while the image is loading
+    pub placeholder: Placeholder,
+    /// Dominant colour and palette
+    pub colors: Colors,
+    /// Focal point in percents, either supplied by client or detected
+    pub focal_point: FocalPoint,
+    /// Unedited (but sanitized) image if it was cropped, rotated, watermarked or converted and
+    /// `ProcessOptions::keep_source` is set
+    pub source: Option<Vec<u8>>,
+    /// Original without watermark if the original is watermarked, edited and converted the same way
+    pub unmarked: Option<EncodedImage>,
+}
+
+/// Uploaded image decoded for processing, with edits applied
+struct DecodedImage {
+    image: DynamicImage,
+    /// Frames of animated GIFs if `ProcessOptions::animate` is set
+    frames: Option<Arc<Vec<Frame>>>,
+    /// Bytes of the image, sanitized for SVGs
+    bytes: Vec<u8>,
+    has_profile: bool,
+    focal_point: FocalPoint,
+}
 
 pub trait Image {
     /// Process image specified by format and bytes encoded in this format
     ///
-    /// * `format` - either "png" or "jpg" - these are types that are supported
+    /// * `format` - "png", "jpg", "gif" or one of formats enabled by cargo features, see `InputFormat`.
+    /// SVGs are sanitized and rasterized.
     /// * `bytes` - bytes representing encoded image
+    /// * `options` - options of processing, e.g. how resized images fit the `ImageSize` boxes
     ///
-    /// Returns HashMap of sized and resized images encoded in PNG
+    /// Images with embedded colour profiles are converted to sRGB before resizing.
+    /// Returns sized and resized images along with placeholders, colours and focal point. Images are
+    /// encoded in PNG, except for animated GIFs with `options.animate` set, whose variants other
+    /// than thumb are animated GIFs. Placeholders, colours and focal point use the first frame.
+    /// Originals in formats other than png, jpeg, gif and svg are converted to PNG, originals of SVGs
+    /// are sanitized SVGs.
+    /// If focal point is not specified in `options`, it is detected. Presets are watermarked if
+    /// the preprocessor has a watermark for them. Presets are also resized for every density in
+    /// `options.densities`, unless the image is too small for that density.
     ///
     /// #Errors
     /// * `S3Error::Image` if encoding is incorrect, incl zero dimensions
-    fn process(&self, format: ImageFormat, bytes: Vec<u8>) -> Box<Future<Item = HashMap<ImageSize, Vec<u8>>, Error = S3Error>>;
+    fn process(&self, format: InputFormat, bytes: Vec<u8>, options: ProcessOptions) -> Box<Future<Item = ProcessedImage, Error = S3Error>>;
+
+    /// Resizes image into a box specified by `params`
+    ///
+    /// * `bytes` - bytes representing encoded image, format is guessed from bytes
+    /// * `params` - box, fit and output format. If fit is `Fit::Smart` and focal point is not
+    /// specified, it is detected.
+    /// * `watermark` - overlay the watermark of the preprocessor, e.g. for images resized from
+    /// the unmarked copy of a watermarked original
+    ///
+    /// Returns resized image encoded in `params.format`, converted to sRGB if the image has
+    /// a colour profile. Animated GIFs resized to GIF stay animated.
+    ///
+    /// #Errors
+    /// * `S3Error::Image` if encoding is incorrect, incl zero dimensions
+    /// * `S3Error::Forbidden` if `watermark` is set, but the preprocessor has no watermark
+    fn resize(&self, bytes: Vec<u8>, params: ResizeParams, watermark: bool) -> Box<Future<Item = EncodedImage, Error = S3Error>>;
 }
 
 pub struct ImageImpl<'a> {
     cpu_pool: &'a CpuPool,
+    watermark: Option<Arc<Watermark>>,
 }
 
 impl<'a> ImageImpl<'a> {
     pub fn new(cpu_pool: &'a CpuPool) -> Self {
-        Self { cpu_pool }
+        Self::with_watermark(cpu_pool, None)
+    }
+
+    /// Creates preprocessor that overlays `watermark` on presets listed in its options
+    pub fn with_watermark(cpu_pool: &'a CpuPool, watermark: Option<Arc<Watermark>>) -> Self {
+        Self { cpu_pool, watermark }
     }
 
     /// Spawns resizing an image on a thread from a thread pool
     ///
-    /// * `size` - image size for resizing
-    /// * `image_type` - either "png", "jpg" or "jpeg" - these are types that are supported
-    /// * `bytes` - bytes representing compressed image (compressed with `image_type` codec)
-    fn resize_image_async(&self, size: &ImageSize, image: DynamicImage) -> Box<Future<Item = Vec<u8>, Error = S3Error>> {
-        let size_clone = size.clone();
-        Box::new(self.cpu_pool.spawn_fn(move || Self::resize_image(&size_clone, image)))
+    /// * `image` - decoded image
+    /// * `params` - box, fit and output format
+    /// * `watermark` - watermark overlaid on resized image
+    fn resize_image_async(
+        &self,
+        image: DynamicImage,
+        params: ResizeParams,
+        watermark: Option<Arc<Watermark>>,
+    ) -> Box<Future<Item = EncodedImage, Error = S3Error>> {
+        Box::new(
+            self.cpu_pool
+                .spawn_fn(move || Self::resize_image(image, &params, watermark.as_ref().map(|watermark| &**watermark))),
+        )
     }
 
-    /// Resizes an image on a thread from a thread pool
+    /// Resizes an image into a box, overlays watermark and encodes it
     ///
-    /// * `size` - image size for resizing
-    /// * `image_type` - either "png", "jpg" or "jpeg" - these are types that are supported
-    /// * `bytes` - bytes representing compressed image (compressed with `image_type` codec)
-    fn resize_image(size: &ImageSize, image: DynamicImage) -> Result<Vec<u8>, S3Error> {
+    /// * `image` - decoded image
+    /// * `params` - box, fit and output format
+    /// * `watermark` - watermark overlaid on resized image
+    fn resize_image(image: DynamicImage, params: &ResizeParams, watermark: Option<&Watermark>) -> Result<EncodedImage, S3Error> {
         let (w, h) = image.dimensions();
-        let smallest_dimension = if w < h { w } else { h };
-        if smallest_dimension == 0 {
+        if w == 0 || h == 0 {
             return Err(S3Error::Image("Uploaded image size is zero".to_string()));
         }
-        let int_size = size.clone() as u32;
-        let width = ((w as f32) * (int_size as f32) / (smallest_dimension as f32)).round() as u32;
-        let height = ((h as f32) * (int_size as f32) / (smallest_dimension as f32)).round() as u32;
-        let resized_image = match int_size {
-            x if x < smallest_dimension => image.resize(width, height, FilterType::Triangle),
-            _ => image,
+        let resized_image = resize::resize(image, params);
+        let resized_image = match watermark {
+            Some(watermark) => watermark.apply(resized_image),
+            None => resized_image,
+        };
+        let (width, height) = resized_image.dimensions();
+        let bytes = match (&params.format, &params.optimize_png) {
+            // jpeg has no alpha channel
+            (&OutputFormat::Jpeg, _) => Self::write_image(DynamicImage::ImageRgb8(resized_image.to_rgb()), &params.format),
+            (&OutputFormat::Gif, _) => animation::encode(vec![Frame {
+                image: resized_image.to_rgba(),
+                delay: 0,
+            }])?,
+            (&OutputFormat::Png, &Some(ref optimization)) => {
+                let optimized = png::optimize(&resized_image, optimization)?;
+                let default = Self::write_image(resized_image, &params.format);
+                if optimized.len() < default.len() {
+                    optimized
+                } else {
+                    default
+                }
+            }
+            _ => Self::write_image(resized_image, &params.format),
+        };
+        let bytes = if params.embed_profile {
+            icc::embed(&params.format, bytes, &icc::srgb_profile())?
+        } else {
+            bytes
         };
+        Ok(EncodedImage {
+            format: params.format.clone(),
+            bytes,
+            width,
+            height,
+        })
+    }
+
+    /// Spawns resizing every frame of an animation on a thread from a thread pool. Frames are shared
+    /// between variants, so that they aren't copied for every variant.
+    fn resize_frames_async(
+        &self,
+        frames: Arc<Vec<Frame>>,
+        params: ResizeParams,
+        watermark: Option<Arc<Watermark>>,
+    ) -> Box<Future<Item = EncodedImage, Error = S3Error>> {
+        Box::new(
+            self.cpu_pool
+                .spawn_fn(move || Self::resize_frames(&frames, &params, watermark.as_ref().map(|watermark| &**watermark))),
+        )
+    }
+
+    /// Resizes every frame of an animation into a box, keeping frame delays, and encodes it in GIF
+    ///
+    /// * `frames` - decoded frames
+    /// * `params` - box and fit, format is always GIF
+    /// * `watermark` - watermark overlaid on every frame
+    fn resize_frames(frames: &[Frame], params: &ResizeParams, watermark: Option<&Watermark>) -> Result<EncodedImage, S3Error> {
+        let frames = frames
+            .iter()
+            .map(|frame| {
+                let image = resize::resize(DynamicImage::ImageRgba8(frame.image.clone()), params);
+                let image = match watermark {
+                    Some(watermark) => watermark.apply(image),
+                    None => image,
+                };
+                Frame {
+                    image: image.to_rgba(),
+                    delay: frame.delay,
+                }
+            })
+            .collect::<Vec<_>>();
+        let (width, height) = frames.first().map(|frame| frame.image.dimensions()).unwrap_or((0, 0));
+        Ok(EncodedImage {
+            format: OutputFormat::Gif,
+            bytes: animation::encode(frames)?,
+            width,
+            height,
+        })
+    }
+
+    /// Decodes frames of an animated GIF, applying edits to every frame. Returns `None`
+    /// if the GIF has a single frame.
+    fn decode_animation(bytes: &[u8], options: &ProcessOptions) -> Result<Option<Vec<Frame>>, S3Error> {
+        let frames = animation::decode(bytes, animation::MAX_PIXELS)?;
+        if frames.len() < 2 {
+            return Ok(None);
+        }
+        if options.crop.is_none() && options.rotation.is_none() {
+            return Ok(Some(frames));
+        }
+        frames
+            .into_iter()
+            .map(|frame| {
+                let image = edit::apply(
+                    DynamicImage::ImageRgba8(frame.image),
+                    options.crop.as_ref(),
+                    options.rotation.as_ref(),
+                )?;
+                Ok(Frame {
+                    image: image.to_rgba(),
+                    delay: frame.delay,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+
+    /// Encodes an image with `image` crate encoder
+    fn write_image(image: DynamicImage, format: &OutputFormat) -> Vec<u8> {
         let mut buffer = Vec::new();
-        let _ = resized_image.write_to(&mut buffer, ImageFormat::PNG);
-        Ok(buffer)
+        if let Some(image_format) = format.image_format() {
+            let _ = image.write_to(&mut buffer, image_format);
+        }
+        buffer
     }
-}
 
-impl<'a> Image for ImageImpl<'a> {
-    fn process(&self, format: ImageFormat, bytes: Vec<u8>) -> Box<Future<Item = HashMap<ImageSize, Vec<u8>>, Error = S3Error>> {
-        let image = match image::load_from_memory_with_format(&bytes, format) {
-            Ok(data) => data,
-            Err(e) => return S3Error::Image(format!("Error parsing image with format {:?}: {}", format, e)).into(),
+    /// Decodes an image. SVGs are sanitized and rasterized, so that the smaller dimension of raster
+    /// is at least `raster_size`. Images with embedded RGB colour profile are converted to sRGB.
+    /// Returns decoded image along with bytes of the image, which are sanitized for SVGs and unchanged
+    /// otherwise, and whether the image had a colour profile.
+    fn decode(format: &InputFormat, bytes: Vec<u8>, raster_size: u32) -> Result<(DynamicImage, Vec<u8>, bool), S3Error> {
+        match format.image_format() {
+            Some(image_format) => {
+                let image = image::load_from_memory_with_format(&bytes, image_format)
+                    .map_err(|e| S3Error::Image(format!("Error parsing image with format {}: {}", format, e)))?;
+                // profiles based on lookup tables, e.g. CMYK ones, are not supported and ignored
+                let profile = icc::extract(format, &bytes).and_then(|profile| icc::Profile::parse(&profile).ok());
+                let image = match profile {
+                    Some(ref profile) if !profile.is_srgb() => profile.to_srgb(image),
+                    _ => image,
+                };
+                Ok((image, bytes, profile.is_some()))
+            }
+            None => Self::decode_svg(&bytes, raster_size).map(|(image, bytes)| (image, bytes, false)),
+        }
+    }
+
+    #[cfg(feature = "svg")]
+    fn decode_svg(bytes: &[u8], raster_size: u32) -> Result<(DynamicImage, Vec<u8>), S3Error> {
+        let sanitized = svg::sanitize(bytes)?;
+        let image = svg::rasterize(&sanitized, raster_size)?;
+        Ok((image, sanitized))
+    }
+
+    #[cfg(not(feature = "svg"))]
+    fn decode_svg(_bytes: &[u8], _raster_size: u32) -> Result<(DynamicImage, Vec<u8>), S3Error> {
+        Err(S3Error::Image("Svg support is disabled".to_string()))
+    }
+
+    /// Params for resizing an image to `size` preset. Resizing with `ImageSize::Original`
+    /// keeps the image as is.
+    fn preset_params(size: &ImageSize, options: &ProcessOptions, focal_point: &FocalPoint, has_profile: bool) -> ResizeParams {
+        let int_size = match size {
+            &ImageSize::Original => None,
+            size => Some(size.clone() as u32),
+        };
+        ResizeParams {
+            width: int_size,
+            height: int_size,
+            fit: options.fit.get(size).cloned().unwrap_or_default(),
+            background: options.background,
+            focal_point: Some(focal_point.clone()),
+            format: OutputFormat::Png,
+            embed_profile: options.embed_profile && has_profile,
+            optimize_png: options.optimize_png.clone(),
+            resampling: options.resampling.get(size).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Watermark for `size` preset, if any
+    fn watermark_for(&self, size: &ImageSize) -> Option<Arc<Watermark>> {
+        self.watermark.clone().filter(|watermark| watermark.applies_to(size))
+    }
+
+    /// Spawns creating placeholders for an image on a thread from a thread pool
+    fn create_placeholder_async(&self, image: DynamicImage) -> Box<Future<Item = Placeholder, Error = S3Error>> {
+        Box::new(self.cpu_pool.spawn_fn(move || placeholder::create(&image)))
+    }
+
+    /// Spawns extracting dominant colour and palette of an image on a thread from a thread pool
+    fn extract_colors_async(&self, image: DynamicImage) -> Box<Future<Item = Colors, Error = S3Error>> {
+        Box::new(self.cpu_pool.spawn_fn(move || palette::extract(&image)))
+    }
+
+    /// Decodes uploaded image along with its frames if it's an animation, applies edits and detects
+    /// focal point if it's not set in `options`
+    fn decode_upload(format: InputFormat, bytes: Vec<u8>, options: &ProcessOptions) -> Result<DecodedImage, S3Error> {
+        // vector images are rasterized large enough for the densest variant
+        let max_density = options.densities.iter().cloned().max().unwrap_or(1).max(1);
+        let (image, bytes, has_profile) = Self::decode(&format, bytes, ImageSize::Large as u32 * max_density)?;
+        let frames = if options.animate && format == InputFormat::Gif {
+            Self::decode_animation(&bytes, options)?.map(Arc::new)
+        } else {
+            None
         };
-        let mut futures: Vec<Box<Future<Item = (ImageSize, Vec<u8>), Error = S3Error>>> =
-            [ImageSize::Thumb, ImageSize::Small, ImageSize::Medium, ImageSize::Large]
+        let image = if options.crop.is_some() || options.rotation.is_some() {
+            edit::apply(image, options.crop.as_ref(), options.rotation.as_ref())?
+        } else {
+            image
+        };
+        let focal_point = match options.focal_point {
+            Some(ref focal_point) => {
+                let (w, h) = image.dimensions();
+                focal_point.to_percents(w, h)
+            }
+            None => saliency::detect(&image),
+        };
+        Ok(DecodedImage {
+            image,
+            frames,
+            bytes,
+            has_profile,
+            focal_point,
+        })
+    }
+
+    /// Spawns resizing decoded image to all variants and creating its placeholders and colours
+    fn process_decoded(
+        &self,
+        format: InputFormat,
+        decoded: DecodedImage,
+        options: &ProcessOptions,
+    ) -> Box<Future<Item = ProcessedImage, Error = S3Error>> {
+        let DecodedImage {
+            image,
+            frames,
+            bytes,
+            has_profile,
+            focal_point,
+        } = decoded;
+        let edited = options.crop.is_some() || options.rotation.is_some();
+        let (mut futures, dense_futures) = {
+            let (w, h) = image.dimensions();
+            // resizes image to `size` preset multiplied by `density`, unless that upscales the image
+            let resize_preset = |size: &ImageSize, density: u32| -> Option<Box<Future<Item = EncodedImage, Error = S3Error>>> {
+                let params = Self::preset_params(size, &options, &focal_point, has_profile);
+                let params = ResizeParams {
+                    width: params.width.map(|width| width * density),
+                    height: params.height.map(|height| height * density),
+                    ..params
+                };
+                if density > 1 && !resize::is_downscale(w, h, &params) {
+                    return None;
+                }
+                let watermark = self.watermark_for(size);
+                Some(match frames {
+                    // thumbs are always static
+                    Some(ref frames) if size != &ImageSize::Thumb => {
+                        let params = ResizeParams {
+                            format: OutputFormat::Gif,
+                            ..params
+                        };
+                        self.resize_frames_async(frames.clone(), params, watermark)
+                    }
+                    _ => self.resize_image_async(image.clone(), params, watermark),
+                })
+            };
+            let presets = ImageSize::presets();
+            let futures: Vec<Box<Future<Item = (ImageSize, EncodedImage), Error = S3Error>>> = presets
                 .iter()
-                .map(|size| {
-                    let img = image.clone();
+                .filter_map(|size| {
                     let size_clone = size.clone();
-                    Box::new(self.resize_image_async(size, img).map(|bytes| (size_clone, bytes)))
-                        as Box<Future<Item = (ImageSize, Vec<u8>), Error = S3Error>>
+                    resize_preset(size, 1).map(|resized| {
+                        Box::new(resized.map(|encoded| (size_clone, encoded)))
+                            as Box<Future<Item = (ImageSize, EncodedImage), Error = S3Error>>
+                    })
+                })
+                .collect();
+            let dense_futures: Vec<Box<Future<Item = (DenseVariant, EncodedImage), Error = S3Error>>> = presets
+                .iter()
+                .flat_map(|size| {
+                    options
+                        .densities
+                        .iter()
+                        .filter(|&&density| density > 1)
+                        .map(move |&density| (size, density))
+                })
+                .filter_map(|(size, density)| {
+                    let variant = DenseVariant {
+                        size: size.clone(),
+                        density,
+                    };
+                    resize_preset(size, density).map(|resized| {
+                        Box::new(resized.map(|encoded| (variant, encoded)))
+                            as Box<Future<Item = (DenseVariant, EncodedImage), Error = S3Error>>
+                    })
                 })
                 .collect();
-        futures.push(Box::new(future::ok((ImageSize::Original, bytes))));
-        Box::new(future::join_all(futures).map(|results| results.into_iter().collect::<HashMap<_, _>>()))
+            (futures, dense_futures)
+        };
+        // originals that are kept as is have dimensions of the decoded image, rasterized ones for SVGs
+        let (width, height) = image.dimensions();
+        let original_watermark = self.watermark_for(&ImageSize::Original);
+        let params = Self::preset_params(&ImageSize::Original, &options, &focal_point, has_profile);
+        let reencode = edited || original_watermark.is_some() || !format.is_web_safe();
+        let unmarked: Box<Future<Item = Option<EncodedImage>, Error = S3Error>> = match original_watermark {
+            Some(_) if edited || !format.is_web_safe() => {
+                let unmarked = match frames {
+                    Some(ref frames) => self.resize_frames_async(frames.clone(), params.clone(), None),
+                    None => self.resize_image_async(image.clone(), params.clone(), None),
+                };
+                Box::new(unmarked.map(Some))
+            }
+            Some(_) => Box::new(future::ok(format.output_format().map(|format| EncodedImage {
+                format,
+                bytes: bytes.clone(),
+                width,
+                height,
+            }))),
+            None => Box::new(future::ok(None)),
+        };
+        let (original, source): (Box<Future<Item = EncodedImage, Error = S3Error>>, _) = if reencode {
+            // edited, watermarked or converted to png image replaces the original one
+            let original = match frames {
+                Some(frames) => self.resize_frames_async(frames, params, original_watermark),
+                None => self.resize_image_async(image.clone(), params, original_watermark),
+            };
+            (original, if options.keep_source { Some(bytes) } else { None })
+        } else {
+            // images that aren't web-safe are always re-encoded
+            let format = format.output_format().unwrap_or(OutputFormat::Png);
+            (
+                Box::new(future::ok(EncodedImage {
+                    format,
+                    bytes,
+                    width,
+                    height,
+                })),
+                None,
+            )
+        };
+        futures.push(Box::new(original.map(|encoded| (ImageSize::Original, encoded))));
+        let colors = self.extract_colors_async(image.clone());
+        let placeholder = self.create_placeholder_async(image);
+        Box::new(
+            future::join_all(futures)
+                .join5(future::join_all(dense_futures), placeholder, colors, unmarked)
+                .map(move |(results, dense_results, placeholder, colors, unmarked)| ProcessedImage {
+                    variants: results.into_iter().collect::<HashMap<_, _>>(),
+                    dense_variants: dense_results.into_iter().collect::<HashMap<_, _>>(),
+                    placeholder,
+                    colors,
+                    focal_point,
+                    source,
+                    unmarked,
+                }),
+        )
+    }
+}
+
+impl<'a> Image for ImageImpl<'a> {
+    fn process(&self, format: InputFormat, bytes: Vec<u8>, options: ProcessOptions) -> Box<Future<Item = ProcessedImage, Error = S3Error>> {
+        let cpu_pool = self.cpu_pool.clone();
+        let watermark = self.watermark.clone();
+        let decode_options = options.clone();
+        Box::new(
+            self.cpu_pool
+                .spawn_fn(move || Self::decode_upload(format, bytes, &decode_options))
+                .and_then(move |decoded| ImageImpl::with_watermark(&cpu_pool, watermark).process_decoded(format, decoded, &options)),
+        )
+    }
+
+    fn resize(&self, bytes: Vec<u8>, params: ResizeParams, watermark: bool) -> Box<Future<Item = EncodedImage, Error = S3Error>> {
+        let watermark = match (watermark, &self.watermark) {
+            (false, _) => None,
+            (true, &Some(ref watermark)) => Some(watermark.clone()),
+            (true, &None) => return S3Error::Forbidden("Watermarked image can't be resized without watermark".to_string()).into(),
+        };
+        Box::new(self.cpu_pool.spawn_fn(move || {
+            let format = InputFormat::guess(&bytes).ok_or_else(|| S3Error::Image("Unknown image format".to_string()))?;
+            // vector images are rasterized to the box size
+            let raster_size = params.width.max(params.height).unwrap_or(ImageSize::Large as u32);
+            let (image, bytes, has_profile) = Self::decode(&format, bytes, raster_size)?;
+            let params = ResizeParams {
+                embed_profile: params.embed_profile && has_profile,
+                ..params
+            };
+            let params = match (&params.fit, &params.focal_point) {
+                (&Fit::Smart, &None) => ResizeParams {
+                    focal_point: Some(saliency::detect(&image)),
+                    ..params
+                },
+                _ => params,
+            };
+            let frames = match (&params.format, format) {
+                (&OutputFormat::Gif, InputFormat::Gif) => {
+                    Some(animation::decode(&bytes, animation::MAX_PIXELS)?).filter(|frames| frames.len() > 1)
+                }
+                _ => None,
+            };
+            let watermark = watermark.as_ref().map(|watermark| &**watermark);
+            match frames {
+                Some(frames) => Self::resize_frames(&frames, &params, watermark),
+                None => Self::resize_image(image, &params, watermark),
+            }
+        }))
     }
 }
 
@@ -94,6 +557,9 @@ mod test {
     use std::fs::File;
     use std::io::Read;
 
+    use image::{ImageFormat, Rgba, RgbaImage};
+
+    use super::super::types::{Color, Position, Resampling, WatermarkOptions};
     use super::*;
 
     #[ignore]
@@ -107,13 +573,17 @@ mod test {
 
         let cpu_pool = CpuPool::new_num_cpus();
         let image = ImageImpl::new(&cpu_pool);
-        let image_hash = image.process(ImageFormat::PNG, original_image_bytes.clone()).wait().unwrap();
+        let image_hash = image
+            .process(InputFormat::Png, original_image_bytes.clone(), ProcessOptions::default())
+            .wait()
+            .unwrap()
+            .variants;
 
-        assert_eq!(image_hash[&ImageSize::Thumb], thumb_image_bytes);
-        assert_eq!(image_hash[&ImageSize::Small], small_image_bytes);
-        assert_eq!(image_hash[&ImageSize::Medium], medium_image_bytes);
-        assert_eq!(image_hash[&ImageSize::Large], large_image_bytes);
-        assert_eq!(image_hash[&ImageSize::Original], original_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Thumb].bytes, thumb_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Small].bytes, small_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Medium].bytes, medium_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Large].bytes, large_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Original].bytes, original_image_bytes);
     }
 
     #[ignore]
@@ -128,13 +598,17 @@ mod test {
 
         let cpu_pool = CpuPool::new_num_cpus();
         let image = ImageImpl::new(&cpu_pool);
-        let image_hash = image.process(ImageFormat::JPEG, original_image_bytes).wait().unwrap();
+        let image_hash = image
+            .process(InputFormat::Jpeg, original_image_bytes, ProcessOptions::default())
+            .wait()
+            .unwrap()
+            .variants;
 
-        assert_eq!(image_hash[&ImageSize::Thumb], thumb_image_bytes);
-        assert_eq!(image_hash[&ImageSize::Small], small_image_bytes);
-        assert_eq!(image_hash[&ImageSize::Medium], medium_image_bytes);
-        assert_eq!(image_hash[&ImageSize::Large], large_image_bytes);
-        assert_eq!(image_hash[&ImageSize::Original], coverted_original_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Thumb].bytes, thumb_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Small].bytes, small_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Medium].bytes, medium_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Large].bytes, large_image_bytes);
+        assert_eq!(image_hash[&ImageSize::Original].bytes, coverted_original_image_bytes);
     }
 
     #[test]
@@ -142,13 +616,269 @@ mod test {
         let original_image_bytes = read_static_file("image-1280x800.jpg");
         let cpu_pool = CpuPool::new_num_cpus();
         let image = ImageImpl::new(&cpu_pool);
-        let error = image.process(ImageFormat::PNG, original_image_bytes.clone()).wait().err().unwrap();
+        let error = image
+            .process(InputFormat::Png, original_image_bytes.clone(), ProcessOptions::default())
+            .wait()
+            .err()
+            .unwrap();
         match error {
             S3Error::Image(_) => (),
             e => assert!(false, format!("Expected error S3Error::Image, found {}", e)),
         }
     }
 
+    #[test]
+    fn test_image_process_watermark() {
+        let original_image_bytes = read_static_file("image-1280x800.jpg");
+        let cpu_pool = CpuPool::new_num_cpus();
+        let mark = RgbaImage::from_pixel(10, 10, Rgba { data: [0, 0, 0, 255] });
+        let options = WatermarkOptions {
+            image_path: None,
+            text: None,
+            font_path: None,
+            color: Color::default(),
+            position: Position::BottomRight,
+            opacity: 1.0,
+            scale: 0.2,
+            margin: 0.0,
+            min_size: 0,
+            presets: vec![ImageSize::Large],
+        };
+        let watermark = Arc::new(Watermark::new(mark.clone(), options.clone()));
+
+        let process = |image: ImageImpl| {
+            image
+                .process(InputFormat::Jpeg, original_image_bytes.clone(), ProcessOptions::default())
+                .wait()
+                .unwrap()
+        };
+        let plain = process(ImageImpl::new(&cpu_pool)).variants;
+        let marked = process(ImageImpl::with_watermark(&cpu_pool, Some(watermark)));
+        assert!(marked.unmarked.is_none());
+        let marked = marked.variants;
+
+        assert_eq!(marked[&ImageSize::Thumb], plain[&ImageSize::Thumb]);
+        assert_eq!(marked[&ImageSize::Original], plain[&ImageSize::Original]);
+        assert_ne!(marked[&ImageSize::Large], plain[&ImageSize::Large]);
+        let large = image::load_from_memory(&marked[&ImageSize::Large].bytes).unwrap().to_rgba();
+        let (w, h) = large.dimensions();
+        // resampling of the mark may lose a level of its alpha
+        let corner = large.get_pixel(w - 1, h - 1).data;
+        assert!(corner[..3].iter().all(|&value| value <= 1), "corner = {:?}", corner);
+
+        // watermarked original keeps an unmarked copy
+        let options = WatermarkOptions {
+            presets: vec![ImageSize::Original],
+            ..options
+        };
+        let watermark = Arc::new(Watermark::new(mark, options));
+        let marked = process(ImageImpl::with_watermark(&cpu_pool, Some(watermark.clone())));
+        assert_ne!(marked.variants[&ImageSize::Original].bytes, original_image_bytes);
+        let unmarked = marked.unmarked.unwrap();
+        assert_eq!(unmarked.format, OutputFormat::Jpeg);
+        assert_eq!(unmarked.bytes, original_image_bytes);
+
+        // sizes resized on the fly from the unmarked copy are watermarked
+        let params = ResizeParams {
+            width: Some(400),
+            height: None,
+            fit: Fit::Inside,
+            background: Color::default(),
+            focal_point: None,
+            format: OutputFormat::Png,
+            embed_profile: false,
+            optimize_png: None,
+            resampling: Resampling::default(),
+        };
+        let resize = |image: ImageImpl, watermark: bool| image.resize(unmarked.bytes.clone(), params.clone(), watermark).wait();
+        let plain = resize(ImageImpl::new(&cpu_pool), false).unwrap();
+        let marked = resize(ImageImpl::with_watermark(&cpu_pool, Some(watermark.clone())), true).unwrap();
+        assert_ne!(marked.bytes, plain.bytes);
+        match resize(ImageImpl::new(&cpu_pool), true) {
+            Err(S3Error::Forbidden(_)) => (),
+            res => assert!(
+                false,
+                format!("Expected error S3Error::Forbidden, found {:?}", res.map(|image| image.bytes.len()))
+            ),
+        }
+    }
+
+    #[test]
+    fn test_image_process_animated_gif() {
+        let frames = vec![
+            Frame {
+                image: RgbaImage::from_pixel(200, 100, Rgba { data: [255, 0, 0, 255] }),
+                delay: 10,
+            },
+            Frame {
+                image: RgbaImage::from_pixel(200, 100, Rgba { data: [0, 0, 255, 255] }),
+                delay: 20,
+            },
+        ];
+        let bytes = animation::encode(frames).unwrap();
+        let cpu_pool = CpuPool::new_num_cpus();
+        let image = ImageImpl::new(&cpu_pool);
+        let options = ProcessOptions {
+            animate: true,
+            ..Default::default()
+        };
+        let variants = image.process(InputFormat::Gif, bytes.clone(), options).wait().unwrap().variants;
+
+        assert_eq!(variants[&ImageSize::Thumb].format, OutputFormat::Png);
+        assert_eq!(variants[&ImageSize::Small].format, OutputFormat::Gif);
+        let small = animation::decode(&variants[&ImageSize::Small].bytes, animation::MAX_PIXELS).unwrap();
+        assert_eq!(small.len(), 2);
+        assert_eq!(small[0].image.dimensions(), (160, 80));
+        assert_eq!(small[1].delay, 20);
+        assert_eq!(variants[&ImageSize::Original].format, OutputFormat::Gif);
+        assert_eq!(variants[&ImageSize::Original].bytes, bytes);
+
+        // without `animate` option all variants are static
+        let variants = image
+            .process(InputFormat::Gif, bytes, ProcessOptions::default())
+            .wait()
+            .unwrap()
+            .variants;
+        assert_eq!(variants[&ImageSize::Small].format, OutputFormat::Png);
+    }
+
+    #[test]
+    fn test_image_process_keeps_original_format() {
+        let cpu_pool = CpuPool::new_num_cpus();
+        let image = ImageImpl::new(&cpu_pool);
+        for &(input_format, image_format, ref output_format) in &[
+            (InputFormat::Jpeg, ImageFormat::JPEG, OutputFormat::Jpeg),
+            (InputFormat::Gif, ImageFormat::GIF, OutputFormat::Gif),
+        ] {
+            let mut bytes = Vec::new();
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(50, 50, Rgba { data: [255, 0, 0, 255] }))
+                .write_to(&mut bytes, image_format)
+                .unwrap();
+            let variants = image
+                .process(input_format, bytes.clone(), ProcessOptions::default())
+                .wait()
+                .unwrap()
+                .variants;
+            assert_eq!(&variants[&ImageSize::Original].format, output_format);
+            assert_eq!(variants[&ImageSize::Original].bytes, bytes);
+        }
+    }
+
+    #[test]
+    fn test_image_process_fit_per_preset() {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(1000, 500, Rgba { data: [255, 0, 0, 255] }))
+            .write_to(&mut bytes, ImageFormat::PNG)
+            .unwrap();
+        let cpu_pool = CpuPool::new_num_cpus();
+        let image = ImageImpl::new(&cpu_pool);
+        let options = ProcessOptions {
+            fit: vec![(ImageSize::Thumb, Fit::Cover)].into_iter().collect(),
+            ..Default::default()
+        };
+        let variants = image.process(InputFormat::Png, bytes, options).wait().unwrap().variants;
+
+        let dimensions = |size: ImageSize| image::load_from_memory(&variants[&size].bytes).unwrap().dimensions();
+        assert_eq!(dimensions(ImageSize::Thumb), (40, 40));
+        assert_eq!(dimensions(ImageSize::Small), (160, 80));
+    }
+
+    #[test]
+    fn test_image_process_densities() {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(700, 700, Rgba { data: [255, 0, 0, 255] }))
+            .write_to(&mut bytes, ImageFormat::PNG)
+            .unwrap();
+        let cpu_pool = CpuPool::new_num_cpus();
+        let image = ImageImpl::new(&cpu_pool);
+        let options = ProcessOptions {
+            densities: vec![2, 3],
+            ..Default::default()
+        };
+        let dense_variants = image.process(InputFormat::Png, bytes, options).wait().unwrap().dense_variants;
+
+        let variant = |size: ImageSize, density: u32| dense_variants.get(&DenseVariant { size, density });
+        assert!(variant(ImageSize::Thumb, 3).is_some());
+        assert!(variant(ImageSize::Medium, 3).is_none());
+        assert!(variant(ImageSize::Large, 2).is_none());
+        let medium = image::load_from_memory(&variant(ImageSize::Medium, 2).unwrap().bytes).unwrap();
+        assert_eq!(medium.dimensions(), (640, 640));
+    }
+
+    #[test]
+    fn test_image_process_embed_profile() {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 50, Rgba { data: [255, 0, 0, 255] }))
+            .write_to(&mut bytes, ImageFormat::PNG)
+            .unwrap();
+        let bytes = icc::embed(&OutputFormat::Png, bytes, &icc::srgb_profile()).unwrap();
+        let cpu_pool = CpuPool::new_num_cpus();
+        let image = ImageImpl::new(&cpu_pool);
+        let process = |embed_profile: bool| {
+            let options = ProcessOptions {
+                embed_profile,
+                ..Default::default()
+            };
+            image.process(InputFormat::Png, bytes.clone(), options).wait().unwrap().variants
+        };
+
+        let variants = process(true);
+        assert_eq!(
+            icc::extract(&InputFormat::Png, &variants[&ImageSize::Small].bytes),
+            Some(icc::srgb_profile())
+        );
+        let variants = process(false);
+        assert_eq!(icc::extract(&InputFormat::Png, &variants[&ImageSize::Small].bytes), None);
+    }
+
+    #[cfg(feature = "bmp")]
+    #[test]
+    fn test_image_process_converts_bmp() {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 50, Rgba { data: [255, 0, 0, 255] }))
+            .write_to(&mut bytes, ImageFormat::BMP)
+            .unwrap();
+        let cpu_pool = CpuPool::new_num_cpus();
+        let image = ImageImpl::new(&cpu_pool);
+        let processed = image
+            .process(
+                InputFormat::Bmp,
+                bytes.clone(),
+                ProcessOptions {
+                    keep_source: true,
+                    ..Default::default()
+                },
+            )
+            .wait()
+            .unwrap();
+
+        let original = &processed.variants[&ImageSize::Original];
+        assert_eq!(original.format, OutputFormat::Png);
+        assert_eq!(image::guess_format(&original.bytes).unwrap(), ImageFormat::PNG);
+        assert_eq!(processed.source, Some(bytes));
+    }
+
+    #[cfg(feature = "svg")]
+    #[test]
+    fn test_image_process_svg() {
+        let svg =
+            br#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10" onclick="alert(1)"><rect width="20" height="10"/></svg>"#;
+        let cpu_pool = CpuPool::new_num_cpus();
+        let image = ImageImpl::new(&cpu_pool);
+        let variants = image
+            .process(InputFormat::Svg, svg.to_vec(), ProcessOptions::default())
+            .wait()
+            .unwrap()
+            .variants;
+
+        let original = &variants[&ImageSize::Original];
+        assert_eq!(original.format, OutputFormat::Svg);
+        assert!(!String::from_utf8_lossy(&original.bytes).contains("onclick"));
+        let large = &variants[&ImageSize::Large];
+        assert_eq!(large.format, OutputFormat::Png);
+        assert_eq!(image::load_from_memory(&large.bytes).unwrap().dimensions(), (1280, 640));
+    }
+
     fn read_static_file(name: &str) -> Vec<u8> {
         let mut file = File::open(format!("tests/static_files/{}", name)).unwrap();
         let mut buf = Vec::new();
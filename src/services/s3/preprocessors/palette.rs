@@ -0,0 +1,154 @@
+//! Dominant colour and palette extraction using median cut
+//! over a downscaled copy of the image
+
+use image::GenericImageView;
+use image::{DynamicImage, FilterType};
+
+use super::super::error::S3Error;
+use super::super::types::Colors;
+
+/// Max width and height of the downscaled copy used for palette extraction
+static SAMPLE_SIZE: u32 = 64;
+/// Number of colours in the palette
+static PALETTE_SIZE: usize = 5;
+/// Pixels with alpha below this value are ignored unless the whole image is transparent
+static ALPHA_THRESHOLD: u8 = 128;
+
+/// Box of pixels in RGB space used by median cut
+struct ColorBox {
+    pixels: Vec<[u8; 3]>,
+}
+
+impl ColorBox {
+    /// Returns the channel with the widest range of values along with the range
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..3)
+            .map(|channel| {
+                let min = self.pixels.iter().map(|p| p[channel]).min().unwrap_or(0);
+                let max = self.pixels.iter().map(|p| p[channel]).max().unwrap_or(0);
+                (channel, max - min)
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap_or((0, 0))
+    }
+
+    /// Splits the box in two at the median of the widest channel. Pixels with
+    /// the same channel value always end up in the same box.
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest_channel();
+        self.pixels.sort_by_key(|p| p[channel]);
+        let median = self.pixels[self.pixels.len() / 2][channel];
+        let index = match self.pixels.iter().position(|p| p[channel] >= median) {
+            Some(0) | None => self.pixels.iter().position(|p| p[channel] > median).unwrap_or(0),
+            Some(index) => index,
+        };
+        let upper = self.pixels.split_off(index);
+        (self, ColorBox { pixels: upper })
+    }
+
+    fn average(&self) -> [u8; 3] {
+        let len = self.pixels.len().max(1) as u64;
+        let mut sum = [0u64; 3];
+        for pixel in &self.pixels {
+            for channel in 0..3 {
+                sum[channel] += u64::from(pixel[channel]);
+            }
+        }
+        [(sum[0] / len) as u8, (sum[1] / len) as u8, (sum[2] / len) as u8]
+    }
+}
+
+/// Extracts dominant colour and palette from an image. Colours are sorted by the number
+/// of pixels they represent, so the first one in the palette is the dominant colour.
+///
+/// #Errors
+/// * `S3Error::Image` if image has zero dimensions
+pub fn extract(image: &DynamicImage) -> Result<Colors, S3Error> {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return Err(S3Error::Image("Uploaded image size is zero".to_string()));
+    }
+    let sample = if w > SAMPLE_SIZE || h > SAMPLE_SIZE {
+        image.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+    let rgba = sample.to_rgba();
+
+    let opaque_pixels = rgba
+        .pixels()
+        .filter(|p| p.data[3] >= ALPHA_THRESHOLD)
+        .map(|p| [p.data[0], p.data[1], p.data[2]])
+        .collect::<Vec<_>>();
+    let pixels = if opaque_pixels.is_empty() {
+        rgba.pixels().map(|p| [p.data[0], p.data[1], p.data[2]]).collect()
+    } else {
+        opaque_pixels
+    };
+
+    let mut boxes = median_cut(pixels, PALETTE_SIZE);
+    boxes.sort_by(|a, b| b.pixels.len().cmp(&a.pixels.len()));
+    let palette = boxes.iter().map(|b| to_hex(b.average())).collect::<Vec<_>>();
+    let dominant_color = palette[0].clone();
+
+    Ok(Colors { dominant_color, palette })
+}
+
+/// Splits pixels into at most `count` boxes of similar colours
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<ColorBox> {
+    let mut boxes = vec![ColorBox { pixels }];
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|&(_, b)| b.pixels.len() > 1)
+            .map(|(i, b)| (i, b.widest_channel().1))
+            .filter(|&(_, range)| range > 0)
+            .max_by_key(|&(_, range)| range);
+        let index = match widest {
+            Some((index, _)) => index,
+            None => break,
+        };
+        let (lower, upper) = boxes.swap_remove(index).split();
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+    boxes
+}
+
+fn to_hex(color: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn test_extract_two_colors() {
+        let buffer = ImageBuffer::from_fn(30, 10, |x, _| {
+            if x < 20 {
+                Rgba { data: [255, 0, 0, 255] }
+            } else {
+                Rgba { data: [0, 0, 255, 255] }
+            }
+        });
+        let colors = extract(&DynamicImage::ImageRgba8(buffer)).unwrap();
+        assert_eq!(colors.dominant_color, "#ff0000");
+        assert_eq!(colors.palette, vec!["#ff0000".to_string(), "#0000ff".to_string()]);
+    }
+
+    #[test]
+    fn test_extract_ignores_transparent_pixels() {
+        let buffer = ImageBuffer::from_fn(10, 10, |x, _| {
+            if x < 8 {
+                Rgba { data: [255, 255, 255, 0] }
+            } else {
+                Rgba { data: [0, 255, 0, 255] }
+            }
+        });
+        let colors = extract(&DynamicImage::ImageRgba8(buffer)).unwrap();
+        assert_eq!(colors.dominant_color, "#00ff00");
+    }
+}
@@ -0,0 +1,53 @@
+//! Low-quality placeholders (BlurHash and LQIP) that frontends render
+//! while the real image is loading
+
+use base64::encode;
+use image::GenericImageView;
+use image::{DynamicImage, FilterType, ImageFormat};
+
+use super::super::error::S3Error;
+use super::super::types::Placeholder;
+use super::blurhash;
+
+/// Width of the low-quality image placeholder in pixels
+static LQIP_WIDTH: u32 = 16;
+/// Width of the downscaled copy used for BlurHash encoding in pixels
+static BLURHASH_SAMPLE_WIDTH: u32 = 32;
+/// Number of BlurHash components along the x and y axes
+static BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Creates BlurHash and base64 encoded LQIP for an image
+///
+/// #Errors
+/// * `S3Error::Image` if image has zero dimensions or LQIP couldn't be encoded
+pub fn create(image: &DynamicImage) -> Result<Placeholder, S3Error> {
+    let blurhash = {
+        let sample = downscale(image, BLURHASH_SAMPLE_WIDTH)?;
+        let (x_components, y_components) = BLURHASH_COMPONENTS;
+        blurhash::encode(&sample.to_rgba(), x_components, y_components)
+    };
+
+    let lqip = {
+        let sample = downscale(image, LQIP_WIDTH)?;
+        let mut buffer = Vec::new();
+        sample
+            .write_to(&mut buffer, ImageFormat::PNG)
+            .map_err(|e| S3Error::Image(format!("Failed to encode placeholder: {}", e)))?;
+        format!("data:image/png;base64,{}", encode(&buffer))
+    };
+
+    Ok(Placeholder { blurhash, lqip })
+}
+
+/// Downscales an image to `width` keeping aspect ratio. Images narrower than `width` are left as is.
+fn downscale(image: &DynamicImage, width: u32) -> Result<DynamicImage, S3Error> {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return Err(S3Error::Image("Uploaded image size is zero".to_string()));
+    }
+    if w <= width {
+        return Ok(image.clone());
+    }
+    let height = ((h as f32) * (width as f32) / (w as f32)).round().max(1.0) as u32;
+    Ok(image.resize_exact(width, height, FilterType::Triangle))
+}
@@ -0,0 +1,348 @@
+//! Lossless optimization of PNG images: bit depth and palette reduction, filter strategies
+//! and compression levels
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use image::{DynamicImage, RgbaImage};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use super::super::error::S3Error;
+use super::super::types::PngOptimization;
+
+pub static PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Filter of a scanline
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Filter {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+static FILTERS: [Filter; 5] = [Filter::None, Filter::Sub, Filter::Up, Filter::Average, Filter::Paeth];
+
+/// How filters are chosen for scanlines
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Strategy {
+    /// The same filter for every scanline
+    Fixed(Filter),
+    /// Filter with the minimal sum of absolute values for every scanline
+    Adaptive,
+}
+
+/// Filter strategies and compression levels, the most promising first
+static TRIALS: [(Strategy, u32); 7] = [
+    (Strategy::Adaptive, 6),
+    (Strategy::Fixed(Filter::None), 9),
+    (Strategy::Adaptive, 9),
+    (Strategy::Fixed(Filter::Paeth), 9),
+    (Strategy::Fixed(Filter::Sub), 9),
+    (Strategy::Fixed(Filter::Up), 9),
+    (Strategy::Fixed(Filter::Average), 9),
+];
+
+/// PNG colour types
+static GRAYSCALE: u8 = 0;
+static TRUECOLOR: u8 = 2;
+static INDEXED: u8 = 3;
+static GRAYSCALE_ALPHA: u8 = 4;
+static TRUECOLOR_ALPHA: u8 = 6;
+
+/// Image in the smallest lossless PNG colour type and bit depth
+struct Reduced {
+    width: u32,
+    height: u32,
+    color_type: u8,
+    bit_depth: u8,
+    /// Palette entries for `INDEXED` colour type, non-opaque ones first
+    palette: Vec<[u8; 4]>,
+    /// Packed scanlines without filter bytes
+    lines: Vec<Vec<u8>>,
+    /// Bytes per complete pixel, at least 1
+    pixel_size: usize,
+}
+
+/// Encodes an image as PNG, trying filter strategies and compression levels allowed by `options.effort`
+/// until `options.time_budget_ms` is exceeded. Returns the smallest result.
+///
+/// #Errors
+/// * `S3Error::Image` if compression fails
+pub fn optimize(image: &DynamicImage, options: &PngOptimization) -> Result<Vec<u8>, S3Error> {
+    let started = Instant::now();
+    let budget = Duration::from_millis(options.time_budget_ms);
+    let reduced = reduce(&image.to_rgba());
+    let trials = match options.effort {
+        0 | 1 => 1,
+        2 => 3,
+        _ => TRIALS.len(),
+    };
+    let mut best: Option<Vec<u8>> = None;
+    for &(strategy, level) in TRIALS.iter().take(trials) {
+        let encoded = encode(&reduced, strategy, level)?;
+        if best.as_ref().map_or(true, |best| encoded.len() < best.len()) {
+            best = Some(encoded);
+        }
+        if started.elapsed() >= budget {
+            break;
+        }
+    }
+    best.ok_or_else(|| S3Error::Image("No png encoding strategies were tried".to_string()))
+}
+
+/// Appends PNG chunk of `kind` type with `data` and its checksum
+pub fn write_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    push_u32(png, data.len() as u32);
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    push_u32(png, crc.sum());
+}
+
+/// Picks the smallest colour type and bit depth that represent the image exactly
+fn reduce(image: &RgbaImage) -> Reduced {
+    let (width, height) = image.dimensions();
+    let opaque = image.pixels().all(|pixel| pixel.data[3] == 255);
+    let gray = image
+        .pixels()
+        .all(|pixel| pixel.data[0] == pixel.data[1] && pixel.data[1] == pixel.data[2]);
+    let mut colors = HashMap::new();
+    for pixel in image.pixels() {
+        if colors.len() > 256 {
+            break;
+        }
+        let next = colors.len();
+        colors.entry(pixel.data).or_insert(next);
+    }
+
+    let row = |y: u32| (0..width).map(move |x| image.get_pixel(x, y).data);
+    if gray && (opaque || colors.len() > 256) {
+        if !opaque {
+            let lines = (0..height)
+                .map(|y| row(y).flat_map(|data| vec![data[0], data[3]]).collect())
+                .collect();
+            return Reduced::new(width, height, GRAYSCALE_ALPHA, 8, Vec::new(), lines, 2);
+        }
+        // samples of lower bit depths are scaled to 0..255 range
+        let bit_depth = [1, 2, 4]
+            .iter()
+            .cloned()
+            .find(|&depth| {
+                let step = 255 / ((1u16 << depth) - 1) as u8;
+                image.pixels().all(|pixel| pixel.data[0] % step == 0)
+            })
+            .unwrap_or(8);
+        let step = 255 / ((1u16 << bit_depth) - 1) as u8;
+        let lines = (0..height)
+            .map(|y| pack(&row(y).map(|data| data[0] / step).collect::<Vec<_>>(), bit_depth))
+            .collect();
+        return Reduced::new(width, height, GRAYSCALE, bit_depth, Vec::new(), lines, 1);
+    }
+    if colors.len() <= 256 {
+        let mut palette = colors.keys().cloned().collect::<Vec<_>>();
+        // transparency chunk ends with the last non-opaque entry
+        palette.sort_by_key(|color| (color[3] == 255, *color));
+        let indices = palette
+            .iter()
+            .enumerate()
+            .map(|(index, color)| (*color, index as u8))
+            .collect::<HashMap<_, _>>();
+        let bit_depth = match palette.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        };
+        let lines = (0..height)
+            .map(|y| pack(&row(y).map(|data| indices[&data]).collect::<Vec<_>>(), bit_depth))
+            .collect();
+        return Reduced::new(width, height, INDEXED, bit_depth, palette, lines, 1);
+    }
+    if opaque {
+        let lines = (0..height).map(|y| row(y).flat_map(|data| data[..3].to_vec()).collect()).collect();
+        Reduced::new(width, height, TRUECOLOR, 8, Vec::new(), lines, 3)
+    } else {
+        let lines = (0..height).map(|y| row(y).flat_map(|data| data.to_vec()).collect()).collect();
+        Reduced::new(width, height, TRUECOLOR_ALPHA, 8, Vec::new(), lines, 4)
+    }
+}
+
+impl Reduced {
+    fn new(width: u32, height: u32, color_type: u8, bit_depth: u8, palette: Vec<[u8; 4]>, lines: Vec<Vec<u8>>, pixel_size: usize) -> Self {
+        Self {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            palette,
+            lines,
+            pixel_size,
+        }
+    }
+}
+
+/// Packs samples of `bit_depth` bits, the leftmost pixel in the high-order bits of a byte
+fn pack(samples: &[u8], bit_depth: u8) -> Vec<u8> {
+    if bit_depth == 8 {
+        return samples.to_vec();
+    }
+    let per_byte = (8 / bit_depth) as usize;
+    samples
+        .chunks(per_byte)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (i, &sample)| byte | (sample << (8 - bit_depth as usize * (i + 1))))
+        })
+        .collect()
+}
+
+fn encode(reduced: &Reduced, strategy: Strategy, level: u32) -> Result<Vec<u8>, S3Error> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    let empty = vec![0; reduced.lines.first().map_or(0, |line| line.len())];
+    let mut previous = &empty;
+    for line in &reduced.lines {
+        let filtered = match strategy {
+            Strategy::Fixed(filter) => apply_filter(filter, line, previous, reduced.pixel_size),
+            Strategy::Adaptive => FILTERS
+                .iter()
+                .map(|&filter| apply_filter(filter, line, previous, reduced.pixel_size))
+                .min_by_key(|filtered| filtered.iter().map(|&byte| i16::from(byte as i8).abs() as u64).sum::<u64>())
+                .unwrap_or_default(),
+        };
+        encoder
+            .write_all(&filtered)
+            .map_err(|e| S3Error::Image(format!("Error compressing png: {}", e)))?;
+        previous = line;
+    }
+    let data = encoder
+        .finish()
+        .map_err(|e| S3Error::Image(format!("Error compressing png: {}", e)))?;
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    let mut header = Vec::with_capacity(13);
+    push_u32(&mut header, reduced.width);
+    push_u32(&mut header, reduced.height);
+    // deflate compression, adaptive filtering, no interlace
+    header.extend_from_slice(&[reduced.bit_depth, reduced.color_type, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+    if !reduced.palette.is_empty() {
+        let colors = reduced.palette.iter().flat_map(|color| color[..3].to_vec()).collect::<Vec<_>>();
+        write_chunk(&mut png, b"PLTE", &colors);
+        let alphas = reduced
+            .palette
+            .iter()
+            .map(|color| color[3])
+            .take_while(|&alpha| alpha != 255)
+            .collect::<Vec<_>>();
+        if !alphas.is_empty() {
+            write_chunk(&mut png, b"tRNS", &alphas);
+        }
+    }
+    write_chunk(&mut png, b"IDAT", &data);
+    write_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+/// Filters a scanline, the result starts with the filter type
+fn apply_filter(filter: Filter, line: &[u8], previous: &[u8], pixel_size: usize) -> Vec<u8> {
+    let mut filtered = Vec::with_capacity(line.len() + 1);
+    filtered.push(filter as u8);
+    for (i, (&x, &b)) in line.iter().zip(previous).enumerate() {
+        let a = if i >= pixel_size { line[i - pixel_size] } else { 0 };
+        let c = if i >= pixel_size { previous[i - pixel_size] } else { 0 };
+        let predicted = match filter {
+            Filter::None => 0,
+            Filter::Sub => a,
+            Filter::Up => b,
+            Filter::Average => ((u16::from(a) + u16::from(b)) / 2) as u8,
+            Filter::Paeth => paeth(a, b, c),
+        };
+        filtered.push(x.wrapping_sub(predicted));
+    }
+    filtered
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image;
+    use image::{ImageFormat, Rgba};
+
+    fn options() -> PngOptimization {
+        PngOptimization {
+            effort: 3,
+            time_budget_ms: 10_000,
+        }
+    }
+
+    fn assert_lossless(image: RgbaImage, color_type: u8, bit_depth: u8) {
+        let optimized = optimize(&DynamicImage::ImageRgba8(image.clone()), &options()).unwrap();
+        // bit depth and colour type follow the signature and IHDR length, type, width and height
+        assert_eq!(&optimized[24..26], &[bit_depth, color_type]);
+        let decoded = image::load_from_memory_with_format(&optimized, ImageFormat::PNG).unwrap().to_rgba();
+        assert_eq!(decoded.into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn test_optimize_palette() {
+        let image = RgbaImage::from_fn(10, 7, |x, y| {
+            if (x + y) % 3 == 0 {
+                Rgba { data: [255, 0, 0, 128] }
+            } else {
+                Rgba { data: [0, 0, 255, 255] }
+            }
+        });
+        assert_lossless(image, INDEXED, 1);
+    }
+
+    #[test]
+    fn test_optimize_grayscale() {
+        let image = RgbaImage::from_fn(9, 5, |x, _| {
+            let value = (x % 4) as u8 * 85;
+            Rgba {
+                data: [value, value, value, 255],
+            }
+        });
+        assert_lossless(image, GRAYSCALE, 2);
+    }
+
+    #[test]
+    fn test_optimize_truecolor() {
+        let image = RgbaImage::from_fn(30, 20, |x, y| Rgba {
+            data: [(x * 8) as u8, (y * 12) as u8, (x * y) as u8, 255],
+        });
+        assert_lossless(image.clone(), TRUECOLOR, 8);
+
+        let mut default = Vec::new();
+        DynamicImage::ImageRgba8(image.clone())
+            .write_to(&mut default, ImageFormat::PNG)
+            .unwrap();
+        let optimized = optimize(&DynamicImage::ImageRgba8(image), &options()).unwrap();
+        assert!(optimized.len() < default.len());
+    }
+}
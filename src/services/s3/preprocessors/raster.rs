@@ -0,0 +1,776 @@
+//! Scanline rasterizer for SVG trees parsed by `usvg`
+//!
+//! Renders filled and stroked paths with solid colors, linear and radial gradients
+//! and group opacity. Text, embedded images, patterns, markers, clip paths, masks
+//! and filters are not rendered, see `unsupported_feature`.
+
+use std::f64::consts::PI;
+
+use image::{Rgba, RgbaImage};
+use usvg;
+use usvg::{NodeKind, Paint, PathSegment, Transform};
+
+/// Number of sub-scanlines sampled per pixel row
+static SUBSAMPLES: usize = 4;
+/// Max distance in pixels between a curve and its flattened polyline
+static TOLERANCE: f64 = 0.2;
+
+/// Point in user or device space
+type Point = (f64, f64);
+
+/// Polyline of a subpath after flattening curves
+struct Subpath {
+    points: Vec<Point>,
+    closed: bool,
+}
+
+/// Premultiplied RGBA canvas with components in `0.0..=1.0`
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 4]>,
+}
+
+/// Color source of a filled area
+enum Shader {
+    Solid([f32; 4]),
+    Gradient(Gradient),
+}
+
+struct Gradient {
+    kind: GradientKind,
+    /// Maps device space to gradient space
+    inverse: Transform,
+    spread: usvg::SpreadMethod,
+    /// Offsets and non-premultiplied colors of stops
+    stops: Vec<(f64, [f32; 4])>,
+}
+
+enum GradientKind {
+    Linear { start: Point, end: Point },
+    Radial { center: Point, radius: f64 },
+}
+
+/// Edge of a polygon in device space with `y0 < y1`
+struct Edge {
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+    winding: i32,
+}
+
+/// Renders SVG tree into an image of `width`x`height` pixels, stretching its view box
+pub fn render(tree: &usvg::Tree, width: u32, height: u32) -> RgbaImage {
+    let svg = tree.svg_node();
+    let view_box = usvg::utils::view_box_to_transform(svg.view_box.rect, svg.view_box.aspect, svg.size);
+    let scale = Transform::new_scale(f64::from(width) / svg.size.width, f64::from(height) / svg.size.height);
+    let transform = concat(&scale, &view_box);
+
+    let mut canvas = Canvas::new(width as usize, height as usize);
+    render_group(tree, &tree.root(), &transform, &mut canvas);
+
+    let mut image = RgbaImage::new(width, height);
+    for (pixel, target) in canvas.pixels.iter().zip(image.pixels_mut()) {
+        let alpha = pixel[3];
+        let channel = |value: f32| {
+            let value = if alpha > 0.0 { value / alpha } else { 0.0 };
+            (value.max(0.0).min(1.0) * 255.0).round() as u8
+        };
+        *target = Rgba {
+            data: [
+                channel(pixel[0]),
+                channel(pixel[1]),
+                channel(pixel[2]),
+                (alpha.max(0.0).min(1.0) * 255.0).round() as u8,
+            ],
+        };
+    }
+    image
+}
+
+/// Name of the first feature used by SVG tree that `render` can't draw, e.g. `text`
+pub fn unsupported_feature(tree: &usvg::Tree) -> Option<&'static str> {
+    tree.root()
+        .descendants()
+        .filter(|node| !tree.is_in_defs(node))
+        .find_map(|node| match *node.borrow() {
+            NodeKind::Text(_) => Some("text"),
+            NodeKind::Image(_) => Some("embedded images"),
+            NodeKind::Group(ref group) if group.clip_path.is_some() => Some("clip paths"),
+            NodeKind::Group(ref group) if group.mask.is_some() => Some("masks"),
+            NodeKind::Group(ref group) if group.filter.is_some() => Some("filters"),
+            NodeKind::Path(ref path) if path.marker.start.is_some() || path.marker.mid.is_some() || path.marker.end.is_some() => {
+                Some("markers")
+            }
+            NodeKind::Path(ref path) => {
+                let fill = path.fill.as_ref().map(|fill| &fill.paint);
+                let stroke = path.stroke.as_ref().map(|stroke| &stroke.paint);
+                let is_supported = |paint: &Paint| match *paint {
+                    Paint::Color(_) => true,
+                    Paint::Link(ref id) => tree.defs_by_id(id).map_or(false, |node| match *node.borrow() {
+                        NodeKind::LinearGradient(_) | NodeKind::RadialGradient(_) => true,
+                        _ => false,
+                    }),
+                };
+                if fill.into_iter().chain(stroke).all(is_supported) {
+                    None
+                } else {
+                    Some("patterns")
+                }
+            }
+            _ => None,
+        })
+}
+
+fn render_group(tree: &usvg::Tree, node: &usvg::Node, transform: &Transform, canvas: &mut Canvas) {
+    for child in node.children() {
+        match *child.borrow() {
+            NodeKind::Path(ref path) => render_path(tree, path, &concat(transform, &path.transform), canvas),
+            NodeKind::Group(ref group) => {
+                let transform = concat(transform, &group.transform);
+                let opacity = group.opacity.map_or(1.0, |opacity| opacity.value());
+                if opacity < 1.0 {
+                    let mut layer = Canvas::new(canvas.width, canvas.height);
+                    render_group(tree, &child, &transform, &mut layer);
+                    canvas.draw_layer(&layer, opacity as f32);
+                } else {
+                    render_group(tree, &child, &transform, canvas);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn render_path(tree: &usvg::Tree, path: &usvg::Path, transform: &Transform, canvas: &mut Canvas) {
+    if path.visibility != usvg::Visibility::Visible {
+        return;
+    }
+    let (sx, sy) = transform.get_scale();
+    let scale = (sx * sy).abs().sqrt();
+    if scale <= 0.0 || !scale.is_finite() {
+        return;
+    }
+    let subpaths = flatten(&path.segments, TOLERANCE / scale);
+    let bbox = bounding_box(&subpaths);
+
+    if let Some(ref fill) = path.fill {
+        if let Some(shader) = shader(tree, &fill.paint, fill.opacity.value(), bbox, transform) {
+            let polygons = subpaths.iter().map(|subpath| subpath.points.clone()).collect::<Vec<_>>();
+            let even_odd = fill.rule == usvg::FillRule::EvenOdd;
+            canvas.fill(&edges(&polygons, transform, false), even_odd, &shader);
+        }
+    }
+
+    if let Some(ref stroke) = path.stroke {
+        if let Some(shader) = shader(tree, &stroke.paint, stroke.opacity.value(), bbox, transform) {
+            let subpaths = match stroke.dasharray {
+                Some(ref dasharray) => dash(&subpaths, dasharray, stroke.dashoffset.into()),
+                None => subpaths,
+            };
+            let polygons = stroke_polygons(&subpaths, stroke, TOLERANCE / scale);
+            canvas.fill(&edges(&polygons, transform, true), false, &shader);
+        }
+    }
+}
+
+/// Shader for a paint with opacity, `None` if paint is unsupported or fully transparent
+fn shader(tree: &usvg::Tree, paint: &Paint, opacity: f64, bbox: Option<(Point, Point)>, transform: &Transform) -> Option<Shader> {
+    let id = match *paint {
+        Paint::Color(color) => {
+            let alpha = opacity as f32;
+            return if alpha > 0.0 {
+                Some(Shader::Solid([
+                    f32::from(color.red) / 255.0 * alpha,
+                    f32::from(color.green) / 255.0 * alpha,
+                    f32::from(color.blue) / 255.0 * alpha,
+                    alpha,
+                ]))
+            } else {
+                None
+            };
+        }
+        Paint::Link(ref id) => id,
+    };
+    let node = tree.defs_by_id(id)?;
+    let kind = node.borrow();
+    let (kind, base) = match *kind {
+        NodeKind::LinearGradient(ref gradient) => (
+            GradientKind::Linear {
+                start: (gradient.x1, gradient.y1),
+                end: (gradient.x2, gradient.y2),
+            },
+            &gradient.base,
+        ),
+        NodeKind::RadialGradient(ref gradient) => (
+            GradientKind::Radial {
+                center: (gradient.cx, gradient.cy),
+                radius: gradient.r.value(),
+            },
+            &gradient.base,
+        ),
+        _ => return None,
+    };
+    let mut gradient_transform = *transform;
+    if base.units == usvg::Units::ObjectBoundingBox {
+        let ((x0, y0), (x1, y1)) = bbox?;
+        if x1 - x0 <= 0.0 || y1 - y0 <= 0.0 {
+            return None;
+        }
+        gradient_transform = concat(&gradient_transform, &Transform::new(x1 - x0, 0.0, 0.0, y1 - y0, x0, y0));
+    }
+    gradient_transform = concat(&gradient_transform, &base.transform);
+    let stops = base
+        .stops
+        .iter()
+        .map(|stop| {
+            let color = [
+                f32::from(stop.color.red) / 255.0,
+                f32::from(stop.color.green) / 255.0,
+                f32::from(stop.color.blue) / 255.0,
+                (stop.opacity.value() * opacity) as f32,
+            ];
+            (stop.offset.value(), color)
+        })
+        .collect::<Vec<_>>();
+    if stops.is_empty() {
+        return None;
+    }
+    Some(Shader::Gradient(Gradient {
+        kind,
+        inverse: invert(&gradient_transform)?,
+        spread: base.spread_method,
+        stops,
+    }))
+}
+
+impl Shader {
+    /// Premultiplied color at the center of a pixel
+    fn color(&self, x: usize, y: usize) -> [f32; 4] {
+        match *self {
+            Shader::Solid(color) => color,
+            Shader::Gradient(ref gradient) => gradient.color(x as f64 + 0.5, y as f64 + 0.5),
+        }
+    }
+}
+
+impl Gradient {
+    fn color(&self, x: f64, y: f64) -> [f32; 4] {
+        let (x, y) = self.inverse.apply(x, y);
+        let t = match self.kind {
+            GradientKind::Linear { start, end } => {
+                let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+                let length = dx * dx + dy * dy;
+                if length > 0.0 {
+                    ((x - start.0) * dx + (y - start.1) * dy) / length
+                } else {
+                    1.0
+                }
+            }
+            GradientKind::Radial { center, radius } => {
+                if radius > 0.0 {
+                    ((x - center.0).powi(2) + (y - center.1).powi(2)).sqrt() / radius
+                } else {
+                    1.0
+                }
+            }
+        };
+        let t = match self.spread {
+            usvg::SpreadMethod::Pad => t.max(0.0).min(1.0),
+            usvg::SpreadMethod::Repeat => t - t.floor(),
+            usvg::SpreadMethod::Reflect => {
+                let t = (t % 2.0 + 2.0) % 2.0;
+                if t > 1.0 {
+                    2.0 - t
+                } else {
+                    t
+                }
+            }
+        };
+
+        let last = self.stops.len() - 1;
+        let color = if t <= self.stops[0].0 {
+            self.stops[0].1
+        } else if t >= self.stops[last].0 {
+            self.stops[last].1
+        } else {
+            let i = self.stops.iter().position(|&(offset, _)| offset > t).unwrap_or(last);
+            let (offset0, color0) = self.stops[i - 1];
+            let (offset1, color1) = self.stops[i];
+            let k = if offset1 > offset0 {
+                ((t - offset0) / (offset1 - offset0)) as f32
+            } else {
+                1.0
+            };
+            let mut color = [0.0; 4];
+            for (c, (c0, c1)) in color.iter_mut().zip(color0.iter().zip(color1.iter())) {
+                *c = c0 + (c1 - c0) * k;
+            }
+            color
+        };
+        [color[0] * color[3], color[1] * color[3], color[2] * color[3], color[3]]
+    }
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; width * height],
+        }
+    }
+
+    /// Composites `source` with coverage `alpha` over pixel at `index`
+    fn blend(&mut self, index: usize, source: [f32; 4], alpha: f32) {
+        let pixel = &mut self.pixels[index];
+        let inverse = 1.0 - source[3] * alpha;
+        for (target, source) in pixel.iter_mut().zip(source.iter()) {
+            *target = source * alpha + *target * inverse;
+        }
+    }
+
+    fn draw_layer(&mut self, layer: &Canvas, opacity: f32) {
+        for index in 0..self.pixels.len() {
+            if layer.pixels[index][3] > 0.0 {
+                self.blend(index, layer.pixels[index], opacity);
+            }
+        }
+    }
+
+    /// Fills polygon given by its edges with antialiasing: coverage is sampled
+    /// on `SUBSAMPLES` sub-scanlines and computed exactly along them
+    fn fill(&mut self, edges: &[Edge], even_odd: bool, shader: &Shader) {
+        let mut edges = edges
+            .iter()
+            .filter(|edge| edge.y1 > 0.0 && edge.y0 < self.height as f64)
+            .collect::<Vec<_>>();
+        if edges.is_empty() {
+            return;
+        }
+        edges.sort_by(|a, b| a.y0.partial_cmp(&b.y0).unwrap_or(::std::cmp::Ordering::Equal));
+        let first_row = edges[0].y0.max(0.0).floor() as usize;
+        let last_row = edges
+            .iter()
+            .fold(0.0f64, |max, edge| max.max(edge.y1))
+            .min(self.height as f64)
+            .ceil() as usize;
+
+        let width = self.width as f64;
+        let weight = 1.0 / SUBSAMPLES as f32;
+        let mut coverage = vec![0.0f32; self.width + 1];
+        let mut active: Vec<&Edge> = Vec::new();
+        let mut crossings: Vec<(f64, i32)> = Vec::new();
+        let mut next = 0;
+        for row in first_row..last_row {
+            for value in coverage.iter_mut() {
+                *value = 0.0;
+            }
+            let (mut min_x, mut max_x) = (self.width, 0);
+            for sample in 0..SUBSAMPLES {
+                let y = row as f64 + (sample as f64 + 0.5) / SUBSAMPLES as f64;
+                while next < edges.len() && edges[next].y0 <= y {
+                    active.push(edges[next]);
+                    next += 1;
+                }
+                active.retain(|edge| edge.y1 > y);
+
+                crossings.clear();
+                crossings.extend(active.iter().filter(|edge| edge.y0 <= y).map(|edge| {
+                    let x = edge.x0 + (y - edge.y0) / (edge.y1 - edge.y0) * (edge.x1 - edge.x0);
+                    (x, edge.winding)
+                }));
+                crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+
+                let mut winding = 0;
+                for pair in crossings.windows(2) {
+                    winding += pair[0].1;
+                    let inside = if even_odd { winding % 2 != 0 } else { winding != 0 };
+                    if !inside {
+                        continue;
+                    }
+                    let (start, end) = (pair[0].0.max(0.0).min(width), pair[1].0.max(0.0).min(width));
+                    if end <= start {
+                        continue;
+                    }
+                    let (first, last) = (start as usize, end as usize);
+                    if first == last {
+                        coverage[first] += (end - start) as f32 * weight;
+                    } else {
+                        coverage[first] += (first as f64 + 1.0 - start) as f32 * weight;
+                        for value in &mut coverage[first + 1..last] {
+                            *value += weight;
+                        }
+                        coverage[last] += (end - last as f64) as f32 * weight;
+                    }
+                    min_x = min_x.min(first);
+                    max_x = max_x.max(last + 1);
+                }
+            }
+
+            for x in min_x..max_x.min(self.width) {
+                let alpha = coverage[x].min(1.0);
+                if alpha > 0.0 {
+                    let index = row * self.width + x;
+                    self.blend(index, shader.color(x, row), alpha);
+                }
+            }
+        }
+    }
+}
+
+/// Transform applying `inner` first and then `outer`
+fn concat(outer: &Transform, inner: &Transform) -> Transform {
+    Transform::new(
+        outer.a * inner.a + outer.c * inner.b,
+        outer.b * inner.a + outer.d * inner.b,
+        outer.a * inner.c + outer.c * inner.d,
+        outer.b * inner.c + outer.d * inner.d,
+        outer.a * inner.e + outer.c * inner.f + outer.e,
+        outer.b * inner.e + outer.d * inner.f + outer.f,
+    )
+}
+
+fn invert(ts: &Transform) -> Option<Transform> {
+    let det = ts.a * ts.d - ts.b * ts.c;
+    if det == 0.0 || !det.is_finite() {
+        return None;
+    }
+    Some(Transform::new(
+        ts.d / det,
+        -ts.b / det,
+        -ts.c / det,
+        ts.a / det,
+        (ts.c * ts.f - ts.d * ts.e) / det,
+        (ts.b * ts.e - ts.a * ts.f) / det,
+    ))
+}
+
+/// Converts path segments into polylines, approximating curves within `tolerance`
+fn flatten(segments: &[PathSegment], tolerance: f64) -> Vec<Subpath> {
+    let mut subpaths = Vec::new();
+    let mut points: Vec<Point> = Vec::new();
+    let mut current = (0.0, 0.0);
+    let finish = |points: &mut Vec<Point>, closed: bool, subpaths: &mut Vec<Subpath>| {
+        if points.len() > 1 {
+            subpaths.push(Subpath {
+                points: points.clone(),
+                closed,
+            });
+        }
+        let start = points.first().cloned();
+        points.clear();
+        start
+    };
+    for segment in segments {
+        match *segment {
+            PathSegment::MoveTo { x, y } => {
+                finish(&mut points, false, &mut subpaths);
+                current = (x, y);
+                points.push(current);
+            }
+            PathSegment::LineTo { x, y } => {
+                current = (x, y);
+                push_point(&mut points, current);
+            }
+            PathSegment::CurveTo { x1, y1, x2, y2, x, y } => {
+                let (p0, p1, p2, p3) = (current, (x1, y1), (x2, y2), (x, y));
+                let length = distance(p0, p1) + distance(p1, p2) + distance(p2, p3);
+                let steps = ((length / tolerance).sqrt().ceil() as usize).max(1).min(1000);
+                for step in 1..=steps {
+                    let t = step as f64 / steps as f64;
+                    let u = 1.0 - t;
+                    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+                    push_point(
+                        &mut points,
+                        (a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0, a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1),
+                    );
+                }
+                current = p3;
+            }
+            PathSegment::ClosePath => {
+                if let Some(start) = finish(&mut points, true, &mut subpaths) {
+                    current = start;
+                    points.push(start);
+                }
+            }
+        }
+    }
+    finish(&mut points, false, &mut subpaths);
+    subpaths
+}
+
+/// Appends point to polyline unless it repeats the last one
+fn push_point(points: &mut Vec<Point>, point: Point) {
+    if points.last().map_or(true, |&last| last != point) {
+        points.push(point);
+    }
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+fn bounding_box(subpaths: &[Subpath]) -> Option<(Point, Point)> {
+    subpaths
+        .iter()
+        .flat_map(|subpath| subpath.points.iter())
+        .fold(None, |bbox, &(x, y)| match bbox {
+            None => Some(((x, y), (x, y))),
+            Some(((x0, y0), (x1, y1))) => Some(((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y)))),
+        })
+}
+
+/// Edges of closed polygons in device space. With `normalize` every polygon is
+/// oriented clockwise so that overlapping polygons add up under nonzero rule.
+fn edges(polygons: &[Vec<Point>], transform: &Transform, normalize: bool) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    for polygon in polygons {
+        let points = polygon.iter().map(|&(x, y)| transform.apply(x, y)).collect::<Vec<_>>();
+        let area: f64 = (0..points.len())
+            .map(|i| {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                a.0 * b.1 - b.0 * a.1
+            })
+            .sum();
+        let flip = normalize && area < 0.0;
+        for i in 0..points.len() {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            if a.1 == b.1 || !(a.0.is_finite() && a.1.is_finite() && b.0.is_finite() && b.1.is_finite()) {
+                continue;
+            }
+            let winding = if (a.1 < b.1) != flip { 1 } else { -1 };
+            let (top, bottom) = if a.1 < b.1 { (a, b) } else { (b, a) };
+            edges.push(Edge {
+                x0: top.0,
+                y0: top.1,
+                x1: bottom.0,
+                y1: bottom.1,
+                winding,
+            });
+        }
+    }
+    edges
+}
+
+/// Splits subpaths into open dashes
+fn dash(subpaths: &[Subpath], dasharray: &[f64], offset: f64) -> Vec<Subpath> {
+    let mut pattern = dasharray.to_vec();
+    if pattern.len() % 2 == 1 {
+        pattern.extend_from_slice(dasharray);
+    }
+    let period: f64 = pattern.iter().sum();
+    if period <= 0.0 || pattern.iter().any(|&length| length < 0.0) {
+        return subpaths
+            .iter()
+            .map(|subpath| Subpath {
+                points: subpath.points.clone(),
+                closed: subpath.closed,
+            })
+            .collect();
+    }
+
+    let mut dashes = Vec::new();
+    for subpath in subpaths {
+        let mut points = subpath.points.clone();
+        if subpath.closed {
+            let first = points[0];
+            points.push(first);
+        }
+        // position inside the dash pattern
+        let mut index = 0;
+        let mut left = pattern[0];
+        let mut skip = (offset % period + period) % period;
+        while skip > 0.0 {
+            if skip < left {
+                left -= skip;
+                break;
+            }
+            skip -= left;
+            index = (index + 1) % pattern.len();
+            left = pattern[index];
+        }
+
+        let mut dash = if index % 2 == 0 { vec![points[0]] } else { Vec::new() };
+        for pair in points.windows(2) {
+            let (mut start, end) = (pair[0], pair[1]);
+            let mut length = distance(start, end);
+            while length > left {
+                let k = left / length;
+                let point = (start.0 + (end.0 - start.0) * k, start.1 + (end.1 - start.1) * k);
+                if index % 2 == 0 {
+                    dash.push(point);
+                    dashes.push(Subpath {
+                        points: dash.split_off(0),
+                        closed: false,
+                    });
+                } else {
+                    dash.push(point);
+                }
+                length -= left;
+                start = point;
+                index = (index + 1) % pattern.len();
+                left = pattern[index];
+            }
+            left -= length;
+            if index % 2 == 0 {
+                push_point(&mut dash, end);
+            }
+        }
+        if index % 2 == 0 && dash.len() > 1 {
+            dashes.push(Subpath {
+                points: dash,
+                closed: false,
+            });
+        }
+    }
+    dashes
+}
+
+/// Outline of a stroke as a set of polygons: a quad per segment plus joins and caps
+fn stroke_polygons(subpaths: &[Subpath], stroke: &usvg::Stroke, tolerance: f64) -> Vec<Vec<Point>> {
+    let half = stroke.width.value() / 2.0;
+    let mut polygons = Vec::new();
+    if half <= 0.0 {
+        return polygons;
+    }
+    for subpath in subpaths {
+        let mut points = subpath.points.clone();
+        if subpath.closed && points.len() > 2 {
+            let first = points[0];
+            push_point(&mut points, first);
+        }
+        if points.len() < 2 {
+            continue;
+        }
+        let segments = points.windows(2).map(|pair| (pair[0], pair[1])).collect::<Vec<_>>();
+        for &(a, b) in &segments {
+            let n = normal(a, b, half);
+            polygons.push(vec![
+                (a.0 + n.0, a.1 + n.1),
+                (b.0 + n.0, b.1 + n.1),
+                (b.0 - n.0, b.1 - n.1),
+                (a.0 - n.0, a.1 - n.1),
+            ]);
+        }
+        let mut joins = segments.windows(2).map(|pair| (pair[0], pair[1])).collect::<Vec<_>>();
+        if subpath.closed && segments.len() > 1 {
+            joins.push((segments[segments.len() - 1], segments[0]));
+        } else {
+            let (first, last) = (segments[0], segments[segments.len() - 1]);
+            polygons.extend(cap(first.1, first.0, stroke.linecap, half, tolerance));
+            polygons.extend(cap(last.0, last.1, stroke.linecap, half, tolerance));
+        }
+        for ((a, b), (_, c)) in joins {
+            polygons.extend(join(a, b, c, stroke, half, tolerance));
+        }
+    }
+    polygons
+}
+
+/// Normal of segment `a`-`b` with length `half`
+fn normal(a: Point, b: Point, half: f64) -> Point {
+    let length = distance(a, b);
+    ((a.1 - b.1) / length * half, (b.0 - a.0) / length * half)
+}
+
+/// Cap at `end` of a segment coming from `start`
+fn cap(start: Point, end: Point, linecap: usvg::LineCap, half: f64, tolerance: f64) -> Option<Vec<Point>> {
+    match linecap {
+        usvg::LineCap::Butt => None,
+        usvg::LineCap::Round => Some(circle(end, half, tolerance)),
+        usvg::LineCap::Square => {
+            let n = normal(start, end, half);
+            let d = (-n.1, n.0);
+            let (p, q) = ((end.0 + n.0, end.1 + n.1), (end.0 - n.0, end.1 - n.1));
+            Some(vec![p, (p.0 - d.0, p.1 - d.1), (q.0 - d.0, q.1 - d.1), q])
+        }
+    }
+}
+
+/// Join at vertex `b` between segments `a`-`b` and `b`-`c`
+fn join(a: Point, b: Point, c: Point, stroke: &usvg::Stroke, half: f64, tolerance: f64) -> Option<Vec<Point>> {
+    let (n0, n1) = (normal(a, b, half), normal(b, c, half));
+    let cross = (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0);
+    if cross == 0.0 && n0.0 * n1.0 + n0.1 * n1.1 > 0.0 {
+        return None;
+    }
+    // the outer side of the turn is opposite to the direction of the turn
+    let side = if cross > 0.0 { -1.0 } else { 1.0 };
+    let p0 = (b.0 + n0.0 * side, b.1 + n0.1 * side);
+    let p1 = (b.0 + n1.0 * side, b.1 + n1.1 * side);
+    match stroke.linejoin {
+        usvg::LineJoin::Round => Some(circle(b, half, tolerance)),
+        usvg::LineJoin::Bevel => Some(vec![b, p0, p1]),
+        usvg::LineJoin::Miter => {
+            let cos = (n0.0 * n1.0 + n0.1 * n1.1) / (half * half);
+            let ratio = 1.0 / ((1.0 + cos) / 2.0).sqrt();
+            if ratio.is_finite() && ratio <= stroke.miterlimit.value() {
+                let (mx, my) = (n0.0 + n1.0, n0.1 + n1.1);
+                let length = (mx * mx + my * my).sqrt();
+                let tip = (b.0 + mx / length * half * ratio * side, b.1 + my / length * half * ratio * side);
+                Some(vec![b, p0, tip, p1])
+            } else {
+                Some(vec![b, p0, p1])
+            }
+        }
+    }
+}
+
+fn circle(center: Point, radius: f64, tolerance: f64) -> Vec<Point> {
+    let steps = ((PI * (radius / tolerance).sqrt()).ceil() as usize).max(8).min(256);
+    (0..steps)
+        .map(|step| {
+            let angle = 2.0 * PI * step as f64 / steps as f64;
+            (center.0 + radius * angle.cos(), center.1 + radius * angle.sin())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_svg(svg: &str) -> RgbaImage {
+        let tree = usvg::Tree::from_str(svg, &usvg::Options::default()).unwrap();
+        render(&tree, 20, 20)
+    }
+
+    #[test]
+    fn test_render_even_odd() {
+        let image = render_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="20">
+              <path d="M0 0H20V20H0Z M5 5H15V15H5Z" fill="#0000ff" fill-rule="evenodd"/>
+            </svg>"##,
+        );
+        assert_eq!(image.get_pixel(2, 2).data, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(10, 10).data, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_render_stroke() {
+        let image = render_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="20">
+              <path d="M4 10H16" stroke="#00ff00" stroke-width="4" stroke-linecap="square"/>
+            </svg>"##,
+        );
+        assert_eq!(image.get_pixel(10, 9).data, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(2, 10).data, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(10, 5).data, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_render_gradient_and_opacity() {
+        let image = render_svg(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="20">
+              <linearGradient id="g"><stop offset="0" stop-color="#000000"/><stop offset="1" stop-color="#ffffff"/></linearGradient>
+              <rect width="20" height="10" fill="url(#g)"/>
+              <g opacity="0.5"><rect y="10" width="20" height="10" fill="#ff0000"/></g>
+            </svg>"##,
+        );
+        let (left, right) = (image.get_pixel(0, 5).data, image.get_pixel(19, 5).data);
+        assert!(left[0] < 20 && right[0] > 235, "{:?} {:?}", left, right);
+        assert_eq!(image.get_pixel(10, 15).data, [255, 0, 0, 128]);
+    }
+}
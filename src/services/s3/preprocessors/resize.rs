@@ -0,0 +1,223 @@
+//! Geometry of resizing images into a bounding box
+
+use image::imageops;
+use image::GenericImageView;
+use image::{DynamicImage, FilterType, Rgba, RgbaImage};
+
+use super::super::types::{Color, Fit, FocalPoint, ResizeParams};
+
+/// Resizes an image into `params.width` x `params.height` box according to `params.fit`. Images are
+/// never upscaled, except for `Fit::Fill`, which always stretches the image to the box.
+/// `Fit::Smart` crops around `params.focal_point`, or the center of the image if it is not set.
+/// Images are resampled with `params.resampling` filter and sharpened if they were downscaled.
+pub fn resize(image: DynamicImage, params: &ResizeParams) -> DynamicImage {
+    let (w, h) = image.dimensions();
+    let filter = params.resampling.filter.filter_type();
+    let resized = match (&params.fit, params.width, params.height) {
+        (&Fit::Cover, Some(width), Some(height)) => cover(image, width, height, &FocalPoint::center(), filter),
+        (&Fit::Smart, Some(width), Some(height)) => {
+            let focal_point = params.focal_point.clone().unwrap_or_else(FocalPoint::center);
+            cover(image, width, height, &focal_point, filter)
+        }
+        (&Fit::Contain, Some(width), Some(height)) => contain(image, width, height, &params.background, filter),
+        (&Fit::Fill, Some(width), Some(height)) => image.resize_exact(width, height, filter),
+        (fit, width, height) => scale_down(image, width, height, fit, filter),
+    };
+    let (resized_w, resized_h) = resized.dimensions();
+    match params.resampling.sharpen {
+        Some(ref sharpen) if resized_w < w || resized_h < h => resized.unsharpen(sharpen.sigma, sharpen.threshold),
+        _ => resized,
+    }
+}
+
+/// Checks if an image `w` x `h` fills the box of `params` without upscaling, e.g. if it is
+/// large enough for a high density variant
+pub fn is_downscale(w: u32, h: u32, params: &ResizeParams) -> bool {
+    // modes that cover the box need both dimensions to be large enough
+    let fit = match &params.fit {
+        &Fit::Cover | &Fit::Smart | &Fit::Fill => &Fit::Outside,
+        &Fit::Contain => &Fit::Inside,
+        fit => fit,
+    };
+    match scale(w, h, params.width, params.height, fit) {
+        Some((target, reference)) => target <= reference,
+        None => true,
+    }
+}
+
+/// Scales an image down keeping aspect ratio
+fn scale_down(image: DynamicImage, width: Option<u32>, height: Option<u32>, fit: &Fit, filter: FilterType) -> DynamicImage {
+    let (w, h) = image.dimensions();
+    let (target, reference) = match scale(w, h, width, height, fit) {
+        Some((target, reference)) if target < reference => (target, reference),
+        _ => return image,
+    };
+    let new_width = ((w as f32) * (target as f32) / (reference as f32)).round() as u32;
+    let new_height = ((h as f32) * (target as f32) / (reference as f32)).round() as u32;
+    image.resize(new_width, new_height, filter)
+}
+
+/// Crops an image to the aspect ratio of the box, then scales it down to the box.
+/// The crop is centered on `focal_point` as far as image bounds allow.
+fn cover(mut image: DynamicImage, width: u32, height: u32, focal_point: &FocalPoint, filter: FilterType) -> DynamicImage {
+    let (w, h) = image.dimensions();
+    let (crop_width, crop_height) = if u64::from(width) * u64::from(h) < u64::from(height) * u64::from(w) {
+        // box is narrower than the image
+        (((u64::from(h) * u64::from(width)) / u64::from(height)).max(1) as u32, h)
+    } else {
+        (w, ((u64::from(w) * u64::from(height)) / u64::from(width)).max(1) as u32)
+    };
+    let (focal_x, focal_y) = focal_point.to_fractions(w, h);
+    let x = crop_offset(focal_x, w, crop_width);
+    let y = crop_offset(focal_y, h, crop_height);
+    let cropped = image.crop(x, y, crop_width, crop_height);
+    if crop_width > width {
+        cropped.resize_exact(width, height, filter)
+    } else {
+        cropped
+    }
+}
+
+/// Offset of a crop of `crop_dimension` centered on `focal` fraction of `dimension`, clamped to image bounds
+fn crop_offset(focal: f32, dimension: u32, crop_dimension: u32) -> u32 {
+    let offset = (focal * dimension as f32 - crop_dimension as f32 / 2.0).round().max(0.0) as u32;
+    offset.min(dimension - crop_dimension)
+}
+
+/// Scales an image down to fit inside the box and centers it on the box filled with `background`
+fn contain(image: DynamicImage, width: u32, height: u32, background: &Color, filter: FilterType) -> DynamicImage {
+    let resized = scale_down(image, Some(width), Some(height), &Fit::Inside, filter);
+    let (w, h) = resized.dimensions();
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba { data: background.0 });
+    imageops::overlay(&mut canvas, &resized.to_rgba(), (width - w) / 2, (height - h) / 2);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Computes scale factor of an image `w` x `h` so that it fits the box. The factor is returned
+/// as a `(target, reference)` pair of the dimension that limits it, i.e. `(width, w)` or `(height, h)`.
+/// Returns `None` if the box is unbounded in both dimensions.
+fn scale(w: u32, h: u32, width: Option<u32>, height: Option<u32>, fit: &Fit) -> Option<(u32, u32)> {
+    match (width, height) {
+        (Some(width), Some(height)) => {
+            // compare width / w and height / h without losing precision
+            let x_is_smaller = u64::from(width) * u64::from(h) < u64::from(height) * u64::from(w);
+            let use_x = match fit {
+                Fit::Outside => !x_is_smaller,
+                _ => x_is_smaller,
+            };
+            Some(if use_x { (width, w) } else { (height, h) })
+        }
+        (Some(width), None) => Some((width, w)),
+        (None, Some(height)) => Some((height, h)),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::types::{OutputFormat, Resampling, ResizeFilter, Sharpen};
+    use super::*;
+
+    fn image(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::new(w, h))
+    }
+
+    #[test]
+    fn test_is_downscale() {
+        let params = |fit: Fit, width: u32, height: u32| ResizeParams {
+            width: Some(width),
+            height: Some(height),
+            fit,
+            background: Color::default(),
+            focal_point: None,
+            format: OutputFormat::Png,
+            embed_profile: false,
+            optimize_png: None,
+            resampling: Resampling::default(),
+        };
+        assert!(is_downscale(200, 100, &params(Fit::Inside, 200, 200)));
+        assert!(!is_downscale(200, 100, &params(Fit::Inside, 400, 400)));
+        assert!(is_downscale(200, 100, &params(Fit::Outside, 100, 100)));
+        assert!(!is_downscale(200, 100, &params(Fit::Outside, 200, 200)));
+        assert!(!is_downscale(200, 100, &params(Fit::Cover, 200, 200)));
+    }
+
+    #[test]
+    fn test_resize_resampling() {
+        // vertical stripes, 2 pixels wide
+        let stripes = DynamicImage::ImageRgba8(RgbaImage::from_fn(80, 80, |x, _| {
+            let value = if x % 4 < 2 { 0 } else { 255 };
+            Rgba {
+                data: [value, value, value, 255],
+            }
+        }));
+        let resize_with = |resampling: Resampling| {
+            let params = ResizeParams {
+                width: Some(40),
+                height: Some(40),
+                fit: Fit::Inside,
+                background: Color::default(),
+                focal_point: None,
+                format: OutputFormat::Png,
+                embed_profile: false,
+                optimize_png: None,
+                resampling,
+            };
+            resize(stripes.clone(), &params).to_rgba()
+        };
+
+        let nearest = resize_with(Resampling {
+            filter: ResizeFilter::Nearest,
+            sharpen: None,
+        });
+        assert!(nearest.pixels().all(|pixel| pixel.data[0] == 0 || pixel.data[0] == 255));
+        let triangle = resize_with(Resampling::default());
+        let sharpened = resize_with(Resampling {
+            filter: ResizeFilter::Triangle,
+            sharpen: Some(Sharpen { sigma: 1.0, threshold: 0 }),
+        });
+        assert_eq!(sharpened.dimensions(), (40, 40));
+        assert_ne!(sharpened.into_raw(), triangle.into_raw());
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(scale(200, 100, Some(50), Some(50), &Fit::Inside), Some((50, 200)));
+        assert_eq!(scale(200, 100, Some(50), Some(50), &Fit::Outside), Some((50, 100)));
+        assert_eq!(scale(200, 100, None, Some(50), &Fit::Inside), Some((50, 100)));
+        assert_eq!(scale(200, 100, None, None, &Fit::Inside), None);
+    }
+
+    #[test]
+    fn test_resize_fit_modes() {
+        let dimensions = |fit: Fit, width: u32, height: u32| {
+            let params = ResizeParams {
+                width: Some(width),
+                height: Some(height),
+                fit,
+                background: Color::default(),
+                focal_point: None,
+                format: OutputFormat::Png,
+                embed_profile: false,
+                optimize_png: None,
+                resampling: Resampling::default(),
+            };
+            resize(image(200, 100), &params).dimensions()
+        };
+        assert_eq!(dimensions(Fit::Cover, 50, 50), (50, 50));
+        assert_eq!(dimensions(Fit::Cover, 400, 400), (100, 100));
+        assert_eq!(dimensions(Fit::Smart, 50, 50), (50, 50));
+        assert_eq!(dimensions(Fit::Contain, 50, 50), (50, 50));
+        assert_eq!(dimensions(Fit::Fill, 50, 60), (50, 60));
+        assert_eq!(dimensions(Fit::Inside, 50, 50), (50, 25));
+        assert_eq!(dimensions(Fit::Outside, 50, 50), (100, 50));
+    }
+
+    #[test]
+    fn test_crop_offset() {
+        assert_eq!(crop_offset(0.5, 200, 100), 50);
+        assert_eq!(crop_offset(0.9, 200, 100), 100);
+        assert_eq!(crop_offset(0.1, 200, 100), 0);
+        assert_eq!(crop_offset(0.6, 200, 100), 70);
+    }
+}
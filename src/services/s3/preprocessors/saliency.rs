@@ -0,0 +1,91 @@
+//! Focal point detection based on edge energy of a downscaled copy of the image
+
+use image::GenericImageView;
+use image::{DynamicImage, FilterType};
+use std::cmp::Ordering;
+
+use super::super::types::{FocalPoint, Length};
+
+/// Max width and height of the downscaled copy used for detection
+static SAMPLE_SIZE: u32 = 64;
+/// Share of the most salient pixels used for computing the focal point
+static SALIENT_SHARE: f32 = 0.1;
+/// Gradients weaker than that are rounding noise of downscaling rather than edges
+static MIN_ENERGY: f32 = 4.0;
+
+/// Detects focal point of an image as a centroid of its most salient pixels, i.e. pixels with
+/// the strongest gradient of luminance. Returns center of the image if there are no edges at all.
+pub fn detect(image: &DynamicImage) -> FocalPoint {
+    let (w, h) = image.dimensions();
+    let sample = if w > SAMPLE_SIZE || h > SAMPLE_SIZE {
+        image.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+    let luma = sample.to_luma();
+    let (width, height) = luma.dimensions();
+    if width < 3 || height < 3 {
+        return FocalPoint::center();
+    }
+
+    let value = |x: u32, y: u32| f32::from(luma.get_pixel(x, y).data[0]);
+    let mut energies = Vec::with_capacity(((width - 2) * (height - 2)) as usize);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let gx = value(x + 1, y) - value(x - 1, y);
+            let gy = value(x, y + 1) - value(x, y - 1);
+            energies.push((x, y, (gx * gx + gy * gy).sqrt()));
+        }
+    }
+
+    let mut sorted = energies.iter().map(|&(_, _, energy)| energy).collect::<Vec<_>>();
+    sorted.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+    let threshold = sorted[((sorted.len() as f32) * SALIENT_SHARE) as usize];
+
+    let (mut sum_x, mut sum_y, mut total) = (0.0f32, 0.0f32, 0.0f32);
+    for &(x, y, energy) in energies
+        .iter()
+        .filter(|&&(_, _, energy)| energy >= MIN_ENERGY && energy >= threshold)
+    {
+        sum_x += (x as f32 + 0.5) * energy;
+        sum_y += (y as f32 + 0.5) * energy;
+        total += energy;
+    }
+    if total == 0.0 {
+        return FocalPoint::center();
+    }
+
+    FocalPoint {
+        x: Length::Percent(sum_x / total / (width as f32) * 100.0),
+        y: Length::Percent(sum_y / total / (height as f32) * 100.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn test_detect_edges() {
+        // white image with a black square in the bottom right corner
+        let buffer = ImageBuffer::from_fn(100, 100, |x, y| {
+            if x > 70 && x < 90 && y > 70 && y < 90 {
+                Rgba { data: [0, 0, 0, 255] }
+            } else {
+                Rgba {
+                    data: [255, 255, 255, 255],
+                }
+            }
+        });
+        let (x, y) = detect(&DynamicImage::ImageRgba8(buffer)).to_fractions(100, 100);
+        assert!(x > 0.6 && x < 0.95, "x = {}", x);
+        assert!(y > 0.6 && y < 0.95, "y = {}", y);
+    }
+
+    #[test]
+    fn test_detect_uniform() {
+        let buffer = ImageBuffer::from_pixel(100, 50, Rgba { data: [10, 20, 30, 255] });
+        assert_eq!(detect(&DynamicImage::ImageRgba8(buffer)), FocalPoint::center());
+    }
+}
@@ -0,0 +1,227 @@
+//! Sanitizing and rasterizing of uploaded SVG images
+
+use image::DynamicImage;
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use usvg;
+
+use super::super::error::S3Error;
+use super::raster;
+
+/// Elements that are removed along with their content
+static FORBIDDEN_ELEMENTS: &[&str] = &["script", "foreignobject", "handler", "listener"];
+/// Embedded images that are allowed in `href` attributes
+static ALLOWED_DATA_URLS: &[&str] = &["data:image/png", "data:image/jpeg", "data:image/gif"];
+/// Max width and height of rasterized image
+static MAX_RASTER_SIZE: f64 = 4096.0;
+
+/// Removes scripts, event handlers, external references and foreign objects from SVG.
+/// Doctypes, processing instructions and comments are removed as well.
+///
+/// #Errors
+/// * `S3Error::Image` if SVG is not well-formed or its root element is not `svg`
+pub fn sanitize(bytes: &[u8]) -> Result<Vec<u8>, S3Error> {
+    let mut reader = Reader::from_reader(bytes);
+    let mut writer = Writer::new(Vec::new());
+    let mut buf = Vec::new();
+    // depth of a forbidden element being skipped
+    let mut skipped_depth = 0;
+    let mut has_root = false;
+    loop {
+        // owned copy of the event, so that `buf` can be cleared
+        let event: Option<Event<'static>> = match reader
+            .read_event(&mut buf)
+            .map_err(|e| S3Error::Image(format!("Error parsing svg at position {}: {}", reader.buffer_position(), e)))?
+        {
+            Event::Eof => break,
+            Event::Start(_) if skipped_depth > 0 => {
+                skipped_depth += 1;
+                None
+            }
+            Event::End(_) if skipped_depth > 0 => {
+                skipped_depth -= 1;
+                None
+            }
+            _ if skipped_depth > 0 => None,
+            Event::Start(ref element) | Event::Empty(ref element) if !has_root && local_name(element.name()) != "svg" => {
+                return Err(S3Error::Image("Root element of svg is not `svg`".to_string()));
+            }
+            Event::Start(ref element) if is_forbidden(element) => {
+                skipped_depth = 1;
+                None
+            }
+            Event::Empty(ref element) if is_forbidden(element) => None,
+            Event::Start(ref element) => {
+                has_root = true;
+                Some(Event::Start(clean_element(element)?))
+            }
+            Event::Empty(ref element) => {
+                has_root = true;
+                Some(Event::Empty(clean_element(element)?))
+            }
+            Event::Text(ref text) | Event::CData(ref text) if has_external_reference(&String::from_utf8_lossy(text)) => None,
+            Event::DocType(_) | Event::PI(_) | Event::Comment(_) => None,
+            event => Some(event.into_owned()),
+        };
+        if let Some(event) = event {
+            writer
+                .write_event(event)
+                .map_err(|e| S3Error::Image(format!("Error writing svg: {}", e)))?;
+        }
+        buf.clear();
+    }
+    if !has_root {
+        return Err(S3Error::Image("Svg has no root element".to_string()));
+    }
+    Ok(writer.into_inner())
+}
+
+/// Rasterizes SVG so that its smaller dimension is at least `min_size` pixels,
+/// unless that makes the larger dimension exceed `MAX_RASTER_SIZE`
+///
+/// #Errors
+/// * `S3Error::Image` if SVG can't be parsed or uses features that can't be rendered, e.g. text or filters
+pub fn rasterize(bytes: &[u8], min_size: u32) -> Result<DynamicImage, S3Error> {
+    let tree = usvg::Tree::from_data(bytes, &usvg::Options::default()).map_err(|e| S3Error::Image(format!("Error parsing svg: {}", e)))?;
+    let size = tree.svg_node().size;
+    let (w, h) = (size.width, size.height);
+    if !(w > 0.0 && h > 0.0) {
+        return Err(S3Error::Image(format!("Invalid svg size {}x{}", w, h)));
+    }
+    let scale = (f64::from(min_size) / w.min(h)).max(1.0).min(MAX_RASTER_SIZE / w.max(h));
+    let width = (w * scale).round().max(1.0).min(MAX_RASTER_SIZE) as u32;
+    let height = (h * scale).round().max(1.0).min(MAX_RASTER_SIZE) as u32;
+
+    if let Some(feature) = raster::unsupported_feature(&tree) {
+        return Err(S3Error::Image(format!("Svg with {} is not supported", feature)));
+    }
+    let image = raster::render(&tree, width, height);
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+/// Name without namespace prefix in lower case, e.g. `href` for `xlink:href`
+fn local_name(name: &[u8]) -> String {
+    let name = String::from_utf8_lossy(name);
+    name.rsplit(':').next().unwrap_or("").to_lowercase()
+}
+
+fn is_forbidden(element: &BytesStart) -> bool {
+    FORBIDDEN_ELEMENTS.contains(&&*local_name(element.name()))
+}
+
+/// Copy of an element without unsafe attributes
+fn clean_element(element: &BytesStart) -> Result<BytesStart<'static>, S3Error> {
+    let mut cleaned = BytesStart::owned_name(element.name().to_vec());
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| S3Error::Image(format!("Error parsing svg attribute: {}", e)))?;
+        if is_safe_attribute(&attribute)? {
+            cleaned.push_attribute(attribute);
+        }
+    }
+    Ok(cleaned)
+}
+
+/// Checks that an attribute is neither an event handler nor a reference to external resource or script
+fn is_safe_attribute(attribute: &Attribute) -> Result<bool, S3Error> {
+    let name = local_name(attribute.key);
+    let value = attribute
+        .unescaped_value()
+        .map_err(|e| S3Error::Image(format!("Error parsing svg attribute: {}", e)))?;
+    let value = String::from_utf8_lossy(&value).to_lowercase();
+    let compact = value.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    if name.starts_with("on") || name == "base" || compact.contains("javascript:") || has_external_reference(&value) {
+        return Ok(false);
+    }
+    if name == "href" || name == "src" {
+        return Ok(compact.starts_with('#') || ALLOWED_DATA_URLS.iter().any(|prefix| compact.starts_with(prefix)));
+    }
+    Ok(true)
+}
+
+/// Checks if CSS value or stylesheet refers to anything but fragments of the same document,
+/// e.g. `url(https://example.com/a.svg#b)` or `@import`
+fn has_external_reference(value: &str) -> bool {
+    let value = value.to_lowercase();
+    if value.contains("@import") {
+        return true;
+    }
+    value.split("url(").skip(1).any(|reference| {
+        let reference = reference.trim_start_matches(|c: char| c.is_whitespace() || c == '"' || c == '\'');
+        !reference.starts_with('#')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_sanitize() {
+        let svg = br##"<?xml version="1.0"?>
+<!DOCTYPE svg [<!ENTITY x "y">]>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" onload="alert(1)" width="10" height="10">
+  <script>alert(1)</script>
+  <foreignObject><div><script>alert(2)</script></div></foreignObject>
+  <style>@import url(https://example.com/a.css);</style>
+  <a xlink:href="javascript:alert(3)"><rect width="10" height="10" fill="url(#gradient)"/></a>
+  <use href="https://example.com/a.svg#b"/>
+  <image xlink:href="data:image/png;base64,AAAA" filter="url( 'https://example.com/f.svg#f')"/>
+</svg>"##;
+        let sanitized = String::from_utf8(sanitize(svg).unwrap()).unwrap();
+        for forbidden in &["DOCTYPE", "onload", "script", "alert", "foreignObject", "example.com"] {
+            assert!(!sanitized.contains(forbidden), "`{}` in {}", forbidden, sanitized);
+        }
+        assert!(sanitized.contains(r##"fill="url(#gradient)""##));
+        assert!(sanitized.contains(r##"<image xlink:href="data:image/png;base64,AAAA"/>"##));
+        assert!(sanitized.contains("<use/>"));
+    }
+
+    #[test]
+    fn test_sanitize_not_svg() {
+        match sanitize(b"<html><script>alert(1)</script></html>") {
+            Err(S3Error::Image(_)) => (),
+            res => assert!(false, format!("Expected error S3Error::Image, found {:?}", res)),
+        }
+    }
+
+    #[test]
+    fn test_rasterize() {
+        let svg =
+            br##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><rect width="20" height="10" fill="#ff0000"/></svg>"##;
+        let image = rasterize(svg, 100).unwrap();
+        assert_eq!(image.dimensions(), (200, 100));
+        assert_eq!(image.to_rgba().get_pixel(100, 50).data, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_rasterize_unsupported() {
+        let svgs: &[(&[u8], &str)] = &[
+            (
+                br##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><text y="10">Logo</text></svg>"##,
+                "text",
+            ),
+            (
+                br##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+              <filter id="f"><feGaussianBlur stdDeviation="2"/></filter>
+              <rect width="20" height="10" filter="url(#f)"/>
+            </svg>"##,
+                "filters",
+            ),
+            (
+                br##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+              <pattern id="p" width="2" height="2" patternUnits="userSpaceOnUse"><rect width="1" height="1"/></pattern>
+              <rect width="20" height="10" fill="url(#p)"/>
+            </svg>"##,
+                "patterns",
+            ),
+        ];
+        for &(svg, feature) in svgs {
+            match rasterize(svg, 100) {
+                Err(S3Error::Image(ref message)) if message.contains(feature) => (),
+                res => assert!(false, format!("Expected error S3Error::Image, found {:?}", res.map(|_| ()))),
+            }
+        }
+    }
+}
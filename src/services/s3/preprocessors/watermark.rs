@@ -0,0 +1,271 @@
+//! Watermarks overlaid on resized variants of uploaded images
+
+use image;
+use image::imageops;
+use image::GenericImageView;
+use image::{DynamicImage, FilterType, Rgba, RgbaImage};
+use rusttype::{point, Font, Scale};
+use std::fs::File;
+use std::io::Read;
+
+use super::super::error::S3Error;
+use super::super::types::{Color, ImageSize, Position, WatermarkOptions};
+
+/// Height of rendered text in pixels. Text is rendered once and then scaled like image watermarks.
+static TEXT_HEIGHT: f32 = 64.0;
+
+/// Watermark prepared for overlaying
+#[derive(Clone, Debug)]
+pub struct Watermark {
+    mark: RgbaImage,
+    options: WatermarkOptions,
+}
+
+impl Watermark {
+    /// Creates watermark from an image
+    pub fn new(mark: RgbaImage, options: WatermarkOptions) -> Self {
+        Self { mark, options }
+    }
+
+    /// Loads watermark image from `options.image_path`, or renders `options.text` with `options.font_path` font
+    ///
+    /// #Errors
+    /// * `S3Error::Image` if files can't be read or decoded, neither image nor text is set,
+    /// or `scale` or `margin` is out of range
+    pub fn load(options: WatermarkOptions) -> Result<Self, S3Error> {
+        if !(options.scale > 0.0 && options.scale <= 1.0) {
+            return Err(S3Error::Image(format!(
+                "Watermark scale must be in (0, 1], found {}",
+                options.scale
+            )));
+        }
+        if !(options.margin >= 0.0 && options.margin < 1.0) {
+            return Err(S3Error::Image(format!(
+                "Watermark margin must be in [0, 1), found {}",
+                options.margin
+            )));
+        }
+        let mark = match (&options.image_path, &options.text, &options.font_path) {
+            (&Some(ref image_path), _, _) => {
+                let bytes = read_file(image_path)?;
+                image::load_from_memory(&bytes)
+                    .map_err(|e| S3Error::Image(format!("Error parsing watermark image {}: {}", image_path, e)))?
+                    .to_rgba()
+            }
+            (&None, &Some(ref text), &Some(ref font_path)) => {
+                let font = Font::from_bytes(read_file(font_path)?)
+                    .map_err(|e| S3Error::Image(format!("Error parsing watermark font {}: {}", font_path, e)))?;
+                render_text(text, &font, &options.color)
+            }
+            _ => return Err(S3Error::Image("Watermark requires either image or text with font".to_string())),
+        };
+        if mark.width() == 0 || mark.height() == 0 {
+            return Err(S3Error::Image("Watermark is empty".to_string()));
+        }
+        Ok(Self::new(mark, options))
+    }
+
+    /// Checks if images resized to `size` preset are watermarked
+    pub fn applies_to(&self, size: &ImageSize) -> bool {
+        self.options.presets.contains(size)
+    }
+
+    /// Overlays watermark on an image. Images smaller than `min_size` are returned as is.
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        let (w, h) = image.dimensions();
+        if w < self.options.min_size || h < self.options.min_size {
+            return image;
+        }
+        let (mark_w, mark_h) = self.mark.dimensions();
+        // the mark is also limited by image height, e.g. for wide marks on tall images
+        let ratio = (self.options.scale * (w as f32) / (mark_w as f32)).min((h as f32) / (mark_h as f32));
+        // rounding must not make the mark larger than the image
+        let width = (((mark_w as f32) * ratio).round() as u32).min(w);
+        let height = (((mark_h as f32) * ratio).round() as u32).min(h);
+        if width == 0 || height == 0 {
+            return image;
+        }
+        let mark = imageops::resize(&self.mark, width, height, FilterType::Triangle);
+
+        let margin = (self.options.margin * (w.min(h) as f32)).round() as u32;
+        let x = offset(w, width, margin, horizontal(&self.options.position));
+        let y = offset(h, height, margin, vertical(&self.options.position));
+
+        let mut canvas = image.to_rgba();
+        let opacity = self.options.opacity.max(0.0).min(1.0);
+        for (mark_x, mark_y, pixel) in mark.enumerate_pixels() {
+            let target = canvas.get_pixel_mut(x + mark_x, y + mark_y);
+            *target = blend(target, pixel, opacity);
+        }
+        DynamicImage::ImageRgba8(canvas)
+    }
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, S3Error> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| S3Error::Image(format!("Error reading watermark file {}: {}", path, e)))?;
+    Ok(bytes)
+}
+
+/// Renders a line of text on transparent background
+fn render_text(text: &str, font: &Font, color: &Color) -> RgbaImage {
+    let scale = Scale::uniform(TEXT_HEIGHT);
+    let v_metrics = font.v_metrics(scale);
+    let glyphs = font.layout(text, scale, point(0.0, v_metrics.ascent)).collect::<Vec<_>>();
+    let width = glyphs
+        .iter()
+        .filter_map(|glyph| glyph.pixel_bounding_box().map(|bounding_box| bounding_box.max.x))
+        .max()
+        .unwrap_or(0)
+        .max(0) as u32;
+    let height = (v_metrics.ascent - v_metrics.descent).ceil() as u32;
+
+    let mut mark = RgbaImage::new(width, height);
+    for glyph in glyphs {
+        if let Some(bounding_box) = glyph.pixel_bounding_box() {
+            glyph.draw(|x, y, coverage| {
+                let x = x as i32 + bounding_box.min.x;
+                let y = y as i32 + bounding_box.min.y;
+                if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                    let mut data = color.0;
+                    data[3] = (f32::from(data[3]) * coverage).round() as u8;
+                    mark.put_pixel(x as u32, y as u32, Rgba { data });
+                }
+            });
+        }
+    }
+    mark
+}
+
+/// Alignment of watermark along an axis
+enum Align {
+    Start,
+    Middle,
+    End,
+}
+
+fn horizontal(position: &Position) -> Align {
+    match position {
+        &Position::TopLeft | &Position::Left | &Position::BottomLeft => Align::Start,
+        &Position::Top | &Position::Center | &Position::Bottom => Align::Middle,
+        &Position::TopRight | &Position::Right | &Position::BottomRight => Align::End,
+    }
+}
+
+fn vertical(position: &Position) -> Align {
+    match position {
+        &Position::TopLeft | &Position::Top | &Position::TopRight => Align::Start,
+        &Position::Left | &Position::Center | &Position::Right => Align::Middle,
+        &Position::BottomLeft | &Position::Bottom | &Position::BottomRight => Align::End,
+    }
+}
+
+/// Offset of a mark of `mark_dimension` inside `dimension`, margin is dropped if the mark doesn't fit
+fn offset(dimension: u32, mark_dimension: u32, margin: u32, align: Align) -> u32 {
+    let space = dimension - mark_dimension;
+    match align {
+        Align::Start => margin.min(space),
+        Align::Middle => space / 2,
+        Align::End => space - margin.min(space),
+    }
+}
+
+/// Alpha-composites `mark` pixel with extra `opacity` over `target` pixel
+fn blend(target: &Rgba<u8>, mark: &Rgba<u8>, opacity: f32) -> Rgba<u8> {
+    let mark_alpha = f32::from(mark.data[3]) / 255.0 * opacity;
+    let target_alpha = f32::from(target.data[3]) / 255.0;
+    let alpha = mark_alpha + target_alpha * (1.0 - mark_alpha);
+    if alpha == 0.0 {
+        return Rgba { data: [0, 0, 0, 0] };
+    }
+    let mut data = [0; 4];
+    for (value, (&mark_value, &target_value)) in data.iter_mut().zip(mark.data.iter().zip(target.data.iter())).take(3) {
+        let blended = f32::from(mark_value) * mark_alpha + f32::from(target_value) * target_alpha * (1.0 - mark_alpha);
+        *value = (blended / alpha).round() as u8;
+    }
+    data[3] = (alpha * 255.0).round() as u8;
+    Rgba { data }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> WatermarkOptions {
+        WatermarkOptions {
+            image_path: None,
+            text: None,
+            font_path: None,
+            color: Color::default(),
+            position: Position::BottomRight,
+            opacity: 0.5,
+            scale: 0.5,
+            margin: 0.0,
+            min_size: 100,
+            presets: vec![ImageSize::Large],
+        }
+    }
+
+    fn white(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            w,
+            h,
+            Rgba {
+                data: [255, 255, 255, 255],
+            },
+        ))
+    }
+
+    #[test]
+    fn test_apply_watermark() {
+        let mark = RgbaImage::from_pixel(10, 10, Rgba { data: [0, 0, 0, 255] });
+        let watermark = Watermark::new(mark, options());
+        assert!(watermark.applies_to(&ImageSize::Large));
+        assert!(!watermark.applies_to(&ImageSize::Thumb));
+
+        let marked = watermark.apply(white(200, 100)).to_rgba();
+        // 100x100 mark in the right half of the image
+        assert_eq!(marked.get_pixel(150, 50).data, [128, 128, 128, 255]);
+        assert_eq!(marked.get_pixel(50, 50).data, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_skip_small_images() {
+        let mark = RgbaImage::from_pixel(10, 10, Rgba { data: [0, 0, 0, 255] });
+        let watermark = Watermark::new(mark, options());
+        let marked = watermark.apply(white(200, 50)).to_rgba();
+        assert_eq!(marked.get_pixel(190, 40).data, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_load_invalid_options() {
+        let load_error = |options: WatermarkOptions| match Watermark::load(options) {
+            Err(S3Error::Image(message)) => message,
+            result => panic!("Expected S3Error::Image, found {:?}", result.map(|_| ())),
+        };
+        for &scale in &[0.0, -0.5, 1.5] {
+            assert!(load_error(WatermarkOptions { scale, ..options() }).contains("scale"));
+        }
+        for &margin in &[1.0, -0.1] {
+            assert!(load_error(WatermarkOptions { margin, ..options() }).contains("margin"));
+        }
+    }
+
+    #[test]
+    fn test_full_size_watermark() {
+        let mark = RgbaImage::from_pixel(300, 10, Rgba { data: [0, 0, 0, 255] });
+        let watermark = Watermark::new(mark, WatermarkOptions { scale: 1.0, ..options() });
+        let marked = watermark.apply(white(201, 100)).to_rgba();
+        assert_eq!(marked.dimensions(), (201, 100));
+    }
+
+    #[test]
+    fn test_offset() {
+        assert_eq!(offset(100, 20, 5, Align::Start), 5);
+        assert_eq!(offset(100, 20, 5, Align::Middle), 40);
+        assert_eq!(offset(100, 20, 5, Align::End), 75);
+        assert_eq!(offset(100, 98, 5, Align::End), 0);
+    }
+}
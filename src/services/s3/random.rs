@@ -1,19 +1,29 @@
 use base64::encode;
+use chrono::{DateTime, Utc};
 use rand;
 use rand::Rng;
 
-/// Lenght of the random hash in s3 filename in bytes
-static HASH_LEN_BYTES: u8 = 8;
+use super::types::KeyId;
+
+/// Crockford's base32 alphabet of ULIDs
+static ULID_ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
 
 pub trait Random {
     fn generate_hash(&self) -> String;
 }
 
-pub struct RandomImpl;
+pub struct RandomImpl {
+    id: KeyId,
+}
 
 impl RandomImpl {
     pub fn new() -> Self {
-        RandomImpl {}
+        Self::with_id(KeyId::default())
+    }
+
+    /// Generates ids of kind `id`, see `KeyId`
+    pub fn with_id(id: KeyId) -> Self {
+        RandomImpl { id }
     }
 
     /// Three symbols +, /, = are not aws and url-friendly, just replace them
@@ -22,13 +32,69 @@ impl RandomImpl {
         let s = s.replace("/", "B");
         s.replace("=", "C")
     }
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        bytes
+    }
 }
 
 impl Random for RandomImpl {
     fn generate_hash(&self) -> String {
-        let mut name_bytes = vec![0; HASH_LEN_BYTES as usize];
-        let buffer = name_bytes.as_mut_slice();
-        rand::thread_rng().fill_bytes(buffer);
-        Self::encode_for_aws(&encode(buffer))
+        match self.id {
+            KeyId::Random { length_bytes } => Self::encode_for_aws(&encode(&Self::random_bytes(length_bytes))),
+            KeyId::Ulid => ulid(Utc::now(), &Self::random_bytes(10)),
+            KeyId::Uuid => uuid_v4(&Self::random_bytes(16)),
+        }
+    }
+}
+
+/// ULID of 48-bit timestamp in milliseconds and 80 random bits, see [spec](https://github.com/ulid/spec)
+fn ulid(time: DateTime<Utc>, random: &[u8]) -> String {
+    let millis = time.timestamp() as u128 * 1000 + u128::from(time.timestamp_subsec_millis());
+    let value = random
+        .iter()
+        .fold(millis & ((1 << 48) - 1), |value, &byte| value << 8 | u128::from(byte));
+    (0..26)
+        .map(|index| ULID_ALPHABET[((value >> (125 - index * 5)) & 31) as usize] as char)
+        .collect()
+}
+
+/// UUID v4 formatted as `xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx`
+fn uuid_v4(random: &[u8]) -> String {
+    let mut bytes = random.to_vec();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_ulid() {
+        // example of ULID spec
+        let time = Utc.timestamp(1_469_918_176, 385_000_000);
+        assert_eq!(&ulid(time, &[0; 10])[..10], "01ARYZ6S41");
+        assert_eq!(ulid(Utc.timestamp(0, 0), &[0; 10]), "00000000000000000000000000");
+        assert_eq!(&ulid(time, &[0xff; 10])[10..], "ZZZZZZZZZZZZZZZZ");
+    }
+
+    #[test]
+    fn test_uuid_v4() {
+        assert_eq!(uuid_v4(&[0; 16]), "00000000-0000-4000-8000-000000000000");
+        assert_eq!(uuid_v4(&[0xff; 16]), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+    }
+
+    #[test]
+    fn test_generate_hash() {
+        assert_eq!(RandomImpl::new().generate_hash().len(), 12);
+        assert_eq!(RandomImpl::with_id(KeyId::Random { length_bytes: 16 }).generate_hash().len(), 24);
+        assert_eq!(RandomImpl::with_id(KeyId::Ulid).generate_hash().len(), 26);
+        assert_eq!(RandomImpl::with_id(KeyId::Uuid).generate_hash().len(), 36);
     }
 }
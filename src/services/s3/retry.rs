@@ -0,0 +1,295 @@
+//! `S3Client` decorator that retries failed requests with exponential backoff
+
+use futures::future;
+use futures::future::Loop;
+use futures::prelude::*;
+use futures_timer::Delay;
+use rand;
+use rand::Rng;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::client::{S3Client, S3Object, S3ObjectInfo};
+use super::error::S3Error;
+use super::types::{RetryOptions, UploadOptions};
+
+/// Error codes of s3 responses that are worth retrying, they come as `S3Error::Unknown`, see `S3Error::code`
+static TRANSIENT_ERROR_CODES: &[&str] = &["InternalError", "ServiceUnavailable", "SlowDown", "RequestTimeout"];
+
+/// Retries requests of `inner` client that failed with transient errors, see `is_transient`.
+/// Uploads are passed through, `S3ClientImpl` retries their requests one by one, so that a failed part
+/// doesn't restart the whole multipart upload.
+pub struct RetryingS3Client {
+    inner: Rc<S3Client>,
+    options: RetryOptions,
+}
+
+impl RetryingS3Client {
+    pub fn new(inner: Box<S3Client>, options: RetryOptions) -> Self {
+        Self {
+            inner: inner.into(),
+            options,
+        }
+    }
+
+    fn retry<T, F>(&self, operation: &'static str, key: String, request: F) -> Box<Future<Item = T, Error = S3Error>>
+    where
+        T: 'static,
+        F: Fn(&S3Client) -> Box<Future<Item = T, Error = S3Error>> + 'static,
+    {
+        let inner = self.inner.clone();
+        retry(&self.options, operation, key, move || request(&*inner))
+    }
+}
+
+impl S3Client for RetryingS3Client {
+    fn upload(
+        &self,
+        bucket: String,
+        key: String,
+        content_type: Option<String>,
+        metadata: HashMap<String, String>,
+        tags: HashMap<String, String>,
+        bytes: Rc<Vec<u8>>,
+        content_md5: String,
+        options: UploadOptions,
+    ) -> Box<Future<Item = (), Error = S3Error>> {
+        self.inner
+            .upload(bucket, key, content_type, metadata, tags, bytes, content_md5, options)
+    }
+
+    fn exists(&self, bucket: String, key: String) -> Box<Future<Item = bool, Error = S3Error>> {
+        self.retry("exists", key.clone(), move |inner| inner.exists(bucket.clone(), key.clone()))
+    }
+
+    fn download(&self, bucket: String, key: String) -> Box<Future<Item = S3Object, Error = S3Error>> {
+        self.retry("download", key.clone(), move |inner| inner.download(bucket.clone(), key.clone()))
+    }
+
+    fn head(&self, bucket: String, key: String) -> Box<Future<Item = S3ObjectInfo, Error = S3Error>> {
+        self.retry("head", key.clone(), move |inner| inner.head(bucket.clone(), key.clone()))
+    }
+
+    fn presigned_url(&self, bucket: String, key: String, expires_in: Duration) -> Box<Future<Item = String, Error = S3Error>> {
+        self.retry("presign", key.clone(), move |inner| {
+            inner.presigned_url(bucket.clone(), key.clone(), expires_in)
+        })
+    }
+
+    fn delete(&self, bucket: String, key: String) -> Box<Future<Item = (), Error = S3Error>> {
+        self.retry("delete", key.clone(), move |inner| inner.delete(bucket.clone(), key.clone()))
+    }
+}
+
+/// Runs `request` until it succeeds, fails with an error that is not transient or runs out of attempts.
+/// Attempts are delayed with exponential backoff, see `backoff`.
+pub fn retry<T, F>(options: &RetryOptions, operation: &'static str, key: String, request: F) -> Box<Future<Item = T, Error = S3Error>>
+where
+    T: 'static,
+    F: Fn() -> Box<Future<Item = T, Error = S3Error>> + 'static,
+{
+    let options = options.clone();
+    Box::new(future::loop_fn(1, move |attempt| {
+        let options = options.clone();
+        let key = key.clone();
+        request().then(move |result| -> Box<Future<Item = Loop<T, u32>, Error = S3Error>> {
+            match result {
+                Ok(item) => Box::new(future::ok(Loop::Break(item))),
+                Err(e) => {
+                    if attempt >= options.attempts || !is_transient(&e) {
+                        return Box::new(future::err(e));
+                    }
+                    let delay = backoff(&options, attempt);
+                    warn!(
+                        "S3 {} of {} failed, attempt {} of {}, retrying in {} ms: {}",
+                        operation,
+                        key,
+                        attempt,
+                        options.attempts,
+                        delay.as_secs() * 1000 + u64::from(delay.subsec_millis()),
+                        e
+                    );
+                    Box::new(
+                        Delay::new(delay)
+                            .map(move |_| Loop::Continue(attempt + 1))
+                            .map_err(|e| S3Error::Unknown(format!("Retry timer error: {}", e))),
+                    )
+                }
+            }
+        })
+    }))
+}
+
+/// Network errors and s3 errors with codes like `SlowDown` are transient, access errors or missing objects are not
+pub fn is_transient(error: &S3Error) -> bool {
+    match error {
+        &S3Error::Network(_) => true,
+        &S3Error::Unknown(_) => error
+            .code()
+            .map(|code| TRANSIENT_ERROR_CODES.contains(&code.as_str()))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Delay before the attempt after `attempt`: `base_delay_ms` doubled for every failed attempt, at most
+/// `max_delay_ms`, with random jitter of up to a half of it, so that concurrent uploads don't retry at once
+pub fn backoff(options: &RetryOptions, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(31);
+    let delay = options.base_delay_ms.saturating_mul(1u64 << exponent).min(options.max_delay_ms);
+    let jitter = if delay > 1 {
+        rand::thread_rng().gen_range(0, delay / 2 + 1)
+    } else {
+        0
+    };
+    Duration::from_millis(delay - jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// Client that fails first `failures` requests with a copy of `error`
+    struct FlakyClient {
+        failures: u32,
+        error: fn() -> S3Error,
+        attempts: Rc<Cell<u32>>,
+    }
+
+    impl FlakyClient {
+        fn request<T: 'static>(&self, item: T) -> Box<Future<Item = T, Error = S3Error>> {
+            self.attempts.set(self.attempts.get() + 1);
+            if self.attempts.get() <= self.failures {
+                (self.error)().into()
+            } else {
+                Box::new(future::ok(item))
+            }
+        }
+    }
+
+    impl S3Client for FlakyClient {
+        fn upload(
+            &self,
+            _bucket: String,
+            _key: String,
+            _content_type: Option<String>,
+            _metadata: HashMap<String, String>,
+            _tags: HashMap<String, String>,
+            _bytes: Rc<Vec<u8>>,
+            _content_md5: String,
+            _options: UploadOptions,
+        ) -> Box<Future<Item = (), Error = S3Error>> {
+            self.request(())
+        }
+
+        fn exists(&self, _bucket: String, _key: String) -> Box<Future<Item = bool, Error = S3Error>> {
+            self.request(true)
+        }
+
+        fn download(&self, _bucket: String, _key: String) -> Box<Future<Item = S3Object, Error = S3Error>> {
+            self.request(S3Object::default())
+        }
+
+        fn head(&self, _bucket: String, _key: String) -> Box<Future<Item = S3ObjectInfo, Error = S3Error>> {
+            self.request(S3ObjectInfo::default())
+        }
+
+        fn presigned_url(&self, _bucket: String, key: String, _expires_in: Duration) -> Box<Future<Item = String, Error = S3Error>> {
+            self.request(key)
+        }
+
+        fn delete(&self, _bucket: String, _key: String) -> Box<Future<Item = (), Error = S3Error>> {
+            self.request(())
+        }
+    }
+
+    fn download(failures: u32, error: fn() -> S3Error) -> (Result<S3Object, S3Error>, u32) {
+        let attempts = Rc::new(Cell::new(0));
+        let client = FlakyClient {
+            failures,
+            error,
+            attempts: attempts.clone(),
+        };
+        let options = RetryOptions {
+            attempts: 3,
+            base_delay_ms: 1,
+            max_delay_ms: 2,
+        };
+        let result = RetryingS3Client::new(Box::new(client), options)
+            .download("bucket".to_string(), "key".to_string())
+            .wait();
+        (result, attempts.get())
+    }
+
+    #[test]
+    fn test_retry() {
+        let network = || S3Error::Network("connection reset".to_string());
+        let (result, attempts) = download(2, network);
+        assert!(result.is_ok());
+        assert_eq!(attempts, 3);
+        let (result, attempts) = download(3, network);
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+        let (result, attempts) = download(1, || S3Error::Access("invalid credentials".to_string()));
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_upload_not_retried() {
+        let attempts = Rc::new(Cell::new(0));
+        let client = FlakyClient {
+            failures: 1,
+            error: || S3Error::Network("connection reset".to_string()),
+            attempts: attempts.clone(),
+        };
+        let result = RetryingS3Client::new(Box::new(client), RetryOptions::default())
+            .upload(
+                "bucket".to_string(),
+                "key".to_string(),
+                None,
+                HashMap::new(),
+                HashMap::new(),
+                Rc::new(Vec::new()),
+                "1B2M2Y8AsgTpgAmY7PhCfg==".to_string(),
+                UploadOptions::default(),
+            )
+            .wait();
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&S3Error::Network("timeout".to_string())));
+        assert!(is_transient(&S3Error::Unknown(
+            "<Error><Code>SlowDown</Code><Message>Please reduce your request rate.</Message></Error>".to_string()
+        )));
+        assert!(!is_transient(&S3Error::Unknown("<Code>AccessDenied</Code>".to_string())));
+        assert!(!is_transient(&S3Error::Unknown(
+            "<Error><Code>AccessDenied</Code><Message>InternalError</Message></Error>".to_string()
+        )));
+        assert!(!is_transient(&S3Error::Unknown("SlowDown".to_string())));
+        assert!(!is_transient(&S3Error::Access("invalid credentials".to_string())));
+        assert!(!is_transient(&S3Error::NotFound("key".to_string())));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let options = RetryOptions {
+            attempts: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 1000,
+        };
+        for _ in 0..10 {
+            let first = backoff(&options, 1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = backoff(&options, 3);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            let capped = backoff(&options, 30);
+            assert!(capped >= Duration::from_millis(500) && capped <= Duration::from_millis(1000));
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Tags of s3 objects, which bucket inventory and lifecycle rules can filter on, see
+//! [AWS docs](https://docs.aws.amazon.com/AmazonS3/latest/dev/object-tagging.html)
+
+use std::collections::HashMap;
+
+use super::utils::percent_encode;
+
+/// Max length of tag values
+static MAX_VALUE_LENGTH: usize = 256;
+/// Characters allowed in tag values along with letters, digits and spaces
+static ALLOWED_CHARACTERS: &str = "+-=._:/@";
+
+/// Replaces characters that s3 doesn't allow in tag values with `_` and truncates the value
+/// to 256 characters, e.g. `photo (1).jpg` becomes `photo _1_.jpg`
+pub fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || ALLOWED_CHARACTERS.contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_VALUE_LENGTH)
+        .collect()
+}
+
+/// Encodes tags as url query, the value of `x-amz-tagging` header, e.g. `route=images&variant=thumb`.
+/// Tags are sorted by key.
+pub fn encode(tags: &HashMap<String, String>) -> String {
+    let mut tags = tags.iter().collect::<Vec<_>>();
+    tags.sort();
+    tags.into_iter()
+        .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("photo (1).jpg"), "photo _1_.jpg");
+        assert_eq!(sanitize("фото_2018-01-01.png"), "фото_2018-01-01.png");
+        assert_eq!(sanitize(&"a".repeat(300)).len(), 256);
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut tags = HashMap::new();
+        tags.insert("variant".to_string(), "medium@2x".to_string());
+        tags.insert("filename".to_string(), "my photo.jpg".to_string());
+        assert_eq!(encode(&tags), "filename=my%20photo.jpg&variant=medium%402x");
+        assert_eq!(encode(&HashMap::new()), "");
+    }
+}
@@ -1,9 +1,16 @@
 //! Shared types for s3 service
 
+use image;
+use image::{FilterType, ImageFormat};
+use serde::de;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
 
 /// Image sizes that will go to s3 for traffic optimization
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ImageSize {
     Thumb = 40,
     Small = 80,
@@ -13,6 +20,13 @@ pub enum ImageSize {
     Original = 0,
 }
 
+impl ImageSize {
+    /// Sizes of resized variants, i.e. all sizes except for `Original`
+    pub fn presets() -> Vec<ImageSize> {
+        vec![ImageSize::Thumb, ImageSize::Small, ImageSize::Medium, ImageSize::Large]
+    }
+}
+
 impl Display for ImageSize {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         match self {
@@ -24,3 +38,970 @@ impl Display for ImageSize {
         }
     }
 }
+
+/// Variant of a size preset for high density screens, e.g. `medium@2x` is twice as large as `medium`
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct DenseVariant {
+    pub size: ImageSize,
+    /// Pixel density multiplier, greater than 1
+    pub density: u32,
+}
+
+impl Display for DenseVariant {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{}@{}x", self.size, self.density)
+    }
+}
+
+/// How an image should fit a `width` x `height` box when resized. If only one dimension
+/// of the box is set, every mode keeps aspect ratio and scales the image to that dimension.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Keep aspect ratio, crop the center of the image to cover the box exactly
+    Cover,
+    /// Keep aspect ratio, crop the image around its focal point to cover the box exactly
+    Smart,
+    /// Keep aspect ratio, letterbox the image with background colour to match the box exactly
+    Contain,
+    /// Ignore aspect ratio, stretch the image to match the box exactly
+    Fill,
+    /// Keep aspect ratio, image is as large as possible while both dimensions are less than or equal to the box
+    Inside,
+    /// Keep aspect ratio, image is as small as possible while both dimensions are greater than or equal to the box
+    Outside,
+}
+
+impl Display for Fit {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            &Fit::Cover => f.write_str("cover"),
+            &Fit::Smart => f.write_str("smart"),
+            &Fit::Contain => f.write_str("contain"),
+            &Fit::Fill => f.write_str("fill"),
+            &Fit::Inside => f.write_str("inside"),
+            &Fit::Outside => f.write_str("outside"),
+        }
+    }
+}
+
+impl FromStr for Fit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cover" => Ok(Fit::Cover),
+            "smart" => Ok(Fit::Smart),
+            "contain" => Ok(Fit::Contain),
+            "fill" => Ok(Fit::Fill),
+            "inside" => Ok(Fit::Inside),
+            "outside" => Ok(Fit::Outside),
+            _ => Err(format!(
+                "Unknown fit `{}`, expected one of: cover, smart, contain, fill, inside, outside",
+                s
+            )),
+        }
+    }
+}
+
+impl Default for Fit {
+    fn default() -> Self {
+        Fit::Outside
+    }
+}
+
+/// RGBA colour, parsed from `#rrggbb` or `#rrggbbaa`
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Color(pub [u8; 4]);
+
+impl Default for Color {
+    /// Opaque white
+    fn default() -> Self {
+        Color([255, 255, 255, 255])
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_digit(16)) {
+            return Err(format!("Invalid colour `{}`, expected `#rrggbb` or `#rrggbbaa`", s));
+        }
+        let mut color = [255u8; 4];
+        for (i, channel) in color.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|e| e.to_string())?;
+        }
+        Ok(Color(color))
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Length in source image pixels or in percents of the source image dimension.
+/// Parsed from `120` or `12.5%`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Length {
+    Pixels(u32),
+    Percent(f32),
+}
+
+impl Length {
+    /// Resolves length into pixels given the source image dimension
+    pub fn to_pixels(&self, dimension: u32) -> u32 {
+        match self {
+            &Length::Pixels(pixels) => pixels,
+            &Length::Percent(percent) => ((dimension as f32) * percent / 100.0).round() as u32,
+        }
+    }
+}
+
+impl FromStr for Length {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.ends_with('%') {
+            match s.trim_end_matches('%').parse::<f32>() {
+                Ok(percent) if percent >= 0.0 && percent <= 100.0 => Ok(Length::Percent(percent)),
+                _ => Err(format!("Invalid percentage `{}`", s)),
+            }
+        } else {
+            s.parse::<u32>().map(Length::Pixels).map_err(|_| format!("Invalid length `{}`", s))
+        }
+    }
+}
+
+/// Point of interest that crops are centered on, parsed from `x,y`, e.g. `120,80` or `30%,60%`
+#[derive(PartialEq, Clone, Debug)]
+pub struct FocalPoint {
+    pub x: Length,
+    pub y: Length,
+}
+
+impl FocalPoint {
+    /// Center of an image
+    pub fn center() -> Self {
+        FocalPoint {
+            x: Length::Percent(50.0),
+            y: Length::Percent(50.0),
+        }
+    }
+
+    /// Resolves focal point into fractions of image dimensions, clamped to `[0, 1]`
+    pub fn to_fractions(&self, w: u32, h: u32) -> (f32, f32) {
+        let fraction = |length: &Length, dimension: u32| {
+            let value = match length {
+                &Length::Pixels(pixels) => (pixels as f32) / (dimension.max(1) as f32),
+                &Length::Percent(percent) => percent / 100.0,
+            };
+            value.max(0.0).min(1.0)
+        };
+        (fraction(&self.x, w), fraction(&self.y, h))
+    }
+
+    /// Same focal point in percents of image dimensions
+    pub fn to_percents(&self, w: u32, h: u32) -> Self {
+        let (x, y) = self.to_fractions(w, h);
+        FocalPoint {
+            x: Length::Percent(x * 100.0),
+            y: Length::Percent(y * 100.0),
+        }
+    }
+}
+
+impl Display for FocalPoint {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let format = |length: &Length| match length {
+            &Length::Pixels(pixels) => format!("{}", pixels),
+            &Length::Percent(percent) => format!("{:.2}%", percent),
+        };
+        write!(f, "{},{}", format(&self.x), format(&self.y))
+    }
+}
+
+impl FromStr for FocalPoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s.split(',').map(|v| v.parse::<Length>()).collect::<Result<Vec<_>, _>>()?;
+        match values.as_slice() {
+            &[x, y] => Ok(FocalPoint { x, y }),
+            _ => Err(format!("Invalid focal point `{}`, expected `x,y`", s)),
+        }
+    }
+}
+
+/// Crop rectangle in source image coordinates, parsed from `x,y,w,h`
+#[derive(PartialEq, Clone, Debug)]
+pub struct Crop {
+    pub x: Length,
+    pub y: Length,
+    pub width: Length,
+    pub height: Length,
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s.split(',').map(|v| v.parse::<Length>()).collect::<Result<Vec<_>, _>>()?;
+        match values.as_slice() {
+            &[x, y, width, height] => Ok(Crop { x, y, width, height }),
+            _ => Err(format!("Invalid crop `{}`, expected `x,y,w,h`", s)),
+        }
+    }
+}
+
+/// Clockwise rotation of an image
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Rotation {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "90" => Ok(Rotation::Rotate90),
+            "180" => Ok(Rotation::Rotate180),
+            "270" => Ok(Rotation::Rotate270),
+            _ => Err(format!("Invalid rotation `{}`, expected one of: 90, 180, 270", s)),
+        }
+    }
+}
+
+/// Options of processing uploaded image
+#[derive(PartialEq, Clone, Debug)]
+pub struct ProcessOptions {
+    /// How resized variants fit the `ImageSize` square boxes, `Fit::default()` for presets that are not listed
+    pub fit: HashMap<ImageSize, Fit>,
+    /// Background colour for `Fit::Contain`
+    pub background: Color,
+    /// Crop rectangle applied to the image before resizing
+    pub crop: Option<Crop>,
+    /// Rotation applied to the image after cropping
+    pub rotation: Option<Rotation>,
+    /// Focal point of the edited image for `Fit::Smart`, detected if not set
+    pub focal_point: Option<FocalPoint>,
+    /// Keep unedited image if it was cropped, rotated or watermarked
+    pub keep_source: bool,
+    /// Resize every frame of animated GIFs instead of using the first frame only
+    pub animate: bool,
+    /// Embed sRGB ICC profile in variants of images that had a colour profile
+    pub embed_profile: bool,
+    /// Pixel densities of extra variants of size presets, e.g. `[2, 3]` for `@2x` and `@3x`
+    pub densities: Vec<u32>,
+    /// Lossless optimization of PNG variants, default encoding if not set
+    pub optimize_png: Option<PngOptimization>,
+    /// Resampling of size presets, `Resampling::default()` for presets that are not listed
+    pub resampling: HashMap<ImageSize, Resampling>,
+    /// ACL, storage class and headers of uploaded variants
+    pub upload: UploadOptions,
+    /// Overrides of `upload` for size presets, high density variants use overrides of their preset
+    pub preset_uploads: HashMap<ImageSize, UploadOptions>,
+    /// Upload all objects with `private` ACL, regardless of `upload` and `preset_uploads`
+    pub private: bool,
+    /// Id of the user who uploads the image
+    pub owner: Option<i32>,
+    /// Name of the uploaded file, stored in `filename` tag of uploaded objects
+    pub filename: Option<String>,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            fit: HashMap::new(),
+            background: Color::default(),
+            crop: None,
+            rotation: None,
+            focal_point: None,
+            keep_source: false,
+            animate: false,
+            embed_profile: false,
+            densities: Vec::new(),
+            optimize_png: None,
+            resampling: HashMap::new(),
+            upload: UploadOptions::default(),
+            preset_uploads: HashMap::new(),
+            private: false,
+            owner: None,
+            filename: None,
+        }
+    }
+}
+
+/// Resampling filter used for resizing, see `image::FilterType`
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    #[serde(rename = "catmull-rom")]
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn filter_type(&self) -> FilterType {
+        match self {
+            &ResizeFilter::Nearest => FilterType::Nearest,
+            &ResizeFilter::Triangle => FilterType::Triangle,
+            &ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            &ResizeFilter::Gaussian => FilterType::Gaussian,
+            &ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl Default for ResizeFilter {
+    fn default() -> Self {
+        ResizeFilter::Triangle
+    }
+}
+
+/// Unsharp mask, see `image::imageops::unsharpen`
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+pub struct Sharpen {
+    /// Amount of blur of the mask, e.g. `0.5`
+    pub sigma: f32,
+    /// Pixels that differ from the blurred ones by less than `threshold` are not sharpened
+    #[serde(default)]
+    pub threshold: i32,
+}
+
+/// How an image is resampled when it is resized
+#[derive(PartialEq, Clone, Debug, Default, Deserialize)]
+pub struct Resampling {
+    #[serde(default)]
+    pub filter: ResizeFilter,
+    /// Unsharp mask applied after downscaling
+    #[serde(default)]
+    pub sharpen: Option<Sharpen>,
+}
+
+/// Corner, side or center of an image where watermark is placed
+#[derive(PartialEq, Eq, Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Position {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position::BottomRight
+    }
+}
+
+fn default_watermark_opacity() -> f32 {
+    0.5
+}
+
+fn default_watermark_scale() -> f32 {
+    0.2
+}
+
+fn default_watermark_margin() -> f32 {
+    0.02
+}
+
+fn default_watermark_min_size() -> u32 {
+    320
+}
+
+fn default_watermark_presets() -> Vec<ImageSize> {
+    vec![ImageSize::Large, ImageSize::Original]
+}
+
+/// Watermark overlaid on resized variants of uploaded images. Either `image_path` or `text`
+/// with `font_path` must be set.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+pub struct WatermarkOptions {
+    /// Path to watermark image, e.g. a png logo with transparent background
+    #[serde(default)]
+    pub image_path: Option<String>,
+    /// Watermark text, used if `image_path` is not set
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Path to TrueType font for `text`
+    #[serde(default)]
+    pub font_path: Option<String>,
+    /// Colour of `text`
+    #[serde(default)]
+    pub color: Color,
+    #[serde(default)]
+    pub position: Position,
+    /// Opacity from `0.0` (invisible) to `1.0`
+    #[serde(default = "default_watermark_opacity")]
+    pub opacity: f32,
+    /// Width of watermark as a fraction of image width, greater than `0.0` and at most `1.0`
+    #[serde(default = "default_watermark_scale")]
+    pub scale: f32,
+    /// Distance from image edges as a fraction of the smaller image dimension, from `0.0` to less than `1.0`
+    #[serde(default = "default_watermark_margin")]
+    pub margin: f32,
+    /// Images with width or height less than `min_size` are not watermarked
+    #[serde(default = "default_watermark_min_size")]
+    pub min_size: u32,
+    /// Presets that are watermarked
+    #[serde(default = "default_watermark_presets")]
+    pub presets: Vec<ImageSize>,
+}
+
+fn default_png_effort() -> u8 {
+    2
+}
+
+fn default_png_time_budget_ms() -> u64 {
+    500
+}
+
+/// Lossless optimization of PNG images. Bit depth and colour type are reduced as far as
+/// pixels allow, then filter strategies and compression levels are tried.
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+pub struct PngOptimization {
+    /// Number of strategies to try: `1` tries one, `2` tries three, `3` tries all of them
+    #[serde(default = "default_png_effort")]
+    pub effort: u8,
+    /// No more strategies are tried for an image after this time, the best result so far is used
+    #[serde(default = "default_png_time_budget_ms")]
+    pub time_budget_ms: u64,
+}
+
+/// ACL, storage class and headers of objects uploaded to s3. Fields that are not set are left to s3 defaults,
+/// except for `acl`, which is `public-read` unless set.
+#[derive(PartialEq, Clone, Debug, Default, Deserialize)]
+pub struct UploadOptions {
+    /// Canned ACL, e.g. `private` or `public-read`
+    #[serde(default)]
+    pub acl: Option<String>,
+    /// Storage class, e.g. `STANDARD_IA` or `REDUCED_REDUNDANCY`
+    #[serde(default)]
+    pub storage_class: Option<String>,
+    /// `Cache-Control` header, e.g. `public, max-age=31536000, immutable`
+    #[serde(default)]
+    pub cache_control: Option<String>,
+    /// `Expires` header in HTTP date format, e.g. `Thu, 01 Dec 2030 16:00:00 GMT`
+    #[serde(default)]
+    pub expires: Option<String>,
+    /// `Content-Disposition` header, e.g. `inline`
+    #[serde(default)]
+    pub content_disposition: Option<String>,
+}
+
+impl UploadOptions {
+    /// Checks if objects uploaded with these options can be read by anyone, ACL is `public-read` if not set
+    pub fn is_public(&self) -> bool {
+        match self.acl.as_ref().map(String::as_str) {
+            None | Some("public-read") | Some("public-read-write") => true,
+            _ => false,
+        }
+    }
+
+    /// Options with fields of `overrides` that are set replacing the ones of `self`
+    pub fn merge(&self, overrides: &UploadOptions) -> UploadOptions {
+        UploadOptions {
+            acl: overrides.acl.clone().or_else(|| self.acl.clone()),
+            storage_class: overrides.storage_class.clone().or_else(|| self.storage_class.clone()),
+            cache_control: overrides.cache_control.clone().or_else(|| self.cache_control.clone()),
+            expires: overrides.expires.clone().or_else(|| self.expires.clone()),
+            content_disposition: overrides.content_disposition.clone().or_else(|| self.content_disposition.clone()),
+        }
+    }
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    100
+}
+
+fn default_retry_max_delay_ms() -> u64 {
+    2000
+}
+
+/// Retries of s3 requests that failed with transient errors, e.g. network ones
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+pub struct RetryOptions {
+    /// Max number of attempts of a request, including the first one
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+    /// Delay before the second attempt, doubled for every next one
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// Max delay between attempts
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            attempts: default_retry_attempts(),
+            base_delay_ms: default_retry_base_delay_ms(),
+            max_delay_ms: default_retry_max_delay_ms(),
+        }
+    }
+}
+
+fn default_multipart_threshold_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_multipart_part_size_bytes() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_multipart_concurrency() -> usize {
+    4
+}
+
+/// Multipart upload of large objects
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+pub struct MultipartOptions {
+    /// Objects larger than this are uploaded in parts
+    #[serde(default = "default_multipart_threshold_bytes")]
+    pub threshold_bytes: usize,
+    /// Size of parts, s3 requires at least 5 MB and at most 10000 parts, so it's increased if needed
+    #[serde(default = "default_multipart_part_size_bytes")]
+    pub part_size_bytes: usize,
+    /// Number of parts of an object that are uploaded at once
+    #[serde(default = "default_multipart_concurrency")]
+    pub concurrency: usize,
+}
+
+impl Default for MultipartOptions {
+    fn default() -> Self {
+        Self {
+            threshold_bytes: default_multipart_threshold_bytes(),
+            part_size_bytes: default_multipart_part_size_bytes(),
+            concurrency: default_multipart_concurrency(),
+        }
+    }
+}
+
+/// Template of object keys that reproduces `img-2IpSsAjuxB8C-small.png` naming
+pub static DEFAULT_KEY_TEMPLATE: &str = "{prefix}-{id}{variant}";
+/// Min length of random ids, shorter ones are likely to collide
+static MIN_RANDOM_ID_BYTES: usize = 8;
+
+fn default_key_template() -> String {
+    DEFAULT_KEY_TEMPLATE.to_string()
+}
+
+/// Naming scheme of uploaded objects
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+pub struct KeyOptions {
+    /// Template of object keys without extension, e.g. `{prefix}/{yyyy}/{mm}/{dd}/{user_id}/{id}{variant}`.
+    /// Placeholders are `{prefix}` (`img` or `avatar`), `{user_id}`, `{yyyy}`, `{mm}` and `{dd}` of upload date in UTC,
+    /// `{id}` and `{variant}`, which is `-<variant>` like `-small`, `-medium@2x` or `-source`, and empty for originals.
+    /// Template must contain `{id}` and end with `{variant}`.
+    #[serde(default = "default_key_template")]
+    pub template: String,
+    /// Unique id of uploaded image
+    #[serde(default)]
+    pub id: KeyId,
+}
+
+impl Default for KeyOptions {
+    fn default() -> Self {
+        Self {
+            template: default_key_template(),
+            id: KeyId::default(),
+        }
+    }
+}
+
+impl KeyOptions {
+    /// Checks that keys are unique and that variants are suffixes of keys of originals,
+    /// which is how variants are found by the key of original
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.template.contains("{id}") {
+            return Err(format!("Key template {} has no {{id}} placeholder", self.template));
+        }
+        if !self.template.ends_with("{variant}") || self.template.matches("{variant}").count() > 1 {
+            return Err(format!(
+                "Key template {} must end with the only {{variant}} placeholder",
+                self.template
+            ));
+        }
+        match self.id {
+            KeyId::Random { length_bytes } if length_bytes < MIN_RANDOM_ID_BYTES => Err(format!(
+                "Random key ids must be at least {} bytes long, found {}",
+                MIN_RANDOM_ID_BYTES, length_bytes
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Unique id of uploaded image in object keys
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum KeyId {
+    /// Random bytes encoded in base64, with `+`, `/` and `=` replaced to keep keys url-friendly
+    Random { length_bytes: usize },
+    /// ULID, 26 characters sorted by time of upload
+    Ulid,
+    /// Random UUID v4
+    Uuid,
+}
+
+impl Default for KeyId {
+    fn default() -> Self {
+        KeyId::Random { length_bytes: 8 }
+    }
+}
+
+/// Server-side encryption of objects stored in s3
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EncryptionOptions {
+    /// SSE-S3, keys are managed by s3
+    S3,
+    /// SSE-KMS with `key_id` of a KMS key, or the default `aws/s3` key if it's not set
+    Kms {
+        #[serde(default)]
+        key_id: Option<String>,
+    },
+    /// SSE-C with a 256-bit key read from `key_path`, either raw or base64 encoded
+    Customer { key_path: String },
+}
+
+/// Formats of uploaded images. Formats other than png, jpeg and gif are decoded
+/// only if the matching cargo feature is enabled, see `InputFormat::is_compiled`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+    Tiff,
+    Ico,
+    Webp,
+    Svg,
+}
+
+impl InputFormat {
+    /// All input formats
+    pub fn all() -> Vec<Self> {
+        vec![
+            InputFormat::Png,
+            InputFormat::Jpeg,
+            InputFormat::Gif,
+            InputFormat::Bmp,
+            InputFormat::Tiff,
+            InputFormat::Ico,
+            InputFormat::Webp,
+            InputFormat::Svg,
+        ]
+    }
+
+    /// Guesses format of an image from its bytes
+    pub fn guess(bytes: &[u8]) -> Option<Self> {
+        if looks_like_svg(bytes) {
+            return Some(InputFormat::Svg);
+        }
+        image::guess_format(bytes).ok().and_then(Self::from_image_format)
+    }
+
+    /// Checks if decoder for the format is compiled in
+    pub fn is_compiled(&self) -> bool {
+        match self {
+            &InputFormat::Png | &InputFormat::Jpeg | &InputFormat::Gif => true,
+            &InputFormat::Bmp => cfg!(feature = "bmp"),
+            &InputFormat::Tiff => cfg!(feature = "tiff"),
+            &InputFormat::Ico => cfg!(feature = "ico"),
+            &InputFormat::Webp => cfg!(feature = "webp"),
+            &InputFormat::Svg => cfg!(feature = "svg"),
+        }
+    }
+
+    /// Checks if browsers can show images in this format, so they can be served as is
+    pub fn is_web_safe(&self) -> bool {
+        self.output_format().is_some()
+    }
+
+    /// Format of images that are served as is, `None` if browsers can't show images in this format
+    pub fn output_format(&self) -> Option<OutputFormat> {
+        match self {
+            &InputFormat::Png => Some(OutputFormat::Png),
+            &InputFormat::Jpeg => Some(OutputFormat::Jpeg),
+            &InputFormat::Gif => Some(OutputFormat::Gif),
+            &InputFormat::Svg => Some(OutputFormat::Svg),
+            _ => None,
+        }
+    }
+
+    /// Extension used in s3 filename
+    pub fn extension(&self) -> &'static str {
+        match self {
+            &InputFormat::Png => "png",
+            &InputFormat::Jpeg => "jpg",
+            &InputFormat::Gif => "gif",
+            &InputFormat::Bmp => "bmp",
+            &InputFormat::Tiff => "tiff",
+            &InputFormat::Ico => "ico",
+            &InputFormat::Webp => "webp",
+            &InputFormat::Svg => "svg",
+        }
+    }
+
+    /// Content-type used for serving file from s3
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            &InputFormat::Png => "image/png",
+            &InputFormat::Jpeg => "image/jpeg",
+            &InputFormat::Gif => "image/gif",
+            &InputFormat::Bmp => "image/bmp",
+            &InputFormat::Tiff => "image/tiff",
+            &InputFormat::Ico => "image/x-icon",
+            &InputFormat::Webp => "image/webp",
+            &InputFormat::Svg => "image/svg+xml",
+        }
+    }
+
+    /// Format for `image` crate decoder, `None` for vector formats
+    pub fn image_format(&self) -> Option<ImageFormat> {
+        match self {
+            &InputFormat::Png => Some(ImageFormat::PNG),
+            &InputFormat::Jpeg => Some(ImageFormat::JPEG),
+            &InputFormat::Gif => Some(ImageFormat::GIF),
+            &InputFormat::Bmp => Some(ImageFormat::BMP),
+            &InputFormat::Tiff => Some(ImageFormat::TIFF),
+            &InputFormat::Ico => Some(ImageFormat::ICO),
+            &InputFormat::Webp => Some(ImageFormat::WEBP),
+            &InputFormat::Svg => None,
+        }
+    }
+
+    /// Input format matching `image` crate format, if any
+    pub fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::PNG => Some(InputFormat::Png),
+            ImageFormat::JPEG => Some(InputFormat::Jpeg),
+            ImageFormat::GIF => Some(InputFormat::Gif),
+            ImageFormat::BMP => Some(InputFormat::Bmp),
+            ImageFormat::TIFF => Some(InputFormat::Tiff),
+            ImageFormat::ICO => Some(InputFormat::Ico),
+            ImageFormat::WEBP => Some(InputFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+impl Display for InputFormat {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            &InputFormat::Png => f.write_str("png"),
+            &InputFormat::Jpeg => f.write_str("jpeg"),
+            &InputFormat::Gif => f.write_str("gif"),
+            &InputFormat::Bmp => f.write_str("bmp"),
+            &InputFormat::Tiff => f.write_str("tiff"),
+            &InputFormat::Ico => f.write_str("ico"),
+            &InputFormat::Webp => f.write_str("webp"),
+            &InputFormat::Svg => f.write_str("svg"),
+        }
+    }
+}
+
+/// Checks if bytes look like an SVG document, i.e. text with `<svg` tag near the beginning
+fn looks_like_svg(bytes: &[u8]) -> bool {
+    let head = &bytes[..bytes.len().min(4096)];
+    let head = String::from_utf8_lossy(head);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with('<') && head.contains("<svg")
+}
+
+/// Formats of images stored in s3
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Gif,
+    /// Sanitized SVG, used only for originals of SVG uploads
+    Svg,
+}
+
+impl OutputFormat {
+    /// Extension used in s3 filename
+    pub fn extension(&self) -> &'static str {
+        match self {
+            &OutputFormat::Png => "png",
+            &OutputFormat::Jpeg => "jpg",
+            &OutputFormat::Gif => "gif",
+            &OutputFormat::Svg => "svg",
+        }
+    }
+
+    /// Content-type used for serving file from s3
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            &OutputFormat::Png => "image/png",
+            &OutputFormat::Jpeg => "image/jpeg",
+            &OutputFormat::Gif => "image/gif",
+            &OutputFormat::Svg => "image/svg+xml",
+        }
+    }
+
+    /// Format for `image` crate encoder, `None` for vector formats
+    pub fn image_format(&self) -> Option<ImageFormat> {
+        match self {
+            &OutputFormat::Png => Some(ImageFormat::PNG),
+            &OutputFormat::Jpeg => Some(ImageFormat::JPEG),
+            &OutputFormat::Gif => Some(ImageFormat::GIF),
+            &OutputFormat::Svg => None,
+        }
+    }
+
+    /// Output format matching `image` crate format, if any
+    pub fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::PNG => Some(OutputFormat::Png),
+            ImageFormat::JPEG => Some(OutputFormat::Jpeg),
+            ImageFormat::GIF => Some(OutputFormat::Gif),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(OutputFormat::Png),
+            "jpg" | "jpeg" => Ok(OutputFormat::Jpeg),
+            "gif" => Ok(OutputFormat::Gif),
+            _ => Err(format!("Unknown format `{}`, expected one of: png, jpeg, gif", s)),
+        }
+    }
+}
+
+/// Parameters of resizing
+#[derive(PartialEq, Clone, Debug)]
+pub struct ResizeParams {
+    /// Width of the box, `None` means unbounded
+    pub width: Option<u32>,
+    /// Height of the box, `None` means unbounded
+    pub height: Option<u32>,
+    pub fit: Fit,
+    /// Background colour for `Fit::Contain`
+    pub background: Color,
+    /// Focal point for `Fit::Smart`, center of the image if not set
+    pub focal_point: Option<FocalPoint>,
+    pub format: OutputFormat,
+    /// Embed sRGB ICC profile in resized image if the source image had a colour profile
+    pub embed_profile: bool,
+    /// Lossless optimization of PNG image, default encoding if not set
+    pub optimize_png: Option<PngOptimization>,
+    /// Resampling filter and sharpening, `triangle` without sharpening by default
+    pub resampling: Resampling,
+}
+
+/// Placeholders that frontends can render while the image is loading
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Placeholder {
+    /// BlurHash string, see https://blurha.sh
+    pub blurhash: String,
+    /// Tiny base64 encoded PNG in a form of data url
+    pub lqip: String,
+}
+
+/// Colours of the image, formatted as `#rrggbb`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Colors {
+    pub dominant_color: String,
+    /// Palette sorted by the number of pixels, starting with the dominant colour
+    pub palette: Vec<String>,
+}
+
+/// Result of image upload
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct UploadedImage {
+    /// Url of original image
+    pub url: String,
+    /// Url of unedited image if it was cropped or rotated and kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    #[serde(flatten)]
+    pub placeholder: Placeholder,
+    #[serde(flatten)]
+    pub colors: Colors,
+    /// `srcset` attribute values by size preset, e.g. `{"medium": "<url> 1x, <url@2x> 2x"}`
+    pub srcset: BTreeMap<String, String>,
+    /// Checksums of every uploaded object by its s3 filename, e.g. `{"img-2IpSsAjuxB8C-small.png": {...}}`
+    pub checksums: BTreeMap<String, Checksums>,
+}
+
+/// Checksums of an uploaded object
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Checksums {
+    /// Base64 encoded MD5 digest, sent to s3 in `Content-MD5` header
+    pub md5: String,
+    /// Hex encoded SHA-256 digest, stored in `sha256` metadata of the object
+    pub sha256: String,
+}
+
+/// Information about uploaded image, read from metadata of the original image in s3.
+/// Fields are missing for images uploaded before the metadata was stored.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ImageInfo {
+    /// Url of original image
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Format of original image, e.g. `png`
+    pub format: String,
+    /// Size of original image in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Urls of variants by name, e.g. `medium` or `medium@2x`
+    pub variants: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lqip: Option<String>,
+    /// Time of upload in RFC 3339 format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploaded_at: Option<String>,
+}
+
+/// Presigned url of an s3 object
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SignedUrl {
+    pub url: String,
+    /// Expiration time of the url in RFC 3339 format
+    pub expires_at: String,
+}
+
+/// User who requests an uploaded object, identified by JWT
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Requester {
+    pub user_id: i32,
+    /// User has the admin role, see `config::JWT`
+    pub admin: bool,
+}
@@ -0,0 +1,56 @@
+//! Encoding utils shared by s3 requests
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+/// Percent-encodes every byte except for unreserved characters of RFC 3986
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Unescaped text of the first element `name` in `xml`, `None` if there's no such element or `xml` is malformed
+pub fn xml_element(xml: &str, name: &str) -> Option<String> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref element)) if element.name() == name.as_bytes() => {
+                let mut text = Vec::new();
+                return reader.read_text(name, &mut text).ok();
+            }
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => (),
+        }
+        buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(
+            percent_encode("arn:aws:iam::1:role/my role"),
+            "arn%3Aaws%3Aiam%3A%3A1%3Arole%2Fmy%20role"
+        );
+        assert_eq!(percent_encode("фото-1.png"), "%D1%84%D0%BE%D1%82%D0%BE-1.png");
+    }
+
+    #[test]
+    fn test_xml_element() {
+        let xml = "<Error><Code>SlowDown</Code><Message>Reduce &amp; retry</Message></Error>";
+        assert_eq!(xml_element(xml, "Code"), Some("SlowDown".to_string()));
+        assert_eq!(xml_element(xml, "Message"), Some("Reduce & retry".to_string()));
+        assert_eq!(xml_element(xml, "RequestId"), None);
+        assert_eq!(xml_element("SlowDown", "Code"), None);
+    }
+}